@@ -1,21 +1,125 @@
-use crate::config::{AnalysisConfig, AnalysisType, Environment, WindowUnit};
-use crate::audio_io;
+use crate::config::{AnalysisConfig, AnalysisType, SpectralEstimator, WindowUnit};
+use crate::archive;
+use crate::audio_io::{self, AudioReader};
+use crate::columnar;
 use crate::dsp;
+use crate::netcdf;
+use crate::provenance::{self, RecordingProvenance};
+use crate::qa;
+use crate::recorder_log;
+use crate::stream::{AudioStream, Segment, Segments};
+use crate::timestamp::{self, RowTime, TimeZoneSpec};
 use crate::utils;
+use crate::wav_metadata::{self, WavMetadata};
 
-use ndarray::{concatenate, Array, Array1, Array2, ArrayView2, Axis, s};
+use ndarray::{concatenate, Array1, Array2, ArrayView2, Axis, s};
 use rayon::prelude::*;
+use std::collections::{HashSet, VecDeque};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::fs;
 use std::time::Instant;
-use chrono::NaiveDateTime;
+use chrono::{DateTime, Utc};
+
+/// Segments processed in parallel per thread in each batch read from the file.
+const SEGMENTS_PER_THREAD: usize = 4;
+
+/// Resolution of filename timestamps; smaller differences between files are not reported as gaps.
+const TIMESTAMP_RESOLUTION_SECS: f64 = 1.0;
 
 // Helper struct to hold intermediate results for a single file
 #[derive(Debug)] // Added Debug for easier inspection if needed
 struct FileAnalysisResult {
-    data: Array2<f64>, // [time/freq_header, values...]
-    start_time: Option<NaiveDateTime>,
-    // duration_secs: f64, // Can be calculated from data if needed
+    data: Array2<f64>, // [freq_header, values...]; one column per frequency (PSD) or a single column (Broadband)
+    times: Vec<RowTime>, // Time of each data row
+    start_time: Option<DateTime<Utc>>,
+    qa_flags: Option<Vec<u32>>, // QA flags of each data row, if quality control is enabled
+    row_spans: Vec<(f64, f64)>, // Audio covered by each data row, in seconds from the file start
+    duration_secs: f64, // Length of the analysed audio
+    row_step_secs: f64, // Nominal time between output rows
+    provenance: RecordingProvenance, // Input file and derived analysis parameters
+    levels: LevelScale, // Conversion of averaged power to output levels
+    head_partial: Option<PartialInterval>, // Clock-aligned interval cut short by the start of the file
+    tail_partial: Option<PartialInterval>, // Clock-aligned interval cut short by the end of the file
+}
+
+/// Converts averaged linear power to output levels in dB.
+#[derive(Debug, Clone, Copy, Default)]
+struct LevelScale {
+    divisor: f64, // Bin width times noise bandwidth for PSD, 1 for broadband
+    pref: f64,
+}
+
+impl LevelScale {
+    fn to_db(self, power: &[f64]) -> Vec<f64> {
+        power.iter().map(|&p| utils::power_to_db(p / self.divisor, self.pref)).collect()
+    }
+}
+
+/// A clock-aligned averaging interval only partly covered by a file. Its segment values are
+/// kept so that an interval split between contiguous files can be averaged as a whole.
+#[derive(Debug, Clone)]
+struct PartialInterval {
+    key: i64,                // Index of the interval, as in `Grouping::Clock`
+    time: RowTime,           // Start of the interval
+    segments: Vec<Vec<f64>>, // Calibrated linear segment values
+    coverage: f64,           // Fraction of the interval's expected segments present
+    span: (f64, f64),        // Audio covered, in seconds from the file start
+    qa_flags: u32,
+    row: Option<usize>,      // Data row of the interval, unless dropped for low coverage
+}
+
+impl PartialInterval {
+    fn merged_with(&self, next: &PartialInterval) -> PartialInterval {
+        PartialInterval {
+            segments: [self.segments.clone(), next.segments.clone()].concat(),
+            coverage: self.coverage + next.coverage,
+            qa_flags: self.qa_flags | next.qa_flags,
+            span: next.span,
+            row: None,
+            ..self.clone()
+        }
+    }
+}
+
+impl FileAnalysisResult {
+    /// Removes data row `row` (counted after the frequency header).
+    fn remove_row(&mut self, row: usize) {
+        let keep: Vec<usize> = (0..self.data.nrows()).filter(|&i| i != row + 1).collect();
+        self.data = self.data.select(Axis(0), &keep);
+        self.times.remove(row);
+        self.row_spans.remove(row);
+        if let Some(flags) = &mut self.qa_flags {
+            flags.remove(row);
+        }
+    }
+
+    /// Inserts the levels of `partial` as data row `row`.
+    fn insert_row(&mut self, row: usize, levels: &[f64], partial: &PartialInterval) {
+        let new_row = Array2::from_shape_vec((1, levels.len()), levels.to_vec()).expect("one level per column");
+        self.data = concatenate(Axis(0), &[self.data.slice(s![..row + 1, ..]), new_row.view(), self.data.slice(s![row + 1.., ..])])
+            .expect("matching column counts");
+        self.times.insert(row, partial.time);
+        self.row_spans.insert(row, partial.span);
+        if let Some(flags) = &mut self.qa_flags {
+            flags.insert(row, partial.qa_flags);
+        }
+    }
+}
+
+/// A discontinuity between consecutive files in a batch.
+/// `next_start < previous_end` means the files overlap.
+#[derive(Debug, Clone, PartialEq)]
+struct TimeGap {
+    previous_end: DateTime<Utc>,
+    next_start: DateTime<Utc>,
+    spans_rows: bool, // Longer than half an output row, so NaN rows fill it (and intervals are not joined across it)
+}
+
+impl TimeGap {
+    fn duration_secs(&self) -> f64 {
+        timestamp::secs_between(self.previous_end, self.next_start)
+    }
 }
 
 /// Processes a single audio file based on the configuration.
@@ -26,23 +130,38 @@ pub fn process_single_file( // Already pub, no change needed here
     println!("Processing file: {}", file_path.display());
     let start_time = Instant::now();
 
-    let (audio_data, fs_hz) = audio_io::read_wav_file(file_path)?;
-    let fs = fs_hz as f64;
-    println!("  Read {} samples at {} Hz", audio_data.len(), fs);
+    let calibration = utils::Calibration::from_config(config)?;
+    println!("  System Sensitivity (S): {}", calibration.describe());
 
-    let sensitivity_db = utils::calculate_system_sensitivity_db(config)?;
-    println!("  System Sensitivity (S): {:.2} dB", sensitivity_db);
+    let (result, audio_info, qa_report) = analyse_file(file_path, config, &calibration)?;
+    println!("  Read {} samples at {} Hz", audio_info.num_samples, audio_info.sample_rate);
+    println!("  Format: {} {} {}-bit, normalised as {}", audio_info.container, audio_info.encoding, audio_info.bits_per_sample, audio_info.normalisation);
 
-    let result = run_core_analysis(&audio_data, fs, config, sensitivity_db, None)?;
+    let stem = file_output_stem(file_path);
+    if let Some(report) = &qa_report {
+        let qa_path = PathBuf::from(&config.output_dir).join(qa_report_filename(&stem));
+        fs::create_dir_all(&config.output_dir)?;
+        qa::write_file_report(&qa_path, report)?;
+        println!("  QA report written to: {}", qa_path.display());
+    }
 
     if config.write_csv {
-        let output_filename = generate_output_filename(file_path, config);
+        let input_path = PathBuf::from(&config.output_dir).join(input_report_filename(&stem));
+        fs::create_dir_all(&config.output_dir)?;
+        audio_io::write_input_report(&input_path, &[(file_path.display().to_string(), audio_info)])?;
+        println!("  Input format written to: {}", input_path.display());
+    }
+
+    if writes_tables(config) {
+        let output_filename = generate_output_filename(&stem, config, result.provenance.analysis.n_fft);
         let output_path = PathBuf::from(&config.output_dir).join(output_filename);
         if let Some(parent) = output_path.parent() {
             fs::create_dir_all(parent)?;
         }
-        write_csv(&output_path, &result.data)?;
-        println!("  Output written to: {}", output_path.display());
+        let output_zone = TimeZoneSpec::from_setting(&config.output_timezone)?;
+        for path in write_tables(&output_path, &result.data, &result.times, result.qa_flags.as_deref(), &output_zone, config, &[&result.provenance])? {
+            println!("  Output written to: {}", path.display());
+        }
     }
 
     let duration = start_time.elapsed();
@@ -50,59 +169,159 @@ pub fn process_single_file( // Already pub, no change needed here
     Ok(())
 }
 
-/// Processes all audio files in a directory based on the configuration.
+/// Results gathered over the recordings of a batch.
+#[derive(Default)]
+struct BatchResults {
+    file_results: Vec<FileAnalysisResult>,
+    qa_reports: Vec<(String, qa::QaReport)>,
+    input_infos: Vec<(String, audio_io::AudioInfo)>,
+    output_stems: HashSet<String>, // Of the recordings whose individual outputs were written
+    processed_files_count: usize,
+}
+
+type RecordingAnalysis = (FileAnalysisResult, audio_io::AudioInfo, Option<qa::QaReport>);
+
+impl BatchResults {
+    /// Analyses one recording of the batch with `analyse`, writes its QA report and individual
+    /// CSV (if enabled) under the output name `stem`, and keeps its results for the batch
+    /// outputs. Errors are reported and the recording skipped. A recording whose output name
+    /// was already used gets no individual outputs, rather than overwriting the other's.
+    fn process(
+        &mut self,
+        path: &Path,
+        stem: String,
+        config: &AnalysisConfig,
+        output_zone: &TimeZoneSpec,
+        analyse: impl FnOnce() -> Result<RecordingAnalysis, Box<dyn std::error::Error>>,
+    ) {
+        self.processed_files_count += 1;
+        println!("Processing file {}: {}", self.processed_files_count, path.display());
+        let file_start_time = Instant::now();
+
+        match analyse() {
+            Ok((result, audio_info, qa_report)) => {
+                self.input_infos.push((path.display().to_string(), audio_info));
+                let writes_individual = qa_report.is_some() || (config.write_individual_batch_csvs && writes_tables(config));
+                let unique = !writes_individual || self.output_stems.insert(stem.clone());
+                if !unique {
+                    eprintln!("  Warning: outputs named {} were already written for another recording; skipping the individual outputs of {}", stem, path.display());
+                }
+                if let Some(report) = qa_report {
+                    if unique {
+                        let qa_path = PathBuf::from(&config.output_dir).join(qa_report_filename(&stem));
+                        match qa::write_file_report(&qa_path, &report) {
+                            Ok(_) => println!("  QA report written to: {}", qa_path.display()),
+                            Err(e) => eprintln!("  Error writing QA report {}: {}", qa_path.display(), e),
+                        }
+                    }
+                    self.qa_reports.push((path.display().to_string(), report));
+                }
+                // Optionally write individual CSV
+                if unique && config.write_individual_batch_csvs && writes_tables(config) {
+                    let output_filename = generate_output_filename(&stem, config, result.provenance.analysis.n_fft);
+                    let output_path = PathBuf::from(&config.output_dir).join(output_filename);
+                    match write_tables(&output_path, &result.data, &result.times, result.qa_flags.as_deref(), output_zone, config, &[&result.provenance]) {
+                        Ok(paths) => paths.iter().for_each(|p| println!("  Individual output written to: {}", p.display())),
+                        Err(e) => eprintln!("  Error writing individual output {}: {}", output_path.display(), e),
+                    }
+                }
+                self.file_results.push(result);
+            }
+            Err(e) => {
+                eprintln!("  Error processing {}: {}. Skipping.", path.display(), e);
+            }
+        }
+
+        let file_duration = file_start_time.elapsed();
+        println!("  Finished processing {} in {:.2} seconds.", path.display(), file_duration.as_secs_f64());
+    }
+}
+
+/// Processes all audio files in a directory based on the configuration. Zip, tar and tar.gz
+/// archives in the directory, or given instead of a directory, are read entry by entry.
 pub fn process_directory( // Already pub, no change needed here
     dir_path: &Path,
     config: &AnalysisConfig,
 ) -> Result<(), Box<dyn std::error::Error>> {
     println!("Processing directory (batch mode): {}", dir_path.display());
     let overall_start_time = Instant::now();
-    let mut file_results: Vec<FileAnalysisResult> = Vec::new();
-    let mut processed_files_count = 0;
+    let output_zone = TimeZoneSpec::from_setting(&config.output_timezone)?;
+    let mut batch = BatchResults::default();
 
     fs::create_dir_all(&config.output_dir)?;
+    let calibration = utils::Calibration::from_config(config)?;
+    let raw_format = audio_io::RawFormat::from_config(config)?;
+    let is_input = |path: &Path| {
+        audio_io::is_audio_file(path)
+            || (raw_format.is_some() && audio_io::is_raw_file(path))
+            || (config.read_sud && audio_io::is_sud_file(path))
+    };
 
-    for entry in fs::read_dir(dir_path)? {
-        let entry = entry?;
-        let path = entry.path();
-
-        if path.is_file() && path.extension().map_or(false, |ext| ext == "wav") {
-            processed_files_count += 1;
-            println!("Processing file {}: {}", processed_files_count, path.display());
-            let file_start_time = Instant::now();
-
-            let file_result = process_single_wav_for_batch(&path, config);
-
-            match file_result {
-                Ok(result) => {
-                    // Optionally write individual CSV
-                    if config.write_individual_batch_csvs && config.write_csv {
-                        let output_filename = generate_output_filename(&path, config);
-                        let output_path = PathBuf::from(&config.output_dir).join(output_filename);
-                        match write_csv(&output_path, &result.data) {
-                            Ok(_) => println!("  Individual output written to: {}", output_path.display()),
-                            Err(e) => eprintln!("  Error writing individual CSV {}: {}", output_path.display(), e),
-                        }
+    let paths: Vec<PathBuf> = if dir_path.is_dir() {
+        fs::read_dir(dir_path)?.map(|entry| entry.map(|e| e.path())).collect::<Result<_, _>>()?
+    } else {
+        vec![dir_path.to_path_buf()]
+    };
+    for path in paths.into_iter().filter(|path| path.is_file()) {
+        if archive::is_archive(&path) {
+            println!("Reading archive: {}", path.display());
+            let read = archive::for_each_entry(&path, is_input, |entry_path, entry, size| {
+                batch.process(entry_path, archive::entry_output_stem(&path, entry_path), config, &output_zone, || {
+                    // Entries are hashed as they are streamed, as they cannot be re-read
+                    let mut hashed = provenance::HashingReader::new(entry);
+                    let reader = AudioReader::from_reader(entry_path, Box::new(&mut hashed), size, raw_format.as_ref())?;
+                    let mut analysis = analyse_recording(entry_path, reader, config, &calibration)?;
+                    if config.write_provenance {
+                        analysis.0.provenance.input = Some(hashed.finish(entry_path)?);
                     }
-                    file_results.push(result);
-                }
-                Err(e) => {
-                    eprintln!("  Error processing {}: {}. Skipping.", path.display(), e);
-                }
+                    Ok(analysis)
+                });
+            });
+            if let Err(e) = read {
+                eprintln!("  Error reading archive {}: {}", path.display(), e);
             }
+        } else if is_input(&path) {
+            batch.process(&path, file_output_stem(&path), config, &output_zone, || analyse_file(&path, config, &calibration));
+        }
+    }
+    let BatchResults { mut file_results, qa_reports, input_infos, processed_files_count, .. } = batch;
 
-            let file_duration = file_start_time.elapsed();
-            println!("  Finished processing {} in {:.2} seconds.", path.display(), file_duration.as_secs_f64());
+    if !qa_reports.is_empty() {
+        let qa_summary_path = PathBuf::from(&config.output_dir).join("PAMGuide_Batch_QA_Summary.csv");
+        match qa::write_batch_report(&qa_summary_path, &qa_reports) {
+            Ok(_) => println!("Batch QA summary written to: {}", qa_summary_path.display()),
+            Err(e) => eprintln!("Error writing batch QA summary {}: {}", qa_summary_path.display(), e),
+        }
+    }
+
+    if !input_infos.is_empty() && config.write_csv {
+        let inputs_path = PathBuf::from(&config.output_dir).join("PAMGuide_Batch_Inputs.csv");
+        match audio_io::write_input_report(&inputs_path, &input_infos) {
+            Ok(_) => println!("Batch input formats written to: {}", inputs_path.display()),
+            Err(e) => eprintln!("Error writing batch input formats {}: {}", inputs_path.display(), e),
         }
     }
 
     // Concatenate results if needed
-    if config.create_batch_summary_file && !file_results.is_empty() && config.write_csv {
+    if config.create_batch_summary_file && !file_results.is_empty() && writes_tables(config) {
         println!("Concatenating results...");
         // Sort results by start time if timestamps were available and parsed
+        let mut gaps: Vec<Option<TimeGap>> = vec![None; file_results.len()];
         if file_results.iter().all(|r| r.start_time.is_some()) {
             file_results.sort_by_key(|r| r.start_time.unwrap());
             println!("  Sorted files by timestamp.");
+
+            // gaps[i] is the discontinuity between file i-1 and file i
+            for i in 1..file_results.len() {
+                gaps[i] = find_time_gap(&file_results[i - 1], &file_results[i]);
+            }
+            report_time_gaps(&gaps, config, &output_zone);
+            if config.averaging_interval.is_some() {
+                let joined = join_split_intervals(&mut file_results, &gaps, config);
+                if joined > 0 {
+                    println!("  Joined {} averaging intervals split between files.", joined);
+                }
+            }
         } else {
             println!("  Warning: Not all files had parseable timestamps. Concatenating in directory order.");
             // TODO: Optionally implement offset time calculation if timestamps are missing
@@ -113,13 +332,32 @@ pub fn process_directory( // Already pub, no change needed here
         let header_row_view = first_result.data.slice(s![0..1, ..]); // Shape [1, N]
         let mut all_data_rows_views: Vec<ArrayView2<f64>> = Vec::with_capacity(file_results.len());
 
-        for result in &file_results {
+        // NaN rows on the output time grid, to be inserted before each file that follows a gap
+        let gap_times: Vec<Vec<RowTime>> = file_results
+            .iter()
+            .enumerate()
+            .map(|(i, result)| match (&gaps[i], config.fill_gaps) {
+                (Some(gap), true) if gap.spans_rows && gap.duration_secs() > 0.0 => gap_fill_times(&file_results[i - 1], result),
+                _ => Vec::new(),
+            })
+            .collect();
+        let gap_rows: Vec<Array2<f64>> = gap_times
+            .iter()
+            .map(|times| Array2::<f64>::from_elem((times.len(), first_result.data.ncols()), f64::NAN))
+            .collect();
+
+        for (result, filler) in file_results.iter().zip(&gap_rows) {
             if config.analysis_type == AnalysisType::Psd && result.data.ncols() != first_result.data.ncols() {
                 eprintln!("  Error: Mismatched frequency bins between files ({} vs {} cols). Cannot concatenate PSD results.", result.data.ncols(), first_result.data.ncols());
                 return Ok(());
             }
+            all_data_rows_views.push(filler.view());
             all_data_rows_views.push(result.data.slice(s![1.., ..])); // Shape [M_i, N]
         }
+        let filled_rows: usize = gap_rows.iter().map(|rows| rows.nrows()).sum();
+        if filled_rows > 0 {
+            println!("  Inserted {} NaN rows to fill gaps.", filled_rows);
+        }
 
         // Concatenate all data rows vertically
         let combined_data: Array2<f64> = concatenate(Axis(0), &all_data_rows_views)?;
@@ -130,6 +368,17 @@ pub fn process_directory( // Already pub, no change needed here
         let mut final_array = Array2::<f64>::zeros((num_rows, num_cols));
         final_array.slice_mut(s![0..1, ..]).assign(&header_row_view);
         final_array.slice_mut(s![1.., ..]).assign(&combined_data);
+        let combined_times: Vec<RowTime> = file_results
+            .iter()
+            .zip(&gap_times)
+            .flat_map(|(r, filler)| filler.iter().chain(&r.times).copied())
+            .collect();
+        let combined_qa_flags: Option<Vec<u32>> = file_results
+            .iter()
+            .zip(&gap_rows)
+            .map(|(r, filler)| r.qa_flags.as_ref().map(|flags| [vec![0; filler.nrows()], flags.clone()].concat()))
+            .collect::<Option<Vec<_>>>()
+            .map(|flags| flags.concat());
 
         // Write summary file
         let summary_filename = format!(
@@ -140,9 +389,10 @@ pub fn process_directory( // Already pub, no change needed here
             if config.calibrated { "Calibrated" } else { "Relative" }
         );
         let summary_path = PathBuf::from(&config.output_dir).join(summary_filename);
-        match write_csv(&summary_path, &final_array) {
-            Ok(_) => println!("  Batch summary written to: {}", summary_path.display()),
-            Err(e) => eprintln!("  Error writing batch summary CSV {}: {}", summary_path.display(), e),
+        let recordings: Vec<&RecordingProvenance> = file_results.iter().map(|r| &r.provenance).collect();
+        match write_tables(&summary_path, &final_array, &combined_times, combined_qa_flags.as_deref(), &output_zone, config, &recordings) {
+            Ok(paths) => paths.iter().for_each(|p| println!("  Batch summary written to: {}", p.display())),
+            Err(e) => eprintln!("  Error writing batch summary output {}: {}", summary_path.display(), e),
         }
 
     } else if file_results.is_empty() {
@@ -154,65 +404,211 @@ pub fn process_directory( // Already pub, no change needed here
     Ok(())
 }
 
-/// Helper function to process a single WAV file for batch mode, returning the result struct.
-fn process_single_wav_for_batch(
+/// Streams a file through the analysis, returning the result, the input format and the QA
+/// report (if quality control is enabled).
+fn analyse_file(
     path: &Path,
     config: &AnalysisConfig,
-) -> Result<FileAnalysisResult, Box<dyn std::error::Error>> {
-    let (audio_data, fs_hz) = audio_io::read_wav_file(path)?;
-    let fs = fs_hz as f64;
-    let sensitivity_db = utils::calculate_system_sensitivity_db(config)?;
-
-    let file_start_datetime = if let Some(format) = &config.timestamp_format {
-        parse_timestamp_from_filename(path, format)
-    } else {
-        None
-    };
-    if config.timestamp_format.is_some() && file_start_datetime.is_none() {
-        eprintln!("  Warning: Could not parse timestamp from filename: {}. Time column will be relative for this file in summary.", path.display());
+    calibration: &utils::Calibration,
+) -> Result<RecordingAnalysis, Box<dyn std::error::Error>> {
+    if audio_io::is_sud_file(path) && !config.read_sud {
+        return Err("SUD decoding is experimental; set read_sud = true to read SoundTrap .sud files".into());
     }
-
-    run_core_analysis(&audio_data, fs, config, sensitivity_db, file_start_datetime)
+    let raw_format = audio_io::RawFormat::from_config(config)?;
+    if !config.write_provenance {
+        let reader = AudioReader::open(path, raw_format.as_ref())?;
+        return analyse_recording(path, reader, config, calibration);
+    }
+    // Files are read front to back, and hashed as they are decoded
+    let file = fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut hashed = provenance::HashingReader::new(file);
+    let reader = AudioReader::from_reader(path, Box::new(&mut hashed), len, raw_format.as_ref())?;
+    let mut analysis = analyse_recording(path, reader, config, calibration)?;
+    analysis.0.provenance.input = Some(hashed.finish(path)?);
+    Ok(analysis)
 }
 
-
-/// Core analysis function performing segmentation, FFT, and level calculation.
-fn run_core_analysis(
-    audio_data: &[f32],
-    fs: f64,
+/// Streams a recording through the analysis, as for `analyse_file`. `path` names the recording
+/// and need not exist on disk (archive entries are named by their path inside the archive).
+fn analyse_recording(
+    path: &Path,
+    reader: AudioReader,
     config: &AnalysisConfig,
-    sensitivity_db: f64,
-    file_start_time: Option<NaiveDateTime>,
-) -> Result<FileAnalysisResult, Box<dyn std::error::Error>> {
+    calibration: &utils::Calibration,
+) -> Result<RecordingAnalysis, Box<dyn std::error::Error>> {
+    let file_start_datetime = resolve_start_time(path, config, &reader);
 
-    let n_total_samples = audio_data.len();
-    let overlap_ratio = config.overlap_percentage / 100.0;
+    let mut audio = AudioStream::new(reader, config)?;
+    let fs = audio.fs();
+    let (n_window_samples, n_step) = segment_layout(config, fs)?;
+    let segments = Segments::new(&mut audio, n_window_samples, n_step);
+    let mut result = run_core_analysis(segments, fs, config, calibration, file_start_datetime)?;
+
+    let file_gaps = audio.gaps();
+    if !file_gaps.is_empty() {
+        let gap_secs: f64 = file_gaps.iter().map(|gap| gap.duration_secs).sum();
+        println!("  {} sampling gaps ({:.6} s) recorded in SUD chunk headers", file_gaps.len(), gap_secs);
+        result.duration_secs += gap_secs;
+    }
+    let (audio_info, qa_report) = audio.finish();
+    result.provenance.format = Some(audio_info.clone());
+    if let Some(report) = &qa_report {
+        qa::print_summary(report);
+        // A row is flagged if any QA block overlapping one of its segments was flagged
+        result.qa_flags = Some(result.row_spans.iter().map(|&(start, end)| report.flags_between(start, end)).collect());
+        for partial in [&mut result.head_partial, &mut result.tail_partial].into_iter().flatten() {
+            partial.qa_flags = report.flags_between(partial.span.0, partial.span.1);
+        }
+    }
+    Ok((result, audio_info, qa_report))
+}
 
+/// Reads an audio file, resamples it to the target sample rate (if set) and applies the
+/// configured pre-filter, returning the samples and sample rate.
+pub fn load_audio(path: &Path, config: &AnalysisConfig) -> Result<(Vec<f32>, f64), Box<dyn std::error::Error>> {
+    let mut audio = AudioStream::open(path, config)?;
+    let samples = audio.read_to_end()?;
+    Ok((samples, audio.fs()))
+}
+
+/// Window length and step between the starts of consecutive windows, in samples.
+fn segment_layout(config: &AnalysisConfig, fs: f64) -> Result<(usize, usize), String> {
+    let overlap_ratio = config.overlap_percentage / 100.0;
     let n_window_samples = match config.window_unit {
         WindowUnit::Samples => config.window_length as usize,
         WindowUnit::Seconds => (config.window_length * fs).round() as usize,
     };
-
-    if n_window_samples == 0 || n_window_samples > n_total_samples {
-        return Err(format!("Invalid window length {} for signal length {}", n_window_samples, n_total_samples).into());
+    if n_window_samples == 0 {
+        return Err(format!("Invalid window length {}", n_window_samples));
     }
-
     let n_step = (n_window_samples as f64 * (1.0 - overlap_ratio)).round() as usize;
     if n_step == 0 {
-         return Err("Overlap results in zero step size.".into());
+        return Err("Overlap results in zero step size.".to_string());
+    }
+    Ok((n_window_samples, n_step))
+}
+
+/// Determines the start time of a file. The chunk times of a SUD file, or (when enabled) a
+/// SoundTrap sidecar log, are the most accurate source and are used when present (the sampling
+/// gaps of a SUD file are found as it is decoded). Otherwise the time comes from the file name (if a timestamp format or regex is configured)
+/// or from metadata embedded in WAV files, in the configured order of preference.
+/// Filename timestamps, and embedded timestamps without a time zone, are local to
+/// `timestamp_timezone` (UTC by default). Embedded metadata is read from the whole file on disk;
+/// recordings that are not on disk (archive entries) only have the header chunks the `reader`
+/// read before their audio samples.
+fn resolve_start_time(
+    path: &Path,
+    config: &AnalysisConfig,
+    reader: &AudioReader,
+) -> Option<DateTime<Utc>> {
+    // SUD files carry the time of each chunk of samples, which no sidecar log improves on
+    if let Some(start_time) = reader.start_time() {
+        println!("  Start time {} from SUD chunk headers", start_time.naive_utc());
+        return Some(start_time);
+    }
+    let log = if config.use_recorder_logs {
+        recorder_log::find_recorder_log(path)
+    } else {
+        Ok(None)
+    };
+    match log {
+        Ok(Some(log)) => {
+            println!("  Start time {} from {}", log.start_time, log.source);
+            return Some(log.start_time.and_utc());
+        }
+        Ok(None) => {}
+        Err(e) => eprintln!("  Warning: {}", e),
     }
 
-    let (scaled_window, _alpha) = dsp::generate_scaled_window(&config.window_type, n_window_samples);
-    let noise_bw = dsp::noise_power_bandwidth(scaled_window.view(), n_window_samples);
-    let delf = fs / n_window_samples as f64;
+    // The zone is validated when the configuration is loaded
+    let zone = TimeZoneSpec::from_setting(&config.timestamp_timezone).unwrap_or(TimeZoneSpec::Utc);
+    let to_utc = |local| match zone.to_utc(local) {
+        Ok(utc) => Some(utc),
+        Err(e) => {
+            eprintln!("  Warning: {}", e);
+            None
+        }
+    };
+    let from_filename = || match timestamp::timestamp_from_filename(path, config) {
+        Ok(start_time) => start_time.and_then(to_utc),
+        Err(e) => {
+            eprintln!("  Warning: {}", e);
+            None
+        }
+    };
+    let from_metadata = || {
+        if !path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("wav")) {
+            return None;
+        }
+        let metadata = if path.is_file() {
+            wav_metadata::read_wav_metadata(path)
+        } else {
+            Ok(WavMetadata::from_chunks(reader.header_chunks()))
+        };
+        let embedded = match metadata {
+            Ok(metadata) => metadata.start_time()?,
+            Err(e) => {
+                eprintln!("  Warning: Could not read WAV metadata of {}: {}", path.display(), e);
+                return None;
+            }
+        };
+        println!("  Start time {} from embedded {} metadata", embedded.time, embedded.source);
+        if embedded.is_utc { Some(embedded.time.and_utc()) } else { to_utc(embedded.time) }
+    };
 
-    let pref = match config.environment {
-        Environment::Air => 20.0,
-        Environment::Wat => 1.0,
+    let start_time = if config.prefer_embedded_timestamp {
+        from_metadata().or_else(from_filename)
+    } else {
+        from_filename().or_else(from_metadata)
     };
+    if start_time.is_none() && (config.timestamp_format.is_some() || config.timestamp_regex.is_some()) {
+        eprintln!("  Warning: No start time found for {}. Time column will be relative for this file.", path.display());
+    }
+    start_time
+}
+
+/// Core analysis function performing segmentation, FFT, and level calculation.
+/// Segments are consumed as they are read, so memory use depends on the window length and
+/// averaging period rather than on the length of the file.
+fn run_core_analysis(
+    mut segments: Segments,
+    fs: f64,
+    config: &AnalysisConfig,
+    calibration: &utils::Calibration,
+    file_start_time: Option<DateTime<Utc>>,
+) -> Result<FileAnalysisResult, Box<dyn std::error::Error>> {
+    let n_window_samples = segments.window_len();
+    let n_step = segments.step();
+
+    let n_fft = dsp::fft_length(n_window_samples, config.nfft, config.nfft_power_of_two)?;
+    if n_fft != n_window_samples {
+        println!("  Zero-padding {} sample windows to {} point FFTs", n_window_samples, n_fft);
+    }
+    // Zero-padding interpolates the spectrum: bins are spaced fs/nfft apart, but adjacent bins
+    // are correlated, so the noise bandwidth (in bins) and band sums scale with nfft/N.
+    let padding_factor = n_fft as f64 / n_window_samples as f64;
+
+    // Welch uses a single window per segment; multitaper averages the spectra of several DPSS tapers.
+    let tapers: Vec<Array1<f32>> = match config.spectral_estimator {
+        SpectralEstimator::Welch => {
+            let (scaled_window, _props) = dsp::generate_scaled_window(&config.window_type, &config.window_parameters, n_window_samples);
+            vec![scaled_window]
+        }
+        SpectralEstimator::Multitaper => {
+            let n_tapers = config.multitaper_tapers
+                .unwrap_or_else(|| ((2.0 * config.multitaper_nw).floor() as usize).saturating_sub(1).max(1));
+            println!("  Using multitaper estimator with NW = {} and {} tapers", config.multitaper_nw, n_tapers);
+            dsp::generate_dpss_tapers(n_window_samples, config.multitaper_nw, n_tapers)?
+        }
+    };
+    let noise_bw = dsp::noise_power_bandwidth(tapers[0].view(), n_window_samples) * padding_factor;
+    let delf = fs / n_fft as f64;
+
+    let pref = utils::reference_pressure(&config.environment);
 
     // Calculate frequency axis and indices for slicing
-    let fft_freqs: Array1<f64> = Array::linspace(0.0, fs / 2.0, n_window_samples / 2 + 1);
+    let fft_freqs: Array1<f64> = (0..=n_fft / 2).map(|k| k as f64 * delf).collect();
     // Note: MATLAB Pss goes from index 2 to N/2+1. Our fft_freqs maps to FFT result indices 0 to N/2.
     // Pss corresponds to indices 1 to N/2 of the full FFT result.
     // So, fft_freqs[1] corresponds to Pss[0].
@@ -227,85 +623,145 @@ fn run_core_analysis(
     }
     let selected_freqs = pss_freqs.slice(s![pss_flow_idx..=pss_fhigh_idx]);
     let n_selected_freqs = selected_freqs.len();
+    // Calibration is applied per bin in the linear domain, before bins are summed or averaged
+    let bin_gains = calibration.bin_gains(&selected_freqs.to_vec());
 
-    // --- Segmentation and Parallel Processing ---
-    let num_segments = if n_total_samples >= n_window_samples {
-                           (n_total_samples - n_window_samples) / n_step + 1
-                       } else { 0 };
-    if num_segments == 0 {
-        return Err("Audio signal too short for specified window length and overlap.".into());
-    }
+    // --- Segmentation, Parallel Processing and Welch Averaging ---
+    // Segments are read in batches and processed in parallel. Each output row is built from a
+    // contiguous range of segments, starting at a given offset (in seconds) from the file start;
+    // the spectra of a row are averaged as soon as the row is complete, then released. Pending
+    // segments are kept with their start sample, from which the audio behind a row is found.
+    let time_step_secs = n_step as f64 / fs;
+    let mut grouper = RowGrouper::from_config(config, n_step, fs, file_start_time)?;
+    let median_bias_correction = config.analysis_type == AnalysisType::Psd;
+    let batch_size = rayon::current_num_threads() * SEGMENTS_PER_THREAD;
 
-    let results_power: Vec<Vec<f64>> = (0..num_segments)
-        .into_par_iter()
-        .map(|i| {
-            let start = i * n_step;
-            let end = start + n_window_samples;
-            let segment = &audio_data[start..end];
+    let mut pending: VecDeque<(u64, Vec<f64>)> = VecDeque::new(); // Segments not yet in a row
+    let mut pending_start = 0; // Index of the first pending segment
+    let mut row_groups: Vec<(f64, (f64, f64), usize)> = Vec::new(); // Offset, audio span and segment count of each row
+    let mut averaged_results: Vec<Vec<f64>> = Vec::new();
+    let mut head_partial: Option<PartialInterval> = None;
+    let mut tail_partial: Option<PartialInterval> = None;
+    let mut num_segments = 0;
+    let mut average_rows = |groups: Vec<RowGroup>, pending: &VecDeque<(u64, Vec<f64>)>, pending_start: usize, at_end: bool| {
+        let n_groups = groups.len();
+        for (g, group) in groups.into_iter().enumerate() {
+            let segments_to_average: Vec<Vec<f64>> = group.segments.clone().map(|i| pending[i - pending_start].1.clone()).collect();
+            // Audio covered by the row, in seconds of recorded samples from the file start
+            let span = (
+                pending[group.segments.start - pending_start].0 as f64 / fs,
+                (pending[group.segments.end - 1 - pending_start].0 + n_window_samples as u64) as f64 / fs,
+            );
+            let row = group.kept().then_some(row_groups.len());
 
-            let mut windowed_segment = segment.to_vec();
-            for (sample, &win_val) in windowed_segment.iter_mut().zip(scaled_window.iter()) {
-                *sample *= win_val;
+            // Intervals cut short by the start or end of the file may continue in the next or
+            // previous file of a batch, so their segments are kept for joining
+            let is_head = group.segments.start == 0;
+            let is_tail = at_end && g + 1 == n_groups;
+            if let (Some(interval), true) = (group.interval, group.is_partial() && (is_head || is_tail)) {
+                let partial = PartialInterval {
+                    key: interval.key,
+                    time: RowTime::at_offset(file_start_time, group.offset_secs),
+                    segments: segments_to_average.clone(),
+                    coverage: interval.coverage,
+                    span,
+                    qa_flags: 0,
+                    row,
+                };
+                if is_head {
+                    head_partial = Some(partial.clone());
+                }
+                if is_tail {
+                    tail_partial = Some(partial);
+                }
             }
 
-            let fft_result = dsp::calculate_fft(&windowed_segment);
+            if row.is_some() {
+                averaged_results.push(if segments_to_average.len() == 1 {
+                    segments_to_average.into_iter().next().unwrap()
+                } else {
+                    dsp::average_segments(&segments_to_average, &config.welch_statistic, median_bias_correction)
+                });
+                row_groups.push((group.offset_secs, span, group.segments.len()));
+            }
+        }
+    };
 
-            // Calculate single-sided power spectrum (linear) Pss
-            let power_spectrum: Vec<f64> = fft_result[1..=n_window_samples / 2]
-                .iter()
-                .map(|c| (c.norm_sqr() / (n_window_samples as f32).powi(2)) as f64 * 2.0)
-                .collect();
+    loop {
+        let batch: Vec<Segment> = segments.by_ref().take(batch_size).collect::<Result<_, _>>()?;
+        if batch.is_empty() {
+            break;
+        }
+        let batch_starts: Vec<(u64, f64)> = batch.iter().map(|segment| (segment.start, segment.gap_secs)).collect();
+        let batch_values: Vec<Vec<f64>> = batch
+            .into_par_iter()
+            .map(|Segment { samples: mut segment, .. }| {
+                dsp::detrend_segment(&mut segment, &config.detrend);
 
-            // Select frequency range relative to Pss
-            power_spectrum[pss_flow_idx..=pss_fhigh_idx].to_vec()
-        })
-        .collect();
+                // Calculate single-sided power spectrum (linear) Pss, averaged over the tapers
+                let mut power_spectrum = vec![0.0f64; n_fft / 2];
+                for taper in &tapers {
+                    let mut windowed_segment = segment.clone();
+                    for (sample, &win_val) in windowed_segment.iter_mut().zip(taper.iter()) {
+                        *sample *= win_val;
+                    }
 
-     // --- Welch Averaging ---
-     let (averaged_results, final_num_segments) = if let Some(welch_k) = config.welch_factor {
-         if welch_k > 1 && welch_k <= num_segments {
-             println!("  Applying Welch averaging with factor {}", welch_k);
-             let num_welch_segments = (num_segments as f64 / welch_k as f64).ceil() as usize;
-             let mut welch_averaged: Vec<Vec<f64>> = Vec::with_capacity(num_welch_segments);
-
-             for i in 0..num_welch_segments {
-                 let welch_start_idx = i * welch_k;
-                 let welch_end_idx = std::cmp::min(welch_start_idx + welch_k, num_segments);
-                 let segments_to_average = &results_power[welch_start_idx..welch_end_idx];
-
-                 if !segments_to_average.is_empty() {
-                     let mut avg_power = vec![0.0; n_selected_freqs];
-                     for freq_idx in 0..n_selected_freqs {
-                         let sum: f64 = segments_to_average.iter().map(|seg| seg[freq_idx]).sum();
-                         avg_power[freq_idx] = sum / segments_to_average.len() as f64;
-                     }
-                     welch_averaged.push(avg_power);
-                 }
-             }
-             (welch_averaged, num_welch_segments)
-         } else {
-             (results_power, num_segments)
-         }
-     } else {
-         (results_power, num_segments)
-     };
+                    let fft_result = dsp::calculate_fft(&windowed_segment, n_fft);
+                    for (p, c) in power_spectrum.iter_mut().zip(&fft_result[1..=n_fft / 2]) {
+                        *p += (c.norm_sqr() / (n_window_samples as f32).powi(2)) as f64 * 2.0 / tapers.len() as f64;
+                    }
+                }
 
-    // --- Convert to dB and Apply Calibration ---
-    let mut final_results_db: Vec<Vec<f64>> = Vec::with_capacity(final_num_segments);
-    for power_vec in averaged_results {
-        let db_vec: Vec<f64> = match config.analysis_type {
-            AnalysisType::Psd => {
-                power_vec.iter()
-                    .map(|&p| utils::power_to_db(p / (delf * noise_bw), pref) - sensitivity_db)
-                    .collect()
-            }
-            AnalysisType::Broadband => {
-                 let sum_power: f64 = power_vec.iter().sum();
-                 vec![utils::power_to_db(sum_power, pref) - sensitivity_db] //-58.77
+                // Select frequency range relative to Pss
+                let selected: Vec<f64> = power_spectrum[pss_flow_idx..=pss_fhigh_idx]
+                    .iter()
+                    .zip(&bin_gains)
+                    .map(|(p, g)| p * g)
+                    .collect();
+
+                // Broadband levels are summed per segment first, so that non-linear statistics
+                // (median, min, max, dB mean) act on the band power rather than on each bin.
+                match config.analysis_type {
+                    AnalysisType::Psd => selected,
+                    AnalysisType::Broadband => vec![selected.iter().sum::<f64>() / padding_factor],
+                }
+            })
+            .collect();
+
+        for ((start, gap_secs), values) in batch_starts.into_iter().zip(batch_values) {
+            pending.push_back((start, values));
+            num_segments += 1;
+            let offset_secs = start as f64 / fs + gap_secs;
+            average_rows(grouper.push(offset_secs), &pending, pending_start, false);
+            while pending_start < grouper.first_open_segment() {
+                pending.pop_front();
+                pending_start += 1;
             }
-        };
-        final_results_db.push(db_vec);
+        }
     }
+    if num_segments == 0 {
+        return Err("Audio signal too short for specified window length and overlap.".into());
+    }
+    average_rows(grouper.finish(), &pending, pending_start, true);
+    drop(pending);
+
+    let final_num_segments = row_groups.len();
+    let row_step_secs = if let Some(interval) = &config.averaging_interval {
+        utils::parse_duration_secs(interval)?
+    } else {
+        row_groups.first().map_or(time_step_secs, |&(_, _, n_segments)| n_segments as f64 * time_step_secs)
+    };
+    let row_spans: Vec<(f64, f64)> = row_groups.iter().map(|&(_, span, _)| span).collect();
+
+    // --- Convert to dB and Apply Calibration ---
+    let levels = LevelScale {
+        divisor: match config.analysis_type {
+            AnalysisType::Psd => delf * noise_bw,
+            AnalysisType::Broadband => 1.0,
+        },
+        pref,
+    };
+    let final_results_db: Vec<Vec<f64>> = averaged_results.iter().map(|power_vec| levels.to_db(power_vec)).collect();
 
     // --- Construct Final Output Array ---
     let n_output_cols = match config.analysis_type {
@@ -313,31 +769,23 @@ fn run_core_analysis(
         AnalysisType::Broadband => 1,
     };
 
-    // Create header row (frequencies for PSD, 0.0 placeholder for Broadband time column)
-    let mut header_row_vec = vec![0.0; n_output_cols + 1]; // +1 for time column
-    if config.analysis_type == AnalysisType::Psd {
-         header_row_vec[1..].copy_from_slice(selected_freqs.as_slice().unwrap());
-    }
-    let header_row = Array1::from(header_row_vec);
-
-    // Create data rows
-    let mut data_rows = Array2::<f64>::zeros((final_num_segments, n_output_cols + 1));
-    let time_step_secs = n_step as f64 / fs;
-    let welch_time_multiplier = config.welch_factor.unwrap_or(1) as f64;
+    // Create header row (frequencies for PSD, 0.0 placeholder for Broadband)
+    let header_row = match config.analysis_type {
+        AnalysisType::Psd => selected_freqs.to_owned(),
+        AnalysisType::Broadband => Array1::zeros(1),
+    };
 
+    // Create data rows and their times
+    let mut data_rows = Array2::<f64>::zeros((final_num_segments, n_output_cols));
     for (i, db_vec) in final_results_db.iter().enumerate() {
-        let time_secs = i as f64 * time_step_secs * welch_time_multiplier;
-        let current_time = if let Some(start_dt) = file_start_time {
-            let start_secs = start_dt.timestamp() as f64 + start_dt.timestamp_subsec_nanos() as f64 * 1e-9;
-            start_secs + time_secs
-        } else {
-            time_secs
-        };
-        data_rows[[i, 0]] = current_time;
         for (j, &db_val) in db_vec.iter().enumerate() {
-            data_rows[[i, j + 1]] = db_val;
+            data_rows[[i, j]] = db_val;
         }
     }
+    let times: Vec<RowTime> = row_groups
+        .iter()
+        .map(|&(offset_secs, _, _)| RowTime::at_offset(file_start_time, offset_secs))
+        .collect();
 
     // Combine header and data manually
     let num_rows = data_rows.nrows() + 1;
@@ -346,16 +794,370 @@ fn run_core_analysis(
     final_array.row_mut(0).assign(&header_row);
     final_array.slice_mut(s![1.., ..]).assign(&data_rows);
 
+    let analysis = provenance::AnalysisParameters {
+        sample_rate_hz: fs,
+        sensitivity_db: match calibration {
+            utils::Calibration::Scalar(sensitivity_db) => Some(*sensitivity_db),
+            utils::Calibration::Curve(_) => None,
+        },
+        calibration: calibration.describe(),
+        n_window_samples,
+        n_step,
+        n_fft,
+        noise_bw,
+        delf,
+        frequency_range_hz: [selected_freqs[0], selected_freqs[n_selected_freqs - 1]],
+        n_frequencies: n_selected_freqs,
+    };
+
     Ok(FileAnalysisResult {
         data: final_array,
+        times,
         start_time: file_start_time,
-        // duration_secs: total_duration_secs, // Removed, can be inferred
+        qa_flags: None,
+        row_spans,
+        duration_secs: segments.samples_read() as f64 / fs,
+        row_step_secs,
+        provenance: RecordingProvenance { input: None, format: None, analysis },
+        levels,
+        head_partial,
+        tail_partial,
     })
 }
 
-/// Generates the output CSV filename based on input path and config.
-fn generate_output_filename(input_path: &Path, config: &AnalysisConfig) -> String {
-    let stem = input_path.file_stem().unwrap_or_default().to_string_lossy();
+/// Compares the expected end of one file with the start of the next. Differences of less than
+/// the one-second resolution of filename timestamps are not reported.
+fn find_time_gap(previous: &FileAnalysisResult, next: &FileAnalysisResult) -> Option<TimeGap> {
+    let previous_end = previous.start_time? + timestamp::secs_to_duration(previous.duration_secs);
+    let next_start = next.start_time?;
+    let duration_secs = timestamp::secs_between(previous_end, next_start).abs();
+    let spans_rows = duration_secs > 0.5 * previous.row_step_secs.min(next.row_step_secs);
+    (duration_secs >= TIMESTAMP_RESOLUTION_SECS).then_some(TimeGap { previous_end, next_start, spans_rows })
+}
+
+/// Joins the clock-aligned intervals split between contiguous files: the segments of an interval
+/// cut short by the end of one file and by the start of the next are averaged together into a
+/// single row of the later file, replacing the partial rows of both. Returns the number of
+/// intervals joined.
+fn join_split_intervals(file_results: &mut [FileAnalysisResult], gaps: &[Option<TimeGap>], config: &AnalysisConfig) -> usize {
+    let median_bias_correction = config.analysis_type == AnalysisType::Psd;
+    let mut joined = 0;
+    for i in 1..file_results.len() {
+        if gaps[i].as_ref().is_some_and(|gap| gap.spans_rows) {
+            continue;
+        }
+        let (before, after) = file_results.split_at_mut(i);
+        let (previous, next) = (&mut before[i - 1], &mut after[0]);
+        let (Some(tail), Some(head)) = (previous.tail_partial.take(), next.head_partial.take()) else {
+            continue;
+        };
+        if tail.key != head.key {
+            previous.tail_partial = Some(tail);
+            next.head_partial = Some(head);
+            continue;
+        }
+
+        if let Some(row) = tail.row {
+            previous.remove_row(row);
+        }
+        if let Some(row) = head.row {
+            next.remove_row(row);
+        }
+        let mut merged = tail.merged_with(&head);
+        if merged.coverage >= config.averaging_min_coverage {
+            let power = dsp::average_segments(&merged.segments, &config.welch_statistic, median_bias_correction);
+            next.insert_row(0, &next.levels.to_db(&power), &merged);
+            merged.row = Some(0);
+        }
+        // A file within a single interval ends with the interval it starts with, which now
+        // carries on into the file after it
+        match &mut next.tail_partial {
+            Some(next_tail) if next_tail.key == merged.key => *next_tail = merged.clone(),
+            Some(next_tail) => {
+                next_tail.row = next_tail.row.map(|row| row + merged.row.is_some() as usize - head.row.is_some() as usize);
+            }
+            None => {}
+        }
+        next.head_partial = Some(merged);
+        joined += 1;
+    }
+    joined
+}
+
+/// Times of the missing rows between the last row of one file and the first row of the next,
+/// continuing the previous file's row grid.
+fn gap_fill_times(previous: &FileAnalysisResult, next: &FileAnalysisResult) -> Vec<RowTime> {
+    let (Some(RowTime::Absolute(last_row_time)), Some(RowTime::Absolute(next_row_time))) = (previous.times.last(), next.times.first()) else {
+        return Vec::new();
+    };
+    let step = previous.row_step_secs;
+    let missing_secs = timestamp::secs_between(*last_row_time, *next_row_time);
+    (1..)
+        .map(|k| k as f64 * step)
+        .take_while(|&offset| offset < missing_secs - 0.5 * step)
+        .map(|offset| RowTime::Absolute(*last_row_time + timestamp::secs_to_duration(offset)))
+        .collect()
+}
+
+/// Prints the gaps and overlaps found in a batch and writes them to a CSV file.
+fn report_time_gaps(gaps: &[Option<TimeGap>], config: &AnalysisConfig, zone: &TimeZoneSpec) {
+    let found: Vec<&TimeGap> = gaps.iter().flatten().collect();
+    if found.is_empty() {
+        println!("  No gaps or overlaps between files.");
+        return;
+    }
+
+    let format_time = |time: DateTime<Utc>| zone.format(time);
+    for gap in &found {
+        let kind = if gap.duration_secs() > 0.0 { "Gap" } else { "Overlap" };
+        println!(
+            "  {} of {:.3} s between {} and {}",
+            kind,
+            gap.duration_secs().abs(),
+            format_time(gap.previous_end.min(gap.next_start)),
+            format_time(gap.previous_end.max(gap.next_start))
+        );
+    }
+
+    let gaps_path = PathBuf::from(&config.output_dir).join("PAMGuide_Batch_Gaps.csv");
+    let write_result = (|| -> Result<(), Box<dyn std::error::Error>> {
+        let mut wtr = csv::Writer::from_path(&gaps_path)?;
+        wtr.write_record(["type", "previous_file_end", "next_file_start", "duration_s"])?;
+        for gap in &found {
+            wtr.write_record([
+                if gap.duration_secs() > 0.0 { "gap" } else { "overlap" }.to_string(),
+                format_time(gap.previous_end),
+                format_time(gap.next_start),
+                format!("{:.3}", gap.duration_secs()),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    })();
+    match write_result {
+        Ok(_) => println!("  Gap report written to: {}", gaps_path.display()),
+        Err(e) => eprintln!("  Error writing gap report {}: {}", gaps_path.display(), e),
+    }
+}
+
+/// How consecutive segments are grouped into output rows.
+enum Grouping {
+    Single,
+    Welch(usize), // Fixed groups of this many segments
+    // Averaging intervals aligned to wall-clock boundaries, in nanoseconds
+    Clock { interval_ns: i64, start_ns: i64, n_step: usize, fs: f64, min_coverage: f64 },
+}
+
+/// A row closed by the `RowGrouper`: the offset of the row start from the file start (in
+/// seconds), the range of segments averaged in the row and, for clock-aligned rows, the interval.
+#[derive(Debug, Clone, PartialEq)]
+struct RowGroup {
+    offset_secs: f64,
+    segments: Range<usize>,
+    interval: Option<ClockInterval>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ClockInterval {
+    key: i64,      // Index of the interval since the Unix epoch (or the file start)
+    coverage: f64, // Fraction of the interval's expected segments present
+    kept: bool,    // False if dropped for coverage below the minimum
+}
+
+impl RowGroup {
+    fn kept(&self) -> bool {
+        self.interval.is_none_or(|interval| interval.kept)
+    }
+
+    fn is_partial(&self) -> bool {
+        self.interval.is_some_and(|interval| interval.coverage < 1.0 - 1e-9)
+    }
+}
+
+/// Assigns segments to output rows as they arrive. Segments are given by their start time, in
+/// seconds from the file start including any sampling gaps; rows never span a gap.
+///
+/// Clock-aligned rows hold the segments whose start time falls in an averaging interval;
+/// without a file timestamp the intervals are aligned to the start of the file instead.
+/// Intervals covered by fewer than `min_coverage` of the expected segments are returned but
+/// not kept, so that an interval split between files can still be joined.
+struct RowGrouper {
+    grouping: Grouping,
+    time_step_secs: f64,
+    n_segments: usize,   // Segments pushed so far
+    group_start: usize,  // First segment of the open row
+    group_offset: f64,   // Start of the open row (fixed-size groups)
+    group_key: i64,      // Interval of the open row (clock-aligned grouping)
+    last_offset: f64,    // Start of the last segment
+    partial_intervals: usize,
+    dropped_intervals: usize,
+}
+
+impl RowGrouper {
+    fn from_config(
+        config: &AnalysisConfig,
+        n_step: usize,
+        fs: f64,
+        file_start_time: Option<DateTime<Utc>>,
+    ) -> Result<RowGrouper, String> {
+        let grouping = if let Some(interval) = &config.averaging_interval {
+            let interval_secs = utils::parse_duration_secs(interval)?;
+            println!("  Applying clock-aligned averaging over {} intervals ({:?})", interval, config.welch_statistic);
+            Self::clock_grouping(n_step, fs, interval_secs, config.averaging_min_coverage, file_start_time)
+        } else if let Some(welch_k) = config.welch_factor.filter(|&k| k > 1) {
+            println!("  Applying Welch averaging with factor {} ({:?})", welch_k, config.welch_statistic);
+            Grouping::Welch(welch_k)
+        } else {
+            Grouping::Single
+        };
+        Ok(RowGrouper::new(grouping, n_step as f64 / fs))
+    }
+
+    fn clock_grouping(
+        n_step: usize,
+        fs: f64,
+        interval_secs: f64,
+        min_coverage: f64,
+        file_start_time: Option<DateTime<Utc>>,
+    ) -> Grouping {
+        let start_ns = match file_start_time {
+            Some(start_dt) => start_dt.timestamp_nanos_opt().unwrap_or(0),
+            None => {
+                println!("  Warning: No file timestamp available. Averaging intervals are aligned to the file start.");
+                0
+            }
+        };
+        Grouping::Clock {
+            // Intervals are checked to be at least 1 ns when the configuration is loaded
+            interval_ns: ((interval_secs * 1e9).round() as i64).max(1),
+            start_ns,
+            n_step,
+            fs,
+            min_coverage,
+        }
+    }
+
+    fn new(grouping: Grouping, time_step_secs: f64) -> RowGrouper {
+        RowGrouper {
+            grouping,
+            time_step_secs,
+            n_segments: 0,
+            group_start: 0,
+            group_offset: 0.0,
+            group_key: 0,
+            last_offset: 0.0,
+            partial_intervals: 0,
+            dropped_intervals: 0,
+        }
+    }
+
+    /// First segment that may still belong to a future row; earlier segments are no longer needed.
+    fn first_open_segment(&self) -> usize {
+        self.group_start
+    }
+
+    /// Adds the next segment, starting `offset_secs` after the file start, and returns the rows
+    /// it completes.
+    fn push(&mut self, offset_secs: f64) -> Vec<RowGroup> {
+        let i = self.n_segments;
+        self.n_segments += 1;
+        // A segment more than a step after the previous one follows a gap in sampling
+        let after_gap = i > 0 && offset_secs - self.last_offset > 1.5 * self.time_step_secs;
+        self.last_offset = offset_secs;
+        match self.grouping {
+            Grouping::Single => {
+                self.group_start = self.n_segments;
+                vec![RowGroup { offset_secs, segments: i..i + 1, interval: None }]
+            }
+            Grouping::Welch(k) => {
+                let mut rows = Vec::new();
+                if after_gap && self.group_start < i {
+                    rows.push(self.close_group(i));
+                }
+                if self.group_start == i {
+                    self.group_offset = offset_secs;
+                }
+                if self.n_segments - self.group_start == k {
+                    rows.push(self.close_group(self.n_segments));
+                }
+                rows
+            }
+            Grouping::Clock { interval_ns, start_ns, .. } => {
+                let segment_ns = start_ns + (offset_secs * 1e9).round() as i64;
+                let key = segment_ns.div_euclid(interval_ns);
+                if i == 0 {
+                    self.group_key = key;
+                }
+                if key == self.group_key {
+                    return Vec::new();
+                }
+                let row = self.close_interval(i);
+                self.group_key = key;
+                vec![row]
+            }
+        }
+    }
+
+    /// Returns the rows left open at the end of the file.
+    fn finish(&mut self) -> Vec<RowGroup> {
+        let (start, end) = (self.group_start, self.n_segments);
+        if start == end {
+            return Vec::new();
+        }
+        match self.grouping {
+            Grouping::Single => Vec::new(),
+            // Files too short for a single group are not averaged
+            Grouping::Welch(_) if start == 0 => (0..end)
+                .map(|i| RowGroup { offset_secs: i as f64 * self.time_step_secs, segments: i..i + 1, interval: None })
+                .collect(),
+            Grouping::Welch(_) => vec![self.close_group(end)],
+            Grouping::Clock { min_coverage, .. } => {
+                let row = self.close_interval(end);
+                if self.partial_intervals > 0 {
+                    println!(
+                        "  Note: {} averaging interval(s) only partially covered by this file; {} dropped below {:.0}% coverage.",
+                        self.partial_intervals, self.dropped_intervals, min_coverage * 100.0
+                    );
+                }
+                vec![row]
+            }
+        }
+    }
+
+    /// Ends the open fixed-size group before segment `end` and returns its row.
+    fn close_group(&mut self, end: usize) -> RowGroup {
+        let start = std::mem::replace(&mut self.group_start, end);
+        RowGroup { offset_secs: self.group_offset, segments: start..end, interval: None }
+    }
+
+    /// Ends the open clock-aligned interval before segment `end` and returns its row, which is
+    /// not kept if the interval has too low a coverage.
+    fn close_interval(&mut self, end: usize) -> RowGroup {
+        let Grouping::Clock { interval_ns, start_ns, n_step, fs, min_coverage } = self.grouping else {
+            unreachable!("close_interval is only used with clock-aligned grouping");
+        };
+        let expected_segments = interval_ns as f64 * 1e-9 * fs / n_step as f64;
+        let start = std::mem::replace(&mut self.group_start, end);
+        let coverage = (end - start) as f64 / expected_segments;
+        if coverage < 1.0 - 1e-9 {
+            self.partial_intervals += 1;
+        }
+        let kept = coverage >= min_coverage;
+        if !kept {
+            self.dropped_intervals += 1;
+        }
+        RowGroup {
+            offset_secs: (self.group_key * interval_ns - start_ns) as f64 * 1e-9,
+            segments: start..end,
+            interval: Some(ClockInterval { key: self.group_key, coverage, kept }),
+        }
+    }
+}
+
+/// Generates the output CSV filename from the recording's output `stem` (see `file_output_stem`
+/// and `archive::entry_output_stem`) and config. `n_fft` is the FFT length used in the
+/// analysis, which `nfft_power_of_two` may have rounded up from `nfft`.
+fn generate_output_filename(stem: &str, config: &AnalysisConfig, n_fft: usize) -> String {
     let analysis_str = match config.analysis_type {
         AnalysisType::Psd => "PSD",
         AnalysisType::Broadband => "Broadband",
@@ -364,76 +1166,274 @@ fn generate_output_filename(input_path: &Path, config: &AnalysisConfig) -> Strin
          WindowUnit::Seconds => format!("{:.2}s", config.window_length),
          WindowUnit::Samples => format!("{}samples", config.window_length as usize),
     };
-    let window_name_str = format!("{:?}", config.window_type);
+    let window_name_str = match config.spectral_estimator {
+        SpectralEstimator::Welch => format!("{:?}", config.window_type),
+        SpectralEstimator::Multitaper => format!("Multitaper{}NW", config.multitaper_nw),
+    };
+    let nfft_str = if config.nfft.is_some() || config.nfft_power_of_two {
+        format!("_{}nfft", n_fft)
+    } else {
+        String::new()
+    };
 
     format!(
-        "{}_{}_{}{}_{:.0}PercentOverlap.csv",
+        "{}_{}_{}{}_{:.0}PercentOverlap{}.csv",
         stem,
         analysis_str,
         window_len_str,
         window_name_str,
-        config.overlap_percentage
+        config.overlap_percentage,
+        nfft_str
     )
 }
 
-/// Writes the analysis data array to a CSV file.
-fn write_csv(path: &Path, data: &Array2<f64>) -> Result<(), Box<dyn std::error::Error>> {
+/// Name of the per-file QA report, e.g. "recording_QA.csv".
+fn qa_report_filename(stem: &str) -> String {
+    format!("{}_QA.csv", stem)
+}
+
+/// Name of the per-file input format report, e.g. "recording_Input.csv".
+fn input_report_filename(stem: &str) -> String {
+    format!("{}_Input.csv", stem)
+}
+
+/// The stem of the output file names of a file on disk.
+fn file_output_stem(path: &Path) -> String {
+    path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+/// Whether any output table format (CSV, Parquet, Arrow IPC, NetCDF) is enabled.
+fn writes_tables(config: &AnalysisConfig) -> bool {
+    config.write_csv || config.write_parquet || config.write_arrow_ipc || config.write_netcdf
+}
+
+/// Writes the analysis data array in each enabled format: CSV to `path`, and Parquet, Arrow IPC
+/// and NetCDF to the same name with a `.parquet`, `.arrow` or `.nc` extension, followed by a
+/// `.json` provenance sidecar describing the `recordings` behind the data. Returns the paths
+/// written.
+fn write_tables(
+    path: &Path,
+    data: &Array2<f64>,
+    times: &[RowTime],
+    qa_flags: Option<&[u32]>,
+    zone: &TimeZoneSpec,
+    config: &AnalysisConfig,
+    recordings: &[&RecordingProvenance],
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut written = Vec::new();
+    if config.write_csv {
+        write_csv(path, data, times, qa_flags, zone)?;
+        written.push(path.to_path_buf());
+    }
+    if config.write_parquet {
+        let parquet_path = path.with_extension("parquet");
+        columnar::write_parquet(&parquet_path, data, times, qa_flags, zone, config, recordings)?;
+        written.push(parquet_path);
+    }
+    if config.write_arrow_ipc {
+        let ipc_path = path.with_extension("arrow");
+        columnar::write_arrow_ipc(&ipc_path, data, times, qa_flags, zone, config, recordings)?;
+        written.push(ipc_path);
+    }
+    if config.write_netcdf {
+        let netcdf_path = path.with_extension("nc");
+        netcdf::write_netcdf(&netcdf_path, data, times, qa_flags, config, recordings)?;
+        written.push(netcdf_path);
+    }
+    if config.write_provenance && !written.is_empty() {
+        let sidecar_path = path.with_extension("json");
+        provenance::write_sidecar(&sidecar_path, &written, config, recordings)?;
+        written.push(sidecar_path);
+    }
+    Ok(written)
+}
+
+/// Writes the analysis data array to a CSV file, with a leading time column and a trailing QA
+/// flags column if given. Absolute times are written as ISO 8601 in `zone`, relative times as
+/// seconds from the start of the file.
+fn write_csv(
+    path: &Path,
+    data: &Array2<f64>,
+    times: &[RowTime],
+    qa_flags: Option<&[u32]>,
+    zone: &TimeZoneSpec,
+) -> Result<(), Box<dyn std::error::Error>> {
     let file = fs::File::create(path)?;
     let mut wtr = csv::WriterBuilder::new().has_headers(false).from_writer(file);
 
-    // Write header row
+    // Write header row: blank time column header, then frequencies
     let header_iter = data.row(0).into_iter().map(|&f| {
-        // Leave time column header blank, format frequencies
         if f == 0.0 { "".to_string() } else { format!("{:.4}", f) }
     });
-    wtr.write_record(header_iter)?;
+    let qa_header = qa_flags.map(|_| "qa_flags".to_string());
+    wtr.write_record(std::iter::once(String::new()).chain(header_iter).chain(qa_header))?;
 
     // Write data rows
-    for row in data.rows().into_iter().skip(1) {
-        let row_iter = row.into_iter().enumerate().map(|(i, &val)| {
-            if i == 0 { // Time column
-                if val > 1e9 { // Heuristic for Unix timestamp
-                    match NaiveDateTime::from_timestamp_opt(val as i64, (val.fract() * 1e9) as u32) {
-                        Some(dt) => dt.format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
-                        None => format!("{:.3}", val), // Fallback
-                    }
-                } else {
-                    format!("{:.3}", val) // Relative time
-                }
-            } else { // Data columns
-                format!("{:.4}", val)
-            }
-        });
-        wtr.write_record(row_iter)?;
+    for (row_idx, row) in data.rows().into_iter().skip(1).enumerate() {
+        let time_value = times[row_idx].format(zone);
+        let qa_value = qa_flags.map(|flags| qa::flag_names(flags[row_idx]));
+        let row_iter = row.into_iter().map(|&val| format!("{:.4}", val));
+        wtr.write_record(std::iter::once(time_value).chain(row_iter).chain(qa_value))?;
     }
 
     wtr.flush()?;
     Ok(())
 }
 
-/// Parses timestamp from filename using the provided format string.
-/// Expects format like "PREFIX.YYYYMMDDTHHMMSSZ.wav"
-fn parse_timestamp_from_filename(path: &Path, format: &str) -> Option<NaiveDateTime> {
-    let stem = path.file_stem()?.to_string_lossy();
-    // Extract the part after the first '.' and before the second '.' (or end of stem)
-    let parts: Vec<&str> = stem.splitn(2, '.').collect();
-    if parts.len() == 2 {
-        let timestamp_str = parts[1];
-         // Optional: Remove trailing 'Z' if present and format doesn't handle it
-         // let timestamp_str_trimmed = timestamp_str.strip_suffix('Z').unwrap_or(timestamp_str);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use chrono::NaiveDate;
 
-        match NaiveDateTime::parse_from_str(timestamp_str, format) {
-            Ok(dt) => Some(dt),
-            Err(e) => {
-                eprintln!("  Timestamp parse error for '{}' (extracted: '{}') with format '{}': {}", stem, timestamp_str, format, e);
-                None
-            }
+    fn clock_aligned_groups(
+        num_segments: usize,
+        n_step: usize,
+        fs: f64,
+        interval_secs: f64,
+        min_coverage: f64,
+        file_start_time: Option<DateTime<Utc>>,
+    ) -> Vec<(f64, Range<usize>)> {
+        let grouping = RowGrouper::clock_grouping(n_step, fs, interval_secs, min_coverage, file_start_time);
+        let mut grouper = RowGrouper::new(grouping, n_step as f64 / fs);
+        let mut groups: Vec<RowGroup> = (0..num_segments).flat_map(|i| grouper.push((i * n_step) as f64 / fs)).collect();
+        groups.extend(grouper.finish());
+        groups.into_iter().filter(RowGroup::kept).map(|group| (group.offset_secs, group.segments)).collect()
+    }
+
+    struct Samples(Option<Vec<f32>>);
+
+    impl crate::stream::SampleSource for Samples {
+        fn next_block(&mut self) -> Result<Option<Vec<f32>>, Box<dyn std::error::Error>> {
+            Ok(self.0.take())
         }
-    } else {
-        eprintln!("  Could not extract timestamp part from filename stem: {}", stem);
-        None
     }
-}
 
-// Helper to convert PAMGuide format (y, m, d, H, M, S, F) to chrono format
-// fn convert_pamguide_format_to_chrono(pg_format: &str) -> String { ... } // TODO
+    #[test]
+    fn test_clock_aligned_groups() {
+        // 1 s segments with 50% overlap, file starting at 16:47:21
+        let start = NaiveDate::from_ymd_opt(2024, 7, 17).unwrap().and_hms_opt(16, 47, 21).unwrap().and_utc();
+        let groups = clock_aligned_groups(300, 500, 1000.0, 60.0, 0.0, Some(start));
+
+        // First interval starts at 16:47:00 and holds the 39 s up to 16:48:00 (78 segments)
+        assert_eq!(groups[0], (-21.0, 0..78));
+        assert_eq!(groups[1], (39.0, 78..198));
+        assert_eq!(groups.last().unwrap().1.end, 300);
+
+        // Partial intervals are dropped when below the required coverage
+        let groups = clock_aligned_groups(300, 500, 1000.0, 60.0, 0.9, Some(start));
+        assert_eq!(groups, vec![(39.0, 78..198)]);
+    }
+
+    #[test]
+    fn test_gap_detection_and_filling() {
+        // Two 60 s files with 10 s rows, the second starting 120 s after the first ends
+        let file = |start_secs: i64| {
+            let start = DateTime::from_timestamp(start_secs, 0).unwrap();
+            let data = Array2::<f64>::zeros((7, 1));
+            let times = (0..6).map(|row| RowTime::at_offset(Some(start), row as f64 * 10.0)).collect();
+            FileAnalysisResult {
+                data,
+                times,
+                start_time: Some(start),
+                qa_flags: None,
+                row_spans: Vec::new(),
+                duration_secs: 60.0,
+                row_step_secs: 10.0,
+                provenance: RecordingProvenance::default(),
+                levels: LevelScale::default(),
+                head_partial: None,
+                tail_partial: None,
+            }
+        };
+        let first = file(1_721_234_800);
+        let second = file(1_721_234_980);
+
+        let gap = find_time_gap(&first, &second).unwrap();
+        assert_eq!(gap.duration_secs(), 120.0);
+        let times = gap_fill_times(&first, &second);
+        assert_eq!(times.len(), 12);
+        assert_eq!(times[0], RowTime::Absolute(DateTime::from_timestamp(1_721_234_860, 0).unwrap()));
+        assert_eq!(*times.last().unwrap(), RowTime::Absolute(DateTime::from_timestamp(1_721_234_970, 0).unwrap()));
+
+        // Differences below a second are within timestamp resolution; longer ones are reported,
+        // but only filled when they span half a row; negative differences are overlaps
+        let short = FileAnalysisResult { duration_secs: 59.6, ..file(1_721_234_800) };
+        assert!(find_time_gap(&short, &file(1_721_234_860)).is_none());
+        let gap = find_time_gap(&first, &file(1_721_234_863)).unwrap();
+        assert_eq!((gap.duration_secs(), gap.spans_rows), (3.0, false));
+        let overlap = find_time_gap(&first, &file(1_721_234_840)).unwrap();
+        assert_eq!((overlap.duration_secs(), overlap.spans_rows), (-20.0, true));
+    }
+
+    #[test]
+    fn test_join_intervals_split_between_files() {
+        let config = test_config(
+            r#"analysis_type = "broadband"
+low_cutoff = 1.0
+high_cutoff = 40.0
+averaging_interval = "1min""#,
+        );
+        let calibration = utils::Calibration::Scalar(0.0);
+        let fs = 100.0;
+        // Two contiguous 95 s files; the interval from 16:48:00 starts in one and ends in the other
+        let analyse = |start: DateTime<Utc>, amplitude: f32| {
+            let signal: Vec<f32> = (0..9500).map(|i| amplitude * (i as f32 * 0.7).sin()).collect();
+            let mut source = Samples(Some(signal));
+            run_core_analysis(Segments::new(&mut source, 100, 50), fs, &config, &calibration, Some(start)).unwrap()
+        };
+        let start = NaiveDate::from_ymd_opt(2024, 7, 17).unwrap().and_hms_opt(16, 47, 21).unwrap().and_utc();
+        let mut results = vec![analyse(start, 1.0), analyse(start + chrono::Duration::seconds(95), 2.0)];
+        let row_time = |minute: u32| RowTime::Absolute(NaiveDate::from_ymd_opt(2024, 7, 17).unwrap().and_hms_opt(16, minute, 0).unwrap().and_utc());
+        assert_eq!(results[0].times.last(), Some(&row_time(48)));
+        assert_eq!(results[1].times.first(), Some(&row_time(48)));
+
+        let gaps = vec![None, find_time_gap(&results[0], &results[1])];
+        assert_eq!(gaps[1], None);
+        assert_eq!(join_split_intervals(&mut results, &gaps, &config), 1);
+
+        let times: Vec<RowTime> = results.iter().flat_map(|r| r.times.clone()).collect();
+        assert_eq!(times, vec![row_time(47), row_time(48), row_time(49), row_time(50)]);
+        assert_eq!(results[0].data.nrows(), 2);
+        // The joined row averages 56 s at amplitude 1 with 4 s at amplitude 2 (four times the power)
+        let expected = results[0].data[[1, 0]] + 10.0 * ((56.0 + 4.0 * 4.0) / 60.0f64).log10();
+        assert!((results[1].data[[1, 0]] - expected).abs() < 0.1, "{} vs {}", results[1].data[[1, 0]], expected);
+    }
+
+    #[test]
+    fn test_inputs_hashed_while_decoded() {
+        let config = test_config(
+            r#"analysis_type = "broadband"
+low_cutoff = 100.0
+high_cutoff = 1000.0
+window_length = 100
+window_unit = "samples""#,
+        );
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tone_16bit.wav");
+        let (result, _, _) = analyse_file(&fixture, &config, &utils::Calibration::Scalar(0.0)).unwrap();
+        assert_eq!(result.provenance.input, Some(provenance::hash_file(&fixture).unwrap()));
+        assert_eq!(result.times.len(), (1000 - 100) / 50 + 1);
+    }
+
+    #[test]
+    fn test_rows_split_at_sampling_gaps() {
+        let config = test_config(
+            r#"analysis_type = "broadband"
+low_cutoff = 1.0
+high_cutoff = 40.0
+welch_factor = 4"#,
+        );
+        let calibration = utils::Calibration::Scalar(0.0);
+        let signal: Vec<f32> = (0..1000).map(|i| (i as f32 * 0.7).sin()).collect();
+        let mut source = Samples(Some(signal));
+        // 2.5 s are missing before sample 350
+        let segments = Segments::new(&mut source, 100, 50).with_gaps(vec![(350, 2.5)]);
+        let result = run_core_analysis(segments, 100.0, &config, &calibration, None).unwrap();
+
+        // Six segments before the gap give a row of four and a partial row of two; the twelve
+        // after it start again at sample 350, 2.5 s later than their position in the file
+        let times: Vec<RowTime> = vec![0.0, 2.0, 6.0, 8.0, 10.0].into_iter().map(RowTime::Relative).collect();
+        assert_eq!(result.times, times);
+        assert_eq!(result.row_spans, vec![(0.0, 2.5), (2.0, 3.5), (3.5, 6.0), (5.5, 8.0), (7.5, 10.0)]);
+    }
+}
@@ -0,0 +1,206 @@
+//! Recordings read straight from zip, tar and gzip-compressed tar archives, without extracting
+//! them to disk. Each entry is given a path inside the archive (`<archive>/<entry name>`), so
+//! that its name is used for timestamps and output files like that of a file on disk.
+
+use flate2::read::MultiGzDecoder;
+use std::fs;
+use std::io::{BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+fn archive_kind(path: &Path) -> Option<ArchiveKind> {
+    let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+    if name.ends_with(".zip") {
+        Some(ArchiveKind::Zip)
+    } else if name.ends_with(".tar") {
+        Some(ArchiveKind::Tar)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(ArchiveKind::TarGz)
+    } else {
+        None
+    }
+}
+
+/// Whether the path has the extension of a zip, tar or tar.gz archive (case-insensitive).
+pub fn is_archive(path: &Path) -> bool {
+    archive_kind(path).is_some()
+}
+
+/// The path of an entry inside the archive. Only the normal components of the entry name are
+/// kept, so that absolute or `..` names stay inside the archive path.
+fn entry_path(archive: &Path, name: &Path) -> PathBuf {
+    let mut path = archive.to_path_buf();
+    path.extend(name.components().filter_map(|c| match c {
+        Component::Normal(part) => Some(part),
+        _ => None,
+    }));
+    path
+}
+
+/// The name of an archive without its extension, e.g. "deployment" for "deployment.tar.gz".
+fn archive_stem(path: &Path) -> String {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let lower = name.to_ascii_lowercase();
+    let extension = [".tar.gz", ".tgz", ".zip", ".tar"].iter().find(|ext| lower.ends_with(*ext)).map_or(0, |ext| ext.len());
+    name[..name.len() - extension].to_string()
+}
+
+/// The stem of the output file names of an archive entry (named as by `entry_path`): the
+/// archive name without its extension, the directories of the entry and its file stem, joined
+/// by '_', e.g. "deployment_siteA_20240717_164721" for "siteA/20240717_164721.wav" in
+/// "deployment.zip". Entries with the same file name in different directories or archives so
+/// get different outputs.
+pub fn entry_output_stem(archive: &Path, entry: &Path) -> String {
+    let mut parts = vec![archive_stem(archive)];
+    if let Some(directories) = entry.strip_prefix(archive).ok().and_then(Path::parent) {
+        parts.extend(directories.iter().map(|part| part.to_string_lossy().into_owned()));
+    }
+    parts.push(entry.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default());
+    parts.join("_")
+}
+
+/// Calls `visit` with the path, contents and size in bytes of each file entry of an archive
+/// that `include` accepts, in archive order. Entries are decompressed as they are read.
+pub fn for_each_entry(
+    path: &Path,
+    include: impl Fn(&Path) -> bool,
+    mut visit: impl FnMut(&Path, Box<dyn Read + '_>, u64),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file = BufReader::new(fs::File::open(path)?);
+    match archive_kind(path).ok_or("Not a zip, tar or tar.gz archive")? {
+        ArchiveKind::Zip => {
+            let mut archive = zip::ZipArchive::new(file)?;
+            for i in 0..archive.len() {
+                let entry = archive.by_index(i)?;
+                let Some(name) = entry.enclosed_name().filter(|_| entry.is_file()) else {
+                    continue;
+                };
+                let name = entry_path(path, &name);
+                if include(&name) {
+                    let size = entry.size();
+                    visit(&name, Box::new(entry), size);
+                }
+            }
+        }
+        ArchiveKind::Tar => visit_tar_entries(path, file, include, visit)?,
+        ArchiveKind::TarGz => visit_tar_entries(path, MultiGzDecoder::new(file), include, visit)?,
+    }
+    Ok(())
+}
+
+fn visit_tar_entries<R: Read>(
+    path: &Path,
+    reader: R,
+    include: impl Fn(&Path) -> bool,
+    mut visit: impl FnMut(&Path, Box<dyn Read + '_>, u64),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry_path(path, &entry.path()?);
+        if include(&name) {
+            let size = entry.size();
+            visit(&name, Box::new(entry), size);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_output_stem() {
+        let archive = Path::new("/data/Deployment.TAR.GZ");
+        let stem = |name: &str| entry_output_stem(archive, &entry_path(archive, Path::new(name)));
+        assert_eq!(stem("siteA/x.wav"), "Deployment_siteA_x");
+        assert_eq!(stem("siteB/x.wav"), "Deployment_siteB_x");
+        assert_eq!(stem("/x.wav"), "Deployment_x");
+        assert_eq!(entry_output_stem(Path::new("b.zip"), Path::new("b.zip/x.wav")), "b_x");
+    }
+
+    #[test]
+    fn test_read_tar_gz_entries() {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast()));
+        for (name, contents) in [("site/5678.240717164721.wav", &b"first"[..]), ("notes.txt", b"skipped"), ("5678.240717164821.wav", b"second")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, name, contents).unwrap();
+        }
+        let bytes = builder.into_inner().unwrap().finish().unwrap();
+        let path = std::env::temp_dir().join(format!("pamguide_archive_test_{}.tar.gz", std::process::id()));
+        fs::write(&path, bytes).unwrap();
+
+        let mut entries = Vec::new();
+        let result = for_each_entry(&path, |name| name.extension().is_some_and(|ext| ext == "wav"), |name, mut reader, size| {
+            let mut contents = String::new();
+            reader.read_to_string(&mut contents).unwrap();
+            entries.push((name.strip_prefix(&path).unwrap().to_path_buf(), contents, size));
+        });
+        fs::remove_file(&path).unwrap();
+        result.unwrap();
+
+        assert_eq!(entries, vec![
+            (PathBuf::from("site/5678.240717164721.wav"), "first".to_string(), 5),
+            (PathBuf::from("5678.240717164821.wav"), "second".to_string(), 6),
+        ]);
+        assert_eq!(entry_path(Path::new("/data/a.zip"), Path::new("/../b/c.wav")), PathBuf::from("/data/a.zip/b/c.wav"));
+    }
+
+    #[test]
+    fn test_decode_zip_entries() {
+        use crate::audio_io::{self, AudioReader};
+        use crate::wav_metadata::WavMetadata;
+        use std::io::Write;
+
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tone_16bit.wav");
+        let wav = fs::read(&fixture).unwrap();
+        // The same recording with an AudioMoth comment between the fmt and data chunks
+        let comment = b"Recorded at 16:47:21 17/07/2024 (UTC) by AudioMoth 24E1";
+        let mut list = b"LIST".to_vec();
+        list.extend(((4 + 8 + comment.len() + 1) as u32).to_le_bytes());
+        list.extend(b"INFOICMT");
+        list.extend((comment.len() as u32).to_le_bytes());
+        list.extend(comment);
+        list.push(0);
+        let mut tagged = [&wav[..36], &list, &wav[36..]].concat();
+        let riff_size = (tagged.len() - 8) as u32;
+        tagged[4..8].copy_from_slice(&riff_size.to_le_bytes());
+
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, contents) in [("site/tone.wav", &wav), ("tagged.wav", &tagged)] {
+            zip.start_file(name, zip::write::SimpleFileOptions::default()).unwrap();
+            zip.write_all(contents).unwrap();
+        }
+        let bytes = zip.finish().unwrap().into_inner();
+        let path = std::env::temp_dir().join(format!("pamguide_archive_test_{}.zip", std::process::id()));
+        fs::write(&path, bytes).unwrap();
+
+        let mut entries = Vec::new();
+        let result = for_each_entry(&path, |_| true, |name, reader, size| {
+            let reader = AudioReader::from_reader(name, reader, size, None).unwrap();
+            let start_time = WavMetadata::from_chunks(reader.header_chunks()).start_time().map(|start| start.time);
+            let (samples, _) = reader.read_to_end().unwrap();
+            entries.push((name.strip_prefix(&path).unwrap().to_path_buf(), samples, start_time));
+        });
+        fs::remove_file(&path).unwrap();
+        result.unwrap();
+
+        let (expected, _) = audio_io::read_audio_file(&fixture, None).unwrap();
+        let start = chrono::NaiveDate::from_ymd_opt(2024, 7, 17).unwrap().and_hms_opt(16, 47, 21).unwrap();
+        assert_eq!(entries, vec![
+            (PathBuf::from("site/tone.wav"), expected.clone(), None),
+            (PathBuf::from("tagged.wav"), expected, Some(start)),
+        ]);
+    }
+}
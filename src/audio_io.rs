@@ -1,49 +1,842 @@
-use hound::WavReader;
+use crate::config::{AnalysisConfig, ByteOrder, RawSampleFormat};
+use crate::recorder_log::SampleGap;
+use crate::sud::SudReader;
+use chrono::{DateTime, Utc};
+use claxon::FlacReader;
+use serde::Serialize;
+use std::fs;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Take};
 use std::path::Path;
 
-/// Reads a mono WAV audio file and returns its normalized samples (in [-1.0, 1.0]) and the sample rate.
-/// Supports 16-bit, 24-bit, 32-bit PCM, and 32-bit float formats.
-pub fn read_wav_file(path: &Path) -> Result<(Vec<f32>, u32), Box<dyn std::error::Error>> {
-    let mut reader = WavReader::open(path)?;
-    let spec = reader.spec();
+/// File extensions (lowercase) of the supported audio formats.
+pub const AUDIO_EXTENSIONS: [&str; 2] = ["wav", "flac"];
 
-    if spec.channels != 1 {
-        return Err(format!(
-            "Unsupported channel count: {}. Only mono files are currently supported.",
-            spec.channels
-        ).into());
+/// Whether the path has the extension of a supported audio format (case-insensitive).
+pub fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| AUDIO_EXTENSIONS.iter().any(|known| ext.eq_ignore_ascii_case(known)))
+}
+
+/// Whether the path is a SoundTrap SUD file (case-insensitive). SUD decoding has not been
+/// checked against a recorder's file, so SUD files are only read when `read_sud` is set.
+pub fn is_sud_file(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("sud"))
+}
+
+/// File extensions (lowercase) of headerless PCM files, read when a raw format is configured.
+pub const RAW_EXTENSIONS: [&str; 2] = ["bin", "raw"];
+
+/// Whether the path has the extension of a headerless PCM file (case-insensitive).
+pub fn is_raw_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| RAW_EXTENSIONS.iter().any(|known| ext.eq_ignore_ascii_case(known)))
+}
+
+/// The layout of headerless PCM files, declared in the configuration since the files do not
+/// describe themselves.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawFormat {
+    pub sample_format: RawSampleFormat,
+    pub bits_per_sample: u16,
+    pub byte_order: ByteOrder,
+    pub channels: u16,
+    pub channel: u16, // 0-based index of the analysed channel
+    pub sample_rate: u32,
+}
+
+impl RawFormat {
+    /// The raw format given by the `raw_*` settings, or None if `raw_sample_rate` is not set.
+    pub fn from_config(config: &AnalysisConfig) -> Result<Option<RawFormat>, String> {
+        let Some(sample_rate) = config.raw_sample_rate else {
+            return Ok(None);
+        };
+        if sample_rate == 0 {
+            return Err("raw_sample_rate must be positive".to_string());
+        }
+        let bits = config.raw_bits_per_sample;
+        let valid_bits = match config.raw_sample_format {
+            RawSampleFormat::Float => [32, 64].contains(&bits),
+            RawSampleFormat::Int | RawSampleFormat::Uint => [8, 16, 24, 32].contains(&bits),
+        };
+        if !valid_bits {
+            return Err(format!("Unsupported raw_bits_per_sample {} for {:?} samples", bits, config.raw_sample_format));
+        }
+        if config.raw_channels == 0 || !(1..=config.raw_channels).contains(&config.raw_channel) {
+            return Err("raw_channel must be between 1 and raw_channels".to_string());
+        }
+        Ok(Some(RawFormat {
+            sample_format: config.raw_sample_format,
+            bits_per_sample: bits,
+            byte_order: config.raw_byte_order,
+            channels: config.raw_channels,
+            channel: config.raw_channel - 1,
+            sample_rate,
+        }))
+    }
+}
+
+/// How the samples of an input file were stored and scaled to [-1.0, 1.0].
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AudioInfo {
+    pub container: &'static str, // "RIFF", "RF64", "BW64", "FLAC", "SUD" or "raw"
+    pub encoding: &'static str,  // "PCM", "IEEE float" or "X3"
+    pub bits_per_sample: u16,    // Bits per stored sample (container size)
+    pub valid_bits: u16,         // Significant bits within each sample
+    pub sample_rate: u32,
+    pub num_samples: u64,
+    pub normalisation: String,   // How stored values map to [-1.0, 1.0]
+}
+
+/// Reads a mono WAV, FLAC or SoundTrap SUD file, or a headerless PCM file in the given raw
+/// format, chosen by extension, and returns its normalized samples and a description of the
+/// format.
+pub fn read_audio_file(path: &Path, raw_format: Option<&RawFormat>) -> Result<(Vec<f32>, AudioInfo), Box<dyn std::error::Error>> {
+    AudioReader::open(path, raw_format)?.read_to_end()
+}
+
+/// Decodes a mono WAV, FLAC, SUD or raw PCM file block by block, so that files of any length
+/// can be read in bounded memory. The input is read front to back, so it can also come from a
+/// stream such as an archive entry.
+pub struct AudioReader<'a> {
+    info: AudioInfo, // `num_samples` is the length given by the file header (0 if unknown)
+    header_chunks: Vec<RiffChunk>, // WAV chunks before the audio samples
+    decoder: Decoder<'a>,
+}
+
+type Input<'a> = Box<dyn Read + 'a>;
+
+enum Decoder<'a> {
+    Wav { data: Take<BufReader<Input<'a>>>, format_tag: u16, bytes_per_sample: usize },
+    Flac { reader: FlacReader<Input<'a>>, shift: u32, scale: f32, buffer: Vec<i32> },
+    Sud { reader: SudReader<'a>, scale: f32 },
+    Raw { data: BufReader<Input<'a>>, format: RawFormat },
+}
+
+impl AudioReader<'static> {
+    /// Opens a WAV, FLAC or SUD file, chosen by extension, and reads its header. Headerless
+    /// `.bin`/`.raw` files are read in `raw_format`.
+    pub fn open(path: &Path, raw_format: Option<&RawFormat>) -> Result<AudioReader<'static>, Box<dyn std::error::Error>> {
+        let file = fs::File::open(path)?;
+        let len = file.metadata()?.len();
+        AudioReader::from_reader(path, Box::new(file), len, raw_format)
+    }
+}
+
+impl<'a> AudioReader<'a> {
+    /// Reads a WAV, FLAC, SUD or headerless PCM recording of `len` bytes from `input`, with the
+    /// format chosen by the extension of `name`.
+    pub fn from_reader(
+        name: &Path,
+        input: Input<'a>,
+        len: u64,
+        raw_format: Option<&RawFormat>,
+    ) -> Result<AudioReader<'a>, Box<dyn std::error::Error>> {
+        let extension = name.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        if extension.eq_ignore_ascii_case("flac") {
+            open_flac(input)
+        } else if extension.eq_ignore_ascii_case("sud") {
+            open_sud(input)
+        } else if is_raw_file(name) {
+            let format = raw_format.ok_or("raw_sample_rate (and the other raw_* settings) must be set to read headerless PCM files")?;
+            Ok(open_raw(input, len, format))
+        } else {
+            open_wav(input)
+        }
+    }
+
+    pub fn info(&self) -> &AudioInfo {
+        &self.info
+    }
+
+    /// The chunks of a WAV file before its `data` chunk, with LIST chunks split into their
+    /// sub-chunks as by `read_riff_chunks`; empty for other formats. Metadata after the audio
+    /// samples is not included, as the input is not read ahead.
+    pub fn header_chunks(&self) -> &[RiffChunk] {
+        &self.header_chunks
+    }
+
+    /// The time of the first sample, where the format records it (the chunk times of SUD
+    /// files).
+    pub fn start_time(&self) -> Option<DateTime<Utc>> {
+        match &self.decoder {
+            Decoder::Sud { reader, .. } => Some(reader.start_time()),
+            _ => None,
+        }
+    }
+
+    /// Sampling gaps found in the blocks read since the last call, where the format records
+    /// them (the chunk times of SUD files), at the index of the first sample after each gap.
+    pub fn take_gaps(&mut self) -> Vec<SampleGap> {
+        match &mut self.decoder {
+            Decoder::Sud { reader, .. } => reader.take_gaps(),
+            _ => Vec::new(),
+        }
     }
 
-    let samples: Result<Vec<f32>, _> = match spec.sample_format {
-        hound::SampleFormat::Int => match spec.bits_per_sample {
-            16 => {
-                reader.samples::<i16>()
-                    .map(|s| s.map(|v| (v as f32 / i16::MAX as f32).clamp(-1.0, 1.0)))
-                    .collect()
+    /// Reads the next block of at least `max_samples` samples where the format allows (whole
+    /// FLAC frames and SUD chunks are returned), fewer at the end of the file. Returns an empty block once the
+    /// file is exhausted.
+    pub fn read_block(&mut self, max_samples: usize) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+        let mut samples = Vec::with_capacity(max_samples);
+        match &mut self.decoder {
+            Decoder::Wav { data, format_tag, bytes_per_sample } => {
+                // A truncated data chunk (e.g. from a recorder that lost power) yields the complete
+                // samples that are present
+                let mut bytes = vec![0u8; max_samples * *bytes_per_sample];
+                let mut filled = 0;
+                while filled < bytes.len() {
+                    let n = data.read(&mut bytes[filled..])?;
+                    if n == 0 {
+                        break;
+                    }
+                    filled += n;
+                }
+                let whole = filled - filled % *bytes_per_sample;
+                decode_samples(&bytes[..whole], *format_tag, *bytes_per_sample, &mut samples);
             }
-            24 => {
-                let max_val = (1 << 23) - 1; // 8_388_607
-                reader.samples::<i32>()
-                    .map(|s| s.map(|v| (v as f32 / max_val as f32).clamp(-1.0, 1.0)))
-                    .collect()
+            Decoder::Flac { reader, shift, scale, buffer } => {
+                while samples.len() < max_samples {
+                    match reader.blocks().read_next_or_eof(std::mem::take(buffer))? {
+                        Some(block) => {
+                            samples.extend(block.channel(0).iter().map(|&v| ((v << *shift) as f32 / *scale).clamp(-1.0, 1.0)));
+                            *buffer = block.into_buffer();
+                        }
+                        None => break,
+                    }
+                }
             }
-            32 => {
-                reader.samples::<i32>()
-                    .map(|s| s.map(|v| (v as f32 / i32::MAX as f32).clamp(-1.0, 1.0)))
-                    .collect()
+            Decoder::Raw { data, format } => {
+                let bytes_per_sample = format.bits_per_sample as usize / 8;
+                let frame_len = bytes_per_sample * format.channels as usize;
+                let mut frames = vec![0u8; max_samples * frame_len];
+                let mut filled = 0;
+                while filled < frames.len() {
+                    let n = data.read(&mut frames[filled..])?;
+                    if n == 0 {
+                        break;
+                    }
+                    filled += n;
+                }
+                // Rewrite the analysed channel as little-endian signed (unsigned for 8-bit)
+                // samples, as stored in WAV files
+                let offset = format.channel as usize * bytes_per_sample;
+                let mut bytes = Vec::with_capacity(filled / frame_len * bytes_per_sample);
+                for frame in frames[..filled - filled % frame_len].chunks_exact(frame_len) {
+                    let start = bytes.len();
+                    bytes.extend_from_slice(&frame[offset..offset + bytes_per_sample]);
+                    let sample = &mut bytes[start..];
+                    if format.byte_order == ByteOrder::Big {
+                        sample.reverse();
+                    }
+                    let flip_sign = match format.sample_format {
+                        RawSampleFormat::Int => bytes_per_sample == 1,
+                        RawSampleFormat::Uint => bytes_per_sample > 1,
+                        RawSampleFormat::Float => false,
+                    };
+                    if flip_sign {
+                        sample[bytes_per_sample - 1] ^= 0x80;
+                    }
+                }
+                let format_tag = if format.sample_format == RawSampleFormat::Float { WAVE_FORMAT_IEEE_FLOAT } else { WAVE_FORMAT_PCM };
+                decode_samples(&bytes, format_tag, bytes_per_sample, &mut samples);
             }
-            _ => return Err(format!("Unsupported integer bit depth: {}", spec.bits_per_sample).into()),
-        },
-        hound::SampleFormat::Float => match spec.bits_per_sample {
-            32 => {
-                reader.samples::<f32>()
-                    .map(|s| s.map(|v| v.clamp(-1.0, 1.0))) // Clamp just to be safe
-                    .collect()
+            Decoder::Sud { reader, scale } => {
+                while samples.len() < max_samples {
+                    match reader.next_chunk()? {
+                        Some(chunk) => samples.extend(chunk.iter().map(|&v| (v as f32 / *scale).clamp(-1.0, 1.0))),
+                        None => break,
+                    }
+                }
             }
-            _ => return Err(format!("Unsupported float bit depth: {}", spec.bits_per_sample).into()),
-        },
+        }
+        Ok(samples)
+    }
+
+    /// Reads all remaining samples. The returned format gives the number of samples read.
+    pub fn read_to_end(mut self) -> Result<(Vec<f32>, AudioInfo), Box<dyn std::error::Error>> {
+        let mut samples = Vec::new();
+        loop {
+            let block = self.read_block(1 << 16)?;
+            if block.is_empty() {
+                break;
+            }
+            samples.extend(block);
+        }
+        self.info.num_samples = samples.len() as u64;
+        Ok((samples, self.info))
+    }
+}
+
+/// Integer samples are scaled by the largest positive value of their (container) bit depth,
+/// e.g. 2^23 - 1 for 24-bit.
+fn int_full_scale(bits: u16) -> f32 {
+    ((1i64 << (bits - 1)) - 1) as f32
+}
+
+fn int_normalisation(bits: u16, valid_bits: u16) -> String {
+    let mut description = format!("x / {} (signed {}-bit)", (1i64 << (bits - 1)) - 1, bits);
+    if valid_bits < bits {
+        description.push_str(&format!(", {} valid bits left-justified", valid_bits));
+    }
+    description
+}
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// The `fmt ` chunk fields needed to decode samples.
+struct WavFormat {
+    format_tag: u16, // PCM or IEEE float; extensible formats are resolved to their sub-format
+    channels: u16,
+    sample_rate: u32,
+    block_align: u16,
+    bits_per_sample: u16,
+    valid_bits: u16,
+}
+
+fn parse_fmt_chunk(data: &[u8]) -> Result<WavFormat, String> {
+    if data.len() < 16 {
+        return Err("Truncated fmt chunk".to_string());
+    }
+    let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+    let mut format = WavFormat {
+        format_tag: u16_at(0),
+        channels: u16_at(2),
+        sample_rate: u32::from_le_bytes(data[4..8].try_into().unwrap()),
+        block_align: u16_at(12),
+        bits_per_sample: u16_at(14),
+        valid_bits: u16_at(14),
+    };
+    if format.format_tag == WAVE_FORMAT_EXTENSIBLE {
+        // cbSize (2), valid bits (2), channel mask (4), then the sub-format GUID whose first two
+        // bytes are the format tag
+        if data.len() < 40 {
+            return Err("Truncated WAVE_FORMAT_EXTENSIBLE fmt chunk".to_string());
+        }
+        if u16_at(18) != 0 {
+            format.valid_bits = u16_at(18);
+        }
+        format.format_tag = u16_at(24);
+    }
+    Ok(format)
+}
+
+/// Opens a mono WAV file for reading, with samples normalized to [-1.0, 1.0]. Supports RIFF,
+/// RF64 and BW64 files with 8-bit (unsigned), 16-, 24- and 32-bit PCM and 32- and 64-bit float
+/// samples, including WAVE_FORMAT_EXTENSIBLE. Integer samples are scaled by the
+/// largest positive value of their bit depth; PCM with fewer valid bits than its container
+/// (e.g. 20 in 24) is left-justified, so it is scaled by the container size. Float samples are
+/// used as they are, clamped to [-1.0, 1.0].
+fn open_wav(input: Input) -> Result<AudioReader, Box<dyn std::error::Error>> {
+    let mut reader = BufReader::new(input);
+    let container = read_wave_header(&mut reader)?;
+
+    let mut format = None;
+    let mut ds64_data_size = None;
+    let mut header_chunks = Vec::new();
+    let mut chunk_header = [0u8; 8];
+    let data_size = loop {
+        if reader.read_exact(&mut chunk_header).is_err() {
+            return Err("No data chunk found".into());
+        }
+        let id: [u8; 4] = chunk_header[0..4].try_into()?;
+        let size = u32::from_le_bytes(chunk_header[4..8].try_into()?);
+        match &id {
+            b"data" => break if size == u32::MAX { ds64_data_size.unwrap_or(u64::MAX) } else { size as u64 },
+            _ if size as u64 <= MAX_METADATA_CHUNK_BYTES => {
+                let mut data = vec![0u8; size as usize];
+                reader.read_exact(&mut data)?;
+                if size & 1 == 1 {
+                    skip_bytes(&mut reader, 1)?;
+                }
+                match &id {
+                    b"fmt " => format = Some(parse_fmt_chunk(&data)?),
+                    b"ds64" => ds64_data_size = parse_ds64_data_size(&data),
+                    _ => {}
+                }
+                if &id == b"LIST" && data.len() >= 4 {
+                    header_chunks.extend(list_sub_chunks(&data[4..]));
+                } else {
+                    header_chunks.push(RiffChunk { id, data });
+                }
+            }
+            _ => skip_bytes(&mut reader, size as u64 + (size & 1) as u64)?,
+        }
     };
+    let format = format.ok_or("No fmt chunk before the data chunk")?;
 
-    let audio_data = samples?;
-    Ok((audio_data, spec.sample_rate))
+    if format.channels != 1 {
+        return Err(format!(
+            "Unsupported channel count: {}. Only mono files are currently supported.",
+            format.channels
+        ).into());
+    }
+    let bytes_per_sample = format.block_align as usize;
+    let (encoding, normalisation) = match (format.format_tag, bytes_per_sample) {
+        (WAVE_FORMAT_PCM, 1) => ("PCM", "(x - 128) / 127 (unsigned 8-bit)".to_string()),
+        (WAVE_FORMAT_PCM, 2..=4) => {
+            ("PCM", int_normalisation(bytes_per_sample as u16 * 8, format.valid_bits.min(bytes_per_sample as u16 * 8)))
+        }
+        (WAVE_FORMAT_IEEE_FLOAT, 4) => ("IEEE float", "x (32-bit float, clamped to [-1, 1])".to_string()),
+        (WAVE_FORMAT_IEEE_FLOAT, 8) => ("IEEE float", "x (64-bit float, clamped to [-1, 1])".to_string()),
+        (WAVE_FORMAT_PCM, _) => return Err(format!("Unsupported integer bit depth: {}", format.bits_per_sample).into()),
+        (WAVE_FORMAT_IEEE_FLOAT, _) => return Err(format!("Unsupported float bit depth: {}", format.bits_per_sample).into()),
+        (tag, _) => return Err(format!("Unsupported WAV format tag: 0x{:04X}", tag).into()),
+    };
+
+    let bits = bytes_per_sample as u16 * 8;
+    let info = AudioInfo {
+        container,
+        encoding,
+        bits_per_sample: bits,
+        valid_bits: format.valid_bits.min(bits),
+        sample_rate: format.sample_rate,
+        num_samples: if data_size == u64::MAX { 0 } else { data_size / bytes_per_sample as u64 },
+        normalisation,
+    };
+    let decoder = Decoder::Wav { data: reader.take(data_size), format_tag: format.format_tag, bytes_per_sample };
+    Ok(AudioReader { info, header_chunks, decoder })
+}
+
+/// Reads past `n` bytes of a stream that may not be seekable.
+fn skip_bytes<R: Read>(reader: &mut R, n: u64) -> Result<(), io::Error> {
+    if io::copy(&mut reader.take(n), &mut io::sink())? < n {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+/// Appends the samples in `bytes` (whole samples only) to `out`, normalized.
+fn decode_samples(bytes: &[u8], format_tag: u16, bytes_per_sample: usize, out: &mut Vec<f32>) {
+    let chunks = bytes.chunks_exact(bytes_per_sample);
+    match (format_tag, bytes_per_sample) {
+        (WAVE_FORMAT_PCM, 1) => out.extend(chunks.map(|b| ((b[0] as f32 - 128.0) / 127.0).clamp(-1.0, 1.0))),
+        (WAVE_FORMAT_PCM, _) => {
+            let bits = bytes_per_sample as u32 * 8;
+            let scale = int_full_scale(bits as u16);
+            out.extend(chunks.map(|b| {
+                // Place the little-endian sample in the top bytes of an i32 to sign-extend it
+                let mut word = [0u8; 4];
+                word[4 - bytes_per_sample..].copy_from_slice(b);
+                let value = i32::from_le_bytes(word) >> (32 - bits);
+                (value as f32 / scale).clamp(-1.0, 1.0)
+            }))
+        }
+        (_, 4) => out.extend(chunks.map(|b| f32::from_le_bytes(b.try_into().unwrap()).clamp(-1.0, 1.0))),
+        _ => out.extend(chunks.map(|b| (f64::from_le_bytes(b.try_into().unwrap()) as f32).clamp(-1.0, 1.0))),
+    }
+}
+
+/// Checks the RIFF/RF64/BW64 header of a WAVE file and returns the container type.
+fn read_wave_header<R: Read>(reader: &mut R) -> Result<&'static str, Box<dyn std::error::Error>> {
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header)?;
+    let container = match &header[0..4] {
+        b"RIFF" => "RIFF",
+        b"RF64" => "RF64",
+        b"BW64" => "BW64",
+        _ => return Err("Not a RIFF/WAVE file".into()),
+    };
+    if &header[8..12] != b"WAVE" {
+        return Err("Not a RIFF/WAVE file".into());
+    }
+    Ok(container)
+}
+
+/// The 64-bit data chunk size from an RF64/BW64 `ds64` chunk (RIFF size, data size, sample
+/// count, ...), used when the data chunk's own size field is 0xFFFFFFFF.
+fn parse_ds64_data_size(data: &[u8]) -> Option<u64> {
+    Some(u64::from_le_bytes(data.get(8..16)?.try_into().ok()?))
+}
+
+/// Opens a mono FLAC file for reading. Integer samples are scaled as in a WAV file with the
+/// same valid bits: left-justified in a whole number of bytes and divided by the largest
+/// positive value of that container (e.g. 20-bit samples are shifted up 4 bits and divided by
+/// 2^23 - 1), so calibration is the same across formats.
+fn open_flac(input: Input) -> Result<AudioReader, Box<dyn std::error::Error>> {
+    let reader = FlacReader::new(input)?;
+    let info = reader.streaminfo();
+
+    if info.channels != 1 {
+        return Err(format!(
+            "Unsupported channel count: {}. Only mono files are currently supported.",
+            info.channels
+        ).into());
+    }
+    if !(4..=32).contains(&info.bits_per_sample) {
+        return Err(format!("Unsupported FLAC bit depth: {}", info.bits_per_sample).into());
+    }
+
+    let valid_bits = info.bits_per_sample as u16;
+    let bits = valid_bits.div_ceil(8) * 8;
+    let audio_info = AudioInfo {
+        container: "FLAC",
+        encoding: "PCM",
+        bits_per_sample: bits,
+        valid_bits,
+        sample_rate: info.sample_rate,
+        num_samples: info.samples.unwrap_or(0),
+        normalisation: int_normalisation(bits, valid_bits),
+    };
+    let decoder = Decoder::Flac { reader, shift: (bits - valid_bits) as u32, scale: int_full_scale(bits), buffer: Vec::new() };
+    Ok(AudioReader { info: audio_info, header_chunks: Vec::new(), decoder })
+}
+
+/// Reads a SoundTrap SUD file, decoding its X3 audio chunks. Samples are scaled as for WAV
+/// files of the same bit depth.
+fn open_sud(input: Input) -> Result<AudioReader, Box<dyn std::error::Error>> {
+    let reader = SudReader::new(input)?;
+    let bits = reader.config().nbits;
+    let info = AudioInfo {
+        container: "SUD",
+        encoding: "X3",
+        bits_per_sample: bits,
+        valid_bits: bits,
+        sample_rate: reader.config().sample_rate,
+        num_samples: 0, // Not recorded in the file header
+        normalisation: int_normalisation(bits, bits),
+    };
+    let decoder = Decoder::Sud { reader, scale: int_full_scale(bits) };
+    Ok(AudioReader { info, header_chunks: Vec::new(), decoder })
+}
+
+/// Opens a headerless PCM file in the declared format. Samples are scaled as in WAV files:
+/// integers by the largest positive value of their bit depth (after removing the offset of
+/// unsigned samples), floats used as they are.
+fn open_raw<'a>(input: Input<'a>, len: u64, format: &RawFormat) -> AudioReader<'a> {
+    let frame_len = format.bits_per_sample as u64 / 8 * format.channels as u64;
+    let bits = format.bits_per_sample;
+    let (encoding, mut normalisation) = match format.sample_format {
+        RawSampleFormat::Int if bits == 8 => ("PCM", "x / 127 (signed 8-bit)".to_string()),
+        RawSampleFormat::Int => ("PCM", int_normalisation(bits, bits)),
+        RawSampleFormat::Uint => {
+            let half = 1i64 << (bits - 1);
+            ("PCM", format!("(x - {}) / {} (unsigned {}-bit)", half, half - 1, bits))
+        }
+        RawSampleFormat::Float => ("IEEE float", format!("x ({}-bit float, clamped to [-1, 1])", bits)),
+    };
+    if bits > 8 {
+        normalisation.push_str(if format.byte_order == ByteOrder::Big { ", big-endian" } else { ", little-endian" });
+    }
+    if format.channels > 1 {
+        normalisation.push_str(&format!(", channel {} of {}", format.channel + 1, format.channels));
+    }
+    let info = AudioInfo {
+        container: "raw",
+        encoding,
+        bits_per_sample: bits,
+        valid_bits: bits,
+        sample_rate: format.sample_rate,
+        num_samples: len / frame_len,
+        normalisation,
+    };
+    let decoder = Decoder::Raw { data: BufReader::new(input), format: format.clone() };
+    AudioReader { info, header_chunks: Vec::new(), decoder }
+}
+
+/// Writes the format and normalisation of each input file, for reproducing calibration.
+pub fn write_input_report(path: &Path, inputs: &[(String, AudioInfo)]) -> Result<(), Box<dyn std::error::Error>> {
+    let file = fs::File::create(path)?;
+    let mut wtr = csv::Writer::from_writer(file);
+    wtr.write_record(["file", "container", "encoding", "bits_per_sample", "valid_bits", "sample_rate", "samples", "normalisation"])?;
+    for (name, info) in inputs {
+        wtr.write_record([
+            name.clone(),
+            info.container.to_string(),
+            info.encoding.to_string(),
+            info.bits_per_sample.to_string(),
+            info.valid_bits.to_string(),
+            info.sample_rate.to_string(),
+            info.num_samples.to_string(),
+            info.normalisation.clone(),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+const MAX_METADATA_CHUNK_BYTES: u64 = 16 * 1024 * 1024;
+
+/// A chunk of a RIFF/WAVE file: its four-character id and contents.
+#[derive(Debug, Clone)]
+pub struct RiffChunk {
+    pub id: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+/// Lists the chunks of a RIFF (or RF64/BW64) WAVE file, skipping over the audio samples in the
+/// `data` chunk (which is returned empty). The sub-chunks of LIST chunks are returned in place
+/// of the LIST.
+pub fn read_riff_chunks<R: Read + Seek>(reader: &mut R) -> Result<Vec<RiffChunk>, Box<dyn std::error::Error>> {
+    read_wave_header(reader)?;
+
+    let mut chunks = Vec::new();
+    let mut ds64_data_size = None;
+    let mut chunk_header = [0u8; 8];
+    while reader.read_exact(&mut chunk_header).is_ok() {
+        let id: [u8; 4] = chunk_header[0..4].try_into()?;
+        let mut size = u32::from_le_bytes(chunk_header[4..8].try_into()?) as u64;
+        if &id == b"data" && size == u32::MAX as u64 {
+            size = ds64_data_size.unwrap_or(size);
+        }
+        let padded_size = size + (size & 1); // Chunks are word aligned
+
+        // Audio samples (and implausibly large chunks) are skipped rather than read
+        if &id == b"data" || size > MAX_METADATA_CHUNK_BYTES {
+            reader.seek(SeekFrom::Current(padded_size as i64))?;
+            chunks.push(RiffChunk { id, data: Vec::new() });
+            continue;
+        }
+
+        let mut data = vec![0u8; size as usize];
+        if reader.read_exact(&mut data).is_err() {
+            break; // Truncated trailing chunk
+        }
+        if padded_size > size {
+            reader.seek(SeekFrom::Current(1))?;
+        }
+
+        if &id == b"ds64" {
+            ds64_data_size = parse_ds64_data_size(&data);
+        }
+        if &id == b"LIST" && data.len() >= 4 {
+            chunks.extend(list_sub_chunks(&data[4..]));
+        } else {
+            chunks.push(RiffChunk { id, data });
+        }
+    }
+    Ok(chunks)
+}
+
+/// Splits the body of a LIST chunk (after its list type) into its sub-chunks.
+fn list_sub_chunks(mut body: &[u8]) -> Vec<RiffChunk> {
+    let mut chunks = Vec::new();
+    while body.len() >= 8 {
+        let id: [u8; 4] = body[0..4].try_into().unwrap();
+        let size = u32::from_le_bytes(body[4..8].try_into().unwrap()) as usize;
+        let end = (8 + size).min(body.len());
+        chunks.push(RiffChunk { id, data: body[8..end].to_vec() });
+        body = &body[(end + (size & 1)).min(body.len())..];
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_audio_file() {
+        assert!(is_audio_file(Path::new("/data/20240717_164721.WAV")));
+        assert!(is_audio_file(Path::new("5678.240717164721.flac")));
+        assert!(is_audio_file(Path::new("archive/site.FLAC")));
+        assert!(!is_audio_file(Path::new("5678.240717164721.sud")));
+        assert!(is_sud_file(Path::new("5678.240717164721.SUD")));
+        assert!(!is_audio_file(Path::new("5678.240717164721.log.xml")));
+        assert!(!is_audio_file(Path::new("README")));
+        assert!(is_raw_file(Path::new("logger/20240717_164721.BIN")));
+        assert!(!is_raw_file(Path::new("20240717_164721.wav")));
+    }
+
+    /// Builds a mono WAV file in memory with the given fmt chunk body and sample bytes.
+    fn wav_bytes(container: &[u8; 4], fmt: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut file = Vec::new();
+        file.extend_from_slice(container);
+        file.extend_from_slice(&u32::MAX.to_le_bytes());
+        file.extend_from_slice(b"WAVE");
+        if container == b"RF64" {
+            file.extend_from_slice(b"ds64");
+            file.extend_from_slice(&28u32.to_le_bytes());
+            file.extend_from_slice(&0u64.to_le_bytes());
+            file.extend_from_slice(&(data.len() as u64).to_le_bytes());
+            file.extend_from_slice(&[0u8; 12]);
+        }
+        file.extend_from_slice(b"fmt ");
+        file.extend_from_slice(&(fmt.len() as u32).to_le_bytes());
+        file.extend_from_slice(fmt);
+        file.extend_from_slice(b"data");
+        let size = if container == b"RF64" { u32::MAX } else { data.len() as u32 };
+        file.extend_from_slice(&size.to_le_bytes());
+        file.extend_from_slice(data);
+        file
+    }
+
+    fn fmt_chunk(format_tag: u16, bits: u16, extensible_sub_format: Option<(u16, u16)>) -> Vec<u8> {
+        let block_align = bits / 8;
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&format_tag.to_le_bytes());
+        fmt.extend_from_slice(&1u16.to_le_bytes());
+        fmt.extend_from_slice(&8000u32.to_le_bytes());
+        fmt.extend_from_slice(&(8000 * block_align as u32).to_le_bytes());
+        fmt.extend_from_slice(&block_align.to_le_bytes());
+        fmt.extend_from_slice(&bits.to_le_bytes());
+        if let Some((sub_format, valid_bits)) = extensible_sub_format {
+            fmt.extend_from_slice(&22u16.to_le_bytes());
+            fmt.extend_from_slice(&valid_bits.to_le_bytes());
+            fmt.extend_from_slice(&4u32.to_le_bytes());
+            fmt.extend_from_slice(&sub_format.to_le_bytes());
+            fmt.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71]);
+        }
+        fmt
+    }
+
+    fn read(file: Vec<u8>) -> (Vec<f32>, AudioInfo) {
+        let path = std::env::temp_dir().join(format!("pamguide_audio_io_test_{}_{}.wav", std::process::id(), file.len()));
+        fs::write(&path, file).unwrap();
+        let result = read_audio_file(&path, None);
+        fs::remove_file(&path).unwrap();
+        result.unwrap()
+    }
+
+    #[test]
+    fn test_read_wav_formats() {
+        // Unsigned 8-bit
+        let (samples, info) = read(wav_bytes(b"RIFF", &fmt_chunk(1, 8, None), &[128, 255, 1, 0]));
+        assert_eq!(samples, vec![0.0, 1.0, -1.0, -1.0]);
+        assert_eq!(info.normalisation, "(x - 128) / 127 (unsigned 8-bit)");
+
+        // 64-bit float in RF64
+        let data: Vec<u8> = [0.25f64, -0.5, 2.0].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let (samples, info) = read(wav_bytes(b"RF64", &fmt_chunk(3, 64, None), &data));
+        assert_eq!(samples, vec![0.25, -0.5, 1.0]);
+        assert_eq!((info.container, info.encoding, info.bits_per_sample), ("RF64", "IEEE float", 64));
+
+        // Extensible 24-bit container with 20 valid bits, scaled by the container size
+        let data = [0x00, 0x00, 0x80, 0xFF, 0xFF, 0x7F, 0x10, 0x00, 0x00];
+        let (samples, info) = read(wav_bytes(b"RIFF", &fmt_chunk(WAVE_FORMAT_EXTENSIBLE, 24, Some((1, 20))), &data));
+        assert_eq!(samples, vec![-1.0, 1.0, 16.0 / 8_388_607.0]);
+        assert_eq!(info.valid_bits, 20);
+        assert_eq!(info.normalisation, "x / 8388607 (signed 24-bit), 20 valid bits left-justified");
+    }
+
+    #[test]
+    fn test_read_raw_formats() {
+        let read_raw = |format: &RawFormat, data: &[u8]| {
+            let path = std::env::temp_dir().join(format!("pamguide_audio_io_test_{}_{}.raw", std::process::id(), data.len()));
+            fs::write(&path, data).unwrap();
+            let result = read_audio_file(&path, Some(format));
+            fs::remove_file(&path).unwrap();
+            result.unwrap()
+        };
+        let mut format = RawFormat {
+            sample_format: RawSampleFormat::Int,
+            bits_per_sample: 16,
+            byte_order: ByteOrder::Big,
+            channels: 2,
+            channel: 1,
+            sample_rate: 96000,
+        };
+
+        // Second channel of big-endian 16-bit stereo; a trailing partial frame is ignored
+        let (samples, info) = read_raw(&format, &[0x7F, 0xFF, 0x80, 0x01, 0x00, 0x00, 0x7F, 0xFF, 0x00]);
+        assert_eq!(samples, vec![-1.0, 1.0]);
+        assert_eq!((info.container, info.sample_rate, info.num_samples), ("raw", 96000, 2));
+        assert_eq!(info.normalisation, "x / 32767 (signed 16-bit), big-endian, channel 2 of 2");
+
+        // Unsigned 24-bit little-endian mono
+        format = RawFormat { sample_format: RawSampleFormat::Uint, bits_per_sample: 24, byte_order: ByteOrder::Little, channels: 1, channel: 0, ..format };
+        let (samples, info) = read_raw(&format, &[0x00, 0x00, 0x80, 0xFF, 0xFF, 0xFF, 0x01, 0x00, 0x00]);
+        assert_eq!(samples, vec![0.0, 1.0, -1.0]);
+        assert_eq!(info.normalisation, "(x - 8388608) / 8388607 (unsigned 24-bit), little-endian");
+
+        // Signed 8-bit
+        format = RawFormat { sample_format: RawSampleFormat::Int, bits_per_sample: 8, ..format };
+        let (samples, _) = read_raw(&format, &[0x00, 0x7F, 0x81]);
+        assert_eq!(samples, vec![0.0, 1.0, -1.0]);
+    }
+
+    /// Builds a mono 8 kHz FLAC file in memory holding one frame (of at least 16 samples) with
+    /// a verbatim subframe (the samples stored as they are), for bit depths the reference
+    /// fixtures lack.
+    fn verbatim_flac_bytes(bits: u32, samples: &[i32]) -> Vec<u8> {
+        fn crc(data: &[u8], poly: u16, width: u32) -> u16 {
+            let top = 1u32 << (width - 1);
+            let mask = ((1u32 << width) - 1) as u16;
+            data.iter().fold(0u16, |crc, &byte| {
+                (0..8).fold(crc ^ ((byte as u16) << (width - 8)), |crc, _| {
+                    if crc as u32 & top != 0 { ((crc << 1) ^ poly) & mask } else { (crc << 1) & mask }
+                })
+            })
+        }
+        // Writes bits MSB first
+        fn put(bytes: &mut Vec<u8>, n_bits: &mut usize, value: u64, width: u32) {
+            for i in (0..width).rev() {
+                if (*n_bits).is_multiple_of(8) {
+                    bytes.push(0);
+                }
+                *bytes.last_mut().unwrap() |= (((value >> i) & 1) as u8) << (7 - *n_bits % 8);
+                *n_bits += 1;
+            }
+        }
+        let n = samples.len() as u64;
+        let mut file = b"fLaC".to_vec();
+        let mut n_bits = file.len() * 8;
+        put(&mut file, &mut n_bits, 0x80, 8); // Last metadata block, STREAMINFO
+        put(&mut file, &mut n_bits, 34, 24);
+        put(&mut file, &mut n_bits, n, 16); // Block size range
+        put(&mut file, &mut n_bits, n, 16);
+        put(&mut file, &mut n_bits, 0, 48); // Frame size range unknown
+        put(&mut file, &mut n_bits, 8000, 20);
+        put(&mut file, &mut n_bits, 0, 3); // One channel
+        put(&mut file, &mut n_bits, bits as u64 - 1, 5);
+        put(&mut file, &mut n_bits, n, 36);
+        file.extend_from_slice(&[0; 16]); // MD5 unknown
+
+        let mut frame = Vec::new();
+        let mut n_bits = 0;
+        put(&mut frame, &mut n_bits, 0b1111_1111_1111_1000, 16); // Sync code, fixed block size
+        put(&mut frame, &mut n_bits, 0b0110_0000, 8); // 8-bit block size at the end, STREAMINFO rate
+        let size_code = match bits { 8 => 1, 12 => 2, 16 => 4, 20 => 5, 24 => 6, _ => unimplemented!() };
+        put(&mut frame, &mut n_bits, 0, 4); // Mono
+        put(&mut frame, &mut n_bits, size_code << 1, 4);
+        put(&mut frame, &mut n_bits, 0, 8); // Frame 0
+        put(&mut frame, &mut n_bits, n - 1, 8);
+        let crc8 = crc(&frame, 0x07, 8);
+        put(&mut frame, &mut n_bits, crc8 as u64, 8);
+        put(&mut frame, &mut n_bits, 0b0000_0010, 8); // Verbatim subframe
+        for &sample in samples {
+            put(&mut frame, &mut n_bits, sample as u64 & ((1 << bits) - 1), bits);
+        }
+        let crc16 = crc(&frame, 0x8005, 16);
+        frame.extend_from_slice(&crc16.to_be_bytes());
+        file.extend(frame);
+        file
+    }
+
+    #[test]
+    fn test_read_flac_matches_wav() {
+        // The fixtures (written by tests/fixtures/make_flac_fixtures.py) start with the
+        // full-scale extremes, zero and +/-1, followed by a 440 Hz tone
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let (flac, flac_info) = read_audio_file(&fixtures.join("tone_24bit.flac"), None).unwrap();
+        let (wav, _) = read_audio_file(&fixtures.join("tone_24bit.wav"), None).unwrap();
+        assert_eq!((flac_info.container, flac_info.bits_per_sample, flac_info.num_samples), ("FLAC", 24, 1000));
+        assert_eq!(flac, wav);
+        assert_eq!(flac[..5], [1.0, -1.0, 0.0, 1.0 / 8_388_607.0, -1.0 / 8_388_607.0]);
+
+        // Encoded by the reference encoder (libFLAC 1.3.2); the samples are checked against the
+        // MD5 of the original audio that it stores in STREAMINFO
+        let path = fixtures.join("reference_16bit.flac");
+        let (flac, flac_info) = read_audio_file(&path, None).unwrap();
+        assert_eq!((flac_info.bits_per_sample, flac_info.valid_bits, flac_info.num_samples), (16, 16, 4410));
+        let pcm: Vec<u8> = flac.iter().flat_map(|&x| ((x * 32_767.0).round() as i16).to_le_bytes()).collect();
+        let streaminfo_md5 = FlacReader::open(&path).unwrap().streaminfo().md5sum;
+        assert_eq!(<md5::Md5 as md5::Digest>::digest(&pcm)[..], streaminfo_md5);
+
+        // 20-bit samples are left-justified in 24 bits, like a WAV file with 20 valid bits
+        let mut samples = vec![(1 << 19) - 1, -((1 << 19) - 1), 0, 1, -1];
+        samples.resize(16, 0);
+        let file = verbatim_flac_bytes(20, &samples);
+        let path = std::env::temp_dir().join(format!("pamguide_audio_io_test_{}.flac", std::process::id()));
+        fs::write(&path, file).unwrap();
+        let result = read_audio_file(&path, None);
+        fs::remove_file(&path).unwrap();
+        let (flac, flac_info) = result.unwrap();
+        assert_eq!((flac_info.bits_per_sample, flac_info.valid_bits), (24, 20));
+        assert_eq!(flac_info.normalisation, "x / 8388607 (signed 24-bit), 20 valid bits left-justified");
+        assert_eq!(flac[..5], [(((1 << 19) - 1) << 4) as f32 / 8_388_607.0, -(((1 << 19) - 1) << 4) as f32 / 8_388_607.0, 0.0, 16.0 / 8_388_607.0, -16.0 / 8_388_607.0]);
+    }
 }
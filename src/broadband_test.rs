@@ -1,10 +1,9 @@
-use crate::config::{AnalysisConfig, AnalysisType, Environment, WindowType, WindowUnit, load_config};
-use crate::audio_io;
+use crate::config::{Environment, WindowUnit, load_config};
+use crate::analysis;
 use crate::dsp;
 use crate::utils;
 
 use ndarray::{Array1, s};
-use rustfft::num_complex::Complex;
 use std::path::Path;
 
 pub fn run_broadband_test(wav_file_path: &str, config_path: &str) -> Result<(), Box<dyn std::error::Error>> {
@@ -20,7 +19,11 @@ pub fn run_broadband_test(wav_file_path: &str, config_path: &str) -> Result<(),
     println!("  Environment: {:?}", config.environment);
     println!("  Calibration: {}", if config.calibrated { "Enabled" } else { "Disabled" });
     if config.calibrated {
-        println!("  Calibration type: {:?}", config.calibration_type.as_ref().unwrap());
+        match (&config.sensitivity_curve_path, &config.calibration_type) {
+            (Some(curve_path), _) => println!("  Sensitivity curve: {}", curve_path),
+            (None, Some(cal_type)) => println!("  Calibration type: {:?}", cal_type),
+            (None, None) => {}
+        }
     }
     println!("  Window type: {:?}", config.window_type);
     println!("  Window length: {} {:?}", config.window_length, config.window_unit);
@@ -30,8 +33,7 @@ pub fn run_broadband_test(wav_file_path: &str, config_path: &str) -> Result<(),
 
     // Read WAV file
     let wav_file_path = Path::new(wav_file_path);
-    let (audio_data, fs_hz) = audio_io::read_wav_file(wav_file_path)?;
-    let fs = fs_hz as f64;
+    let (audio_data, fs) = analysis::load_audio(wav_file_path, &config)?;
     println!("WAV file read: {}", wav_file_path.display());
     println!("  Sample rate: {} Hz", fs);
     println!("  Number of samples: {}", audio_data.len());
@@ -53,9 +55,9 @@ pub fn run_broadband_test(wav_file_path: &str, config_path: &str) -> Result<(),
     println!();
 
     // Calculate system sensitivity
-    let sensitivity_db = utils::calculate_system_sensitivity_db(&config)?;
+    let calibration = utils::Calibration::from_config(&config)?;
     println!("SYSTEM SENSITIVITY:");
-    println!("  S = {:.2} dB", sensitivity_db);
+    println!("  S = {}", calibration.describe());
     println!();
 
     // Determine window parameters
@@ -80,30 +82,38 @@ pub fn run_broadband_test(wav_file_path: &str, config_path: &str) -> Result<(),
     println!("  Number of segments: {}", num_segments);
     println!();
 
+    let n_fft = dsp::fft_length(n_window_samples, config.nfft, config.nfft_power_of_two)?;
+    let padding_factor = n_fft as f64 / n_window_samples as f64;
+    println!("  FFT length: {} points (padding factor {:.3})", n_fft, padding_factor);
+    println!();
+
     // Generate window function
-    let (scaled_window, alpha) = dsp::generate_scaled_window(&config.window_type, n_window_samples);
-    let noise_bw = dsp::noise_power_bandwidth(scaled_window.view(), n_window_samples);
+    let (scaled_window, window_props) = dsp::generate_scaled_window(&config.window_type, &config.window_parameters, n_window_samples);
+    // In bins of the zero-padded FFT, as in the main analysis
+    let noise_bw = dsp::noise_power_bandwidth(scaled_window.view(), n_window_samples) * padding_factor;
     
     println!("WINDOW FUNCTION:");
     println!("  Window type: {:?}", config.window_type);
-    println!("  Alpha (scaling factor): {:.4}", alpha);
+    println!("  Alpha (coherent gain): {:.4}", window_props.coherent_gain);
+    println!("  ENBW: {:.4} bins", window_props.enbw);
     println!("  Noise power bandwidth (B): {:.6}", noise_bw);
     
     // Print first few window values
     println!("  First 5 window values:");
-    for i in 0..5.min(scaled_window.len()) {
-        println!("    w[{}] = {:.6}", i, scaled_window[i]);
+    for (i, w) in scaled_window.iter().take(5).enumerate() {
+        println!("    w[{}] = {:.6}", i, w);
     }
     println!();
 
     // Calculate frequency parameters
-    let delf = fs / n_window_samples as f64;
-    let fft_freqs: Array1<f64> = Array1::linspace(0.0, fs / 2.0, n_window_samples / 2 + 1);
+    let delf = fs / n_fft as f64;
+    let fft_freqs: Array1<f64> = (0..=n_fft / 2).map(|k| k as f64 * delf).collect();
     let pss_freqs = fft_freqs.slice(s![1..]);
     
     let pss_flow_idx = pss_freqs.iter().position(|&f| f >= config.low_cutoff).unwrap_or(0);
     let pss_fhigh_idx = pss_freqs.iter().rposition(|&f| f <= config.high_cutoff).unwrap_or(pss_freqs.len() - 1);
     let selected_freqs = pss_freqs.slice(s![pss_flow_idx..=pss_fhigh_idx]);
+    let bin_gains = calibration.bin_gains(&selected_freqs.to_vec());
     
     println!("FREQUENCY PARAMETERS:");
     println!("  Frequency bin width (delf): {:.4} Hz", delf);
@@ -126,11 +136,15 @@ pub fn run_broadband_test(wav_file_path: &str, config_path: &str) -> Result<(),
     println!("PROCESSING FIRST SEGMENT AS EXAMPLE:");
     
     // Extract first segment
-    let segment = &audio_data[0..n_window_samples];
+    let mut segment = audio_data[0..n_window_samples].to_vec();
     println!("  Segment length: {} samples", segment.len());
+
+    // Remove DC offset / trend
+    dsp::detrend_segment(&mut segment, &config.detrend);
+    println!("  Detrend: {:?}", config.detrend);
     
     // Apply window
-    let mut windowed_segment = segment.to_vec();
+    let mut windowed_segment = segment;
     for (sample, &win_val) in windowed_segment.iter_mut().zip(scaled_window.iter()) {
         *sample *= win_val;
     }
@@ -149,39 +163,41 @@ pub fn run_broadband_test(wav_file_path: &str, config_path: &str) -> Result<(),
     println!("    RMS: {}", win_rms);
     
     // Calculate FFT
-    let fft_result = dsp::calculate_fft(&windowed_segment);
+    let fft_result = dsp::calculate_fft(&windowed_segment, n_fft);
     
     println!("  FFT result (first 5 values):");
-    for i in 0..5.min(fft_result.len()) {
+    for (i, x) in fft_result.iter().take(5).enumerate() {
         println!("    X[{}] = {:.6} + {:.6}i (|X| = {:.6})", 
-                 i, fft_result[i].re, fft_result[i].im, fft_result[i].norm());
+                 i, x.re, x.im, x.norm());
     }
     
     // Calculate power spectrum
-    let power_spectrum: Vec<f64> = fft_result[1..=n_window_samples / 2]
+    let power_spectrum: Vec<f64> = fft_result[1..=n_fft / 2]
         .iter()
         .map(|c| (c.norm_sqr() / (n_window_samples as f32).powi(2)) as f64 * 2.0)
         .collect();
     
     println!("  Power spectrum (first 5 values):");
-    for i in 0..5.min(power_spectrum.len()) {
-        println!("    P[{}] = {:.6e}", i, power_spectrum[i]);
+    for (i, p) in power_spectrum.iter().take(5).enumerate() {
+        println!("    P[{}] = {:.6e}", i, p);
     }
     
     // Select frequency range
     let selected_power = &power_spectrum[pss_flow_idx..=pss_fhigh_idx];
     
     println!("  Selected power spectrum (first 5 values):");
-    for i in 0..5.min(selected_power.len()) {
-        println!("    P_sel[{}] = {:.6e}", i, selected_power[i]);
+    for (i, p) in selected_power.iter().take(5).enumerate() {
+        println!("    P_sel[{}] = {:.6e}", i, p);
     }
     
     // Calculate sum of power
-    let sum_power: f64 = selected_power.iter().sum();
+    let sum_power: f64 = selected_power.iter().sum::<f64>() / padding_factor;
     println!("  Sum of power: {:.6e}", sum_power);
     
-    // Convert to dB
-    let db_without_constant = utils::power_to_db(sum_power, pref) - sensitivity_db;
+    // Apply calibration per bin and convert to dB
+    let calibrated_power = calibrated_band_power(selected_power, &bin_gains, padding_factor);
+    println!("  Calibrated sum of power: {:.6e}", calibrated_power);
+    let db_without_constant = utils::power_to_db(calibrated_power, pref);
     
     println!("  Broadband level:");
     println!("    Without constant: {:.2} dB", db_without_constant);
@@ -195,23 +211,22 @@ pub fn run_broadband_test(wav_file_path: &str, config_path: &str) -> Result<(),
     for i in 0..num_segments {
         let start = i * n_step;
         let end = start + n_window_samples;
-        let segment = &audio_data[start..end];
+        let mut windowed_segment = audio_data[start..end].to_vec();
+        dsp::detrend_segment(&mut windowed_segment, &config.detrend);
         
-        let mut windowed_segment = segment.to_vec();
         for (sample, &win_val) in windowed_segment.iter_mut().zip(scaled_window.iter()) {
             *sample *= win_val;
         }
         
-        let fft_result = dsp::calculate_fft(&windowed_segment);
+        let fft_result = dsp::calculate_fft(&windowed_segment, n_fft);
         
-        let power_spectrum: Vec<f64> = fft_result[1..=n_window_samples / 2]
+        let power_spectrum: Vec<f64> = fft_result[1..=n_fft / 2]
             .iter()
             .map(|c| (c.norm_sqr() / (n_window_samples as f32).powi(2)) as f64 * 2.0)
             .collect();
         
         let selected_power = &power_spectrum[pss_flow_idx..=pss_fhigh_idx];
-        let sum_power: f64 = selected_power.iter().sum();
-        all_segment_powers.push(sum_power);
+        all_segment_powers.push(calibrated_band_power(selected_power, &bin_gains, padding_factor));
     }
     
     // Calculate statistics of all segment powers
@@ -219,14 +234,14 @@ pub fn run_broadband_test(wav_file_path: &str, config_path: &str) -> Result<(),
     let max_power = all_segment_powers.iter().fold(f64::NEG_INFINITY, |a, &b| a.max(b));
     let mean_power = all_segment_powers.iter().sum::<f64>() / all_segment_powers.len() as f64;
     
-    println!("  All segment powers statistics:");
+    println!("  All segment powers statistics (calibrated):");
     println!("    Min: {:.6e}", min_power);
     println!("    Max: {:.6e}", max_power);
     println!("    Mean: {:.6e}", mean_power);
     
     // Convert mean to dB
-    let mean_db_with_constant = utils::power_to_db(mean_power, pref) - sensitivity_db - 58.77;
-    let mean_db_without_constant = utils::power_to_db(mean_power, pref) - sensitivity_db;
+    let mean_db_with_constant = utils::power_to_db(mean_power, pref) - 58.77;
+    let mean_db_without_constant = utils::power_to_db(mean_power, pref);
     
     println!("  Mean broadband level:");
     println!("    Without constant: {:.2} dB", mean_db_without_constant);
@@ -237,6 +252,11 @@ pub fn run_broadband_test(wav_file_path: &str, config_path: &str) -> Result<(),
     Ok(())
 }
 
+/// Sums the power in the selected bins after applying the per-bin calibration gains.
+fn calibrated_band_power(selected_power: &[f64], bin_gains: &[f64], padding_factor: f64) -> f64 {
+    selected_power.iter().zip(bin_gains).map(|(p, g)| p * g).sum::<f64>() / padding_factor
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,154 @@
+use crate::config::{AnalysisConfig, WindowParameters, WindowType, load_config};
+use crate::audio_io;
+use crate::dsp;
+use crate::utils;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Length of the flat-top analysis segments in seconds
+const SEGMENT_SECONDS: f64 = 1.0;
+/// Search range around the expected tone frequency, as a fraction of that frequency
+const TONE_SEARCH_FRACTION: f64 = 0.05;
+
+/// Measured level of a calibrator tone.
+#[derive(Debug, Clone, Copy)]
+pub struct ToneMeasurement {
+    pub frequency: f64,
+    /// Uncalibrated tone level in dB (re full scale, with the environment's reference pressure)
+    pub level_db: f64,
+}
+
+/// Derives the end-to-end system sensitivity from a calibrator tone recording, or with
+/// `check_only` compares the configured calibration against the tone and reports the deviation.
+pub fn run_calibration(
+    tone_wav_path: &Path,
+    config_path: &Path,
+    reference_spl: f64,
+    tone_frequency: Option<f64>,
+    check_only: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = load_config(config_path)?;
+    println!("=== CALIBRATION FROM TONE ===");
+    println!("Tone recording: {}", tone_wav_path.display());
+    println!("  Reference level: {:.2} dB", reference_spl);
+
+    let (audio_data, audio_info) = audio_io::read_audio_file(tone_wav_path, audio_io::RawFormat::from_config(&config)?.as_ref())?;
+    let fs_hz = audio_info.sample_rate;
+    let pref = utils::reference_pressure(&config.environment);
+    let tone = measure_tone(&audio_data, fs_hz as f64, tone_frequency, pref)?;
+    println!("  Tone found at {:.2} Hz", tone.frequency);
+    println!("  Uncalibrated tone level: {:.2} dB", tone.level_db);
+
+    if check_only {
+        if !config.calibrated {
+            return Err("Checking a calibration requires calibrated = true in the configuration".into());
+        }
+        let calibration = utils::Calibration::from_config(&config)?;
+        let sensitivity_db = calibration.sensitivity_db_at(tone.frequency);
+        let measured_spl = tone.level_db - sensitivity_db;
+        println!("  Configured sensitivity at {:.2} Hz: {:.2} dB", tone.frequency, sensitivity_db);
+        println!("  Calibrated tone level: {:.2} dB", measured_spl);
+        println!("  Deviation from reference: {:+.2} dB", measured_spl - reference_spl);
+        return Ok(());
+    }
+
+    // Levels are computed as L = L_uncal - S, so the tone gives S = L_uncal - L_ref
+    let system_sensitivity = tone.level_db - reference_spl;
+    println!("  Derived end-to-end system sensitivity (EE): {:.2} dB", system_sensitivity);
+
+    let output_path = write_calibration_file(&config, tone_wav_path, &tone, reference_spl, system_sensitivity)?;
+    println!("  Calibration settings written to: {}", output_path.display());
+    Ok(())
+}
+
+/// Finds the calibrator tone in the recording and measures its level. Flat-top windowed spectra
+/// of 1 s segments are averaged so the peak bin gives the tone's mean-square amplitude.
+pub fn measure_tone(audio_data: &[f32], fs: f64, tone_frequency: Option<f64>, pref: f64) -> Result<ToneMeasurement, String> {
+    let n_window = ((SEGMENT_SECONDS * fs).round() as usize).min(audio_data.len());
+    if n_window < 16 {
+        return Err("Tone recording is too short".to_string());
+    }
+    let n_step = n_window / 2;
+    let num_segments = (audio_data.len() - n_window) / n_step + 1;
+    let (window, _props) = dsp::generate_scaled_window(&WindowType::Flattop, &WindowParameters::default(), n_window);
+
+    let mut mean_power = vec![0.0f64; n_window / 2];
+    for i in 0..num_segments {
+        let mut segment = audio_data[i * n_step..i * n_step + n_window].to_vec();
+        for (sample, &win_val) in segment.iter_mut().zip(window.iter()) {
+            *sample *= win_val;
+        }
+        let fft_result = dsp::calculate_fft(&segment, n_window);
+        for (p, c) in mean_power.iter_mut().zip(&fft_result[1..=n_window / 2]) {
+            *p += (c.norm_sqr() / (n_window as f32).powi(2)) as f64 * 2.0 / num_segments as f64;
+        }
+    }
+
+    // mean_power[k] corresponds to FFT bin k + 1
+    let delf = fs / n_window as f64;
+    let (search_start, search_end) = match tone_frequency {
+        Some(f) => {
+            let lo = ((f * (1.0 - TONE_SEARCH_FRACTION) / delf).floor() as usize).max(1);
+            let hi = ((f * (1.0 + TONE_SEARCH_FRACTION) / delf).ceil() as usize).min(n_window / 2);
+            if lo > hi {
+                return Err(format!("Tone frequency {} Hz is outside the recorded band", f));
+            }
+            (lo - 1, hi - 1)
+        }
+        None => (0, mean_power.len() - 1),
+    };
+    let peak_idx = (search_start..=search_end)
+        .max_by(|&a, &b| mean_power[a].total_cmp(&mean_power[b]))
+        .ok_or_else(|| "No frequency bins to search for the tone".to_string())?;
+
+    Ok(ToneMeasurement {
+        frequency: (peak_idx + 1) as f64 * delf,
+        level_db: utils::power_to_db(mean_power[peak_idx], pref),
+    })
+}
+
+/// Writes the derived calibration as config.toml settings next to the other outputs.
+fn write_calibration_file(
+    config: &AnalysisConfig,
+    tone_wav_path: &Path,
+    tone: &ToneMeasurement,
+    reference_spl: f64,
+    system_sensitivity: f64,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let stem = tone_wav_path.file_stem().unwrap_or_default().to_string_lossy();
+    let output_path = PathBuf::from(&config.output_dir).join(format!("{}_Calibration.toml", stem));
+    fs::create_dir_all(&config.output_dir)?;
+
+    let contents = format!(
+        "# Derived from calibrator tone '{}'\n\
+         # Tone: {:.2} Hz, uncalibrated level {:.2} dB, reference level {:.2} dB\n\
+         calibrated = true\n\
+         calibration_type = \"EE\"\n\
+         system_sensitivity = {:.2}\n",
+        tone_wav_path.display(),
+        tone.frequency,
+        tone.level_db,
+        reference_spl,
+        system_sensitivity
+    );
+    fs::write(&output_path, contents)?;
+    Ok(output_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measure_tone() {
+        // 0.5 amplitude tone between FFT bins: mean square 0.125, i.e. -9.03 dB
+        let fs = 8000.0;
+        let audio: Vec<f32> = (0..4 * 8000)
+            .map(|i| 0.5 * (2.0 * std::f64::consts::PI * 250.4 * i as f64 / fs).sin() as f32)
+            .collect();
+        let tone = measure_tone(&audio, fs, Some(250.0), 1.0).unwrap();
+        assert_eq!(tone.frequency, 250.0);
+        assert!((tone.level_db - 10.0 * 0.125f64.log10()).abs() < 0.05, "{}", tone.level_db);
+    }
+}
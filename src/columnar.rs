@@ -0,0 +1,183 @@
+//! Parquet and Arrow IPC versions of the output tables. Unlike the CSV files, times are typed
+//! timestamps and levels are float32, and the schema carries the frequencies, units and band
+//! limits of the analysis.
+
+use crate::config::{AnalysisConfig, AnalysisType};
+use crate::provenance::{self, RecordingProvenance};
+use crate::qa;
+use crate::timestamp::{RowTime, TimeZoneSpec};
+use crate::utils;
+
+use arrow_array::{ArrayRef, Float32Array, Float64Array, RecordBatch, StringArray, TimestampMicrosecondArray};
+use arrow_schema::{ArrowError, DataType, Field, Schema, TimeUnit};
+use ndarray::Array2;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::metadata::KeyValue;
+use parquet::file::properties::WriterProperties;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Builds a record batch from an output table (a header row of frequencies, then one row of
+/// levels per time, as for `write_csv`). Absolute times go in a `time` column in `zone`, and
+/// relative times in a `time_offset_secs` column; each is only present if used, and null in
+/// rows of the other kind. The schema metadata describes the analysis and the storage format
+/// of the `recordings` behind the table.
+fn output_batch(
+    data: &Array2<f64>,
+    times: &[RowTime],
+    qa_flags: Option<&[u32]>,
+    zone: &TimeZoneSpec,
+    config: &AnalysisConfig,
+    recordings: &[&RecordingProvenance],
+) -> Result<RecordBatch, ArrowError> {
+    let mut fields = Vec::new();
+    let mut columns: Vec<ArrayRef> = Vec::new();
+
+    let absolute: Vec<Option<i64>> = times
+        .iter()
+        .map(|time| match time {
+            RowTime::Absolute(t) => Some(t.timestamp_micros()),
+            RowTime::Relative(_) => None,
+        })
+        .collect();
+    if absolute.iter().any(Option::is_some) {
+        let zone_name: Arc<str> = zone.name().into();
+        fields.push(Field::new("time", DataType::Timestamp(TimeUnit::Microsecond, Some(zone_name.clone())), true));
+        columns.push(Arc::new(TimestampMicrosecondArray::from(absolute).with_timezone(zone_name)));
+    }
+    let relative: Vec<Option<f64>> = times
+        .iter()
+        .map(|time| match time {
+            RowTime::Absolute(_) => None,
+            RowTime::Relative(offset) => Some(*offset),
+        })
+        .collect();
+    if relative.iter().any(Option::is_some) {
+        fields.push(Field::new("time_offset_secs", DataType::Float64, true));
+        columns.push(Arc::new(Float64Array::from(relative)));
+    }
+
+    let frequencies: Vec<f64> = data.row(0).to_vec();
+    for (j, &frequency) in frequencies.iter().enumerate() {
+        let field = match config.analysis_type {
+            AnalysisType::Psd => Field::new(format!("{:.4}", frequency), DataType::Float32, false)
+                .with_metadata(HashMap::from([("frequency_hz".to_string(), frequency.to_string())])),
+            AnalysisType::Broadband => Field::new("broadband", DataType::Float32, false).with_metadata(HashMap::from([
+                ("low_cutoff_hz".to_string(), config.low_cutoff.to_string()),
+                ("high_cutoff_hz".to_string(), config.high_cutoff.to_string()),
+            ])),
+        };
+        fields.push(field);
+        columns.push(Arc::new(Float32Array::from_iter_values(data.column(j).iter().skip(1).map(|&v| v as f32))));
+    }
+    if let Some(flags) = qa_flags {
+        fields.push(Field::new("qa_flags", DataType::Utf8, false));
+        columns.push(Arc::new(StringArray::from_iter_values(flags.iter().map(|&f| qa::flag_names(f)))));
+    }
+
+    let mut metadata = HashMap::from([
+        ("analysis_type".to_string(), match config.analysis_type { AnalysisType::Psd => "PSD", AnalysisType::Broadband => "Broadband" }.to_string()),
+        ("units".to_string(), utils::level_units(config)),
+        ("calibrated".to_string(), config.calibrated.to_string()),
+        ("low_cutoff_hz".to_string(), config.low_cutoff.to_string()),
+        ("high_cutoff_hz".to_string(), config.high_cutoff.to_string()),
+    ]);
+    if config.analysis_type == AnalysisType::Psd {
+        let list: Vec<String> = frequencies.iter().map(|f| f.to_string()).collect();
+        metadata.insert("frequencies_hz".to_string(), list.join(","));
+    }
+    metadata.extend(provenance::input_format_metadata(recordings).into_iter().map(|(key, value)| (key.to_string(), value)));
+    let schema = Schema::new(fields).with_metadata(metadata);
+    RecordBatch::try_new(Arc::new(schema), columns)
+}
+
+/// Writes an output table as a Snappy-compressed Parquet file.
+pub fn write_parquet(
+    path: &Path,
+    data: &Array2<f64>,
+    times: &[RowTime],
+    qa_flags: Option<&[u32]>,
+    zone: &TimeZoneSpec,
+    config: &AnalysisConfig,
+    recordings: &[&RecordingProvenance],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let batch = output_batch(data, times, qa_flags, zone, config, recordings)?;
+    // The schema metadata is also stored as Parquet key-value metadata, for readers that do not
+    // decode the embedded Arrow schema
+    let key_values = batch.schema().metadata().iter().map(|(k, v)| KeyValue::new(k.clone(), v.clone())).collect();
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .set_key_value_metadata(Some(key_values))
+        .build();
+    let mut writer = ArrowWriter::try_new(fs::File::create(path)?, batch.schema(), Some(properties))?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+/// Writes an output table as an Arrow IPC file (Feather v2).
+pub fn write_arrow_ipc(
+    path: &Path,
+    data: &Array2<f64>,
+    times: &[RowTime],
+    qa_flags: Option<&[u32]>,
+    zone: &TimeZoneSpec,
+    config: &AnalysisConfig,
+    recordings: &[&RecordingProvenance],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let batch = output_batch(data, times, qa_flags, zone, config, recordings)?;
+    let mut writer = arrow_ipc::writer::FileWriter::try_new(fs::File::create(path)?, &batch.schema())?;
+    writer.write(&batch)?;
+    writer.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_io::AudioInfo;
+    use crate::config::test_config;
+    use arrow_array::Array;
+    use chrono::{TimeZone, Utc};
+    use ndarray::array;
+
+    #[test]
+    fn test_output_batch_types() {
+        let config = test_config(r#"calibrated = false"#);
+        let data = array![[10.0, 20.0], [-30.5, -40.25], [f64::NAN, -41.0]];
+        let start = Utc.with_ymd_and_hms(2024, 7, 17, 16, 47, 21).unwrap();
+        let times = [RowTime::Absolute(start), RowTime::Relative(1.5)];
+        let zone = TimeZoneSpec::parse("+02:00").unwrap();
+        let recording = RecordingProvenance {
+            format: Some(AudioInfo {
+                container: "FLAC",
+                encoding: "PCM",
+                bits_per_sample: 24,
+                valid_bits: 20,
+                sample_rate: 48000,
+                num_samples: 96000,
+                normalisation: "x / 8388607 (signed 24-bit), 20 valid bits left-justified".to_string(),
+            }),
+            ..Default::default()
+        };
+        let batch = output_batch(&data, &times, Some(&[0, 0]), &zone, &config, &[&recording]).unwrap();
+
+        let schema = batch.schema();
+        let names: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+        assert_eq!(names, ["time", "time_offset_secs", "10.0000", "20.0000", "qa_flags"]);
+        assert_eq!(schema.field(0).data_type(), &DataType::Timestamp(TimeUnit::Microsecond, Some("+02:00".into())));
+        assert_eq!(schema.field(3).metadata()["frequency_hz"], "20");
+        assert_eq!(schema.metadata()["frequencies_hz"], "10,20");
+        assert_eq!(schema.metadata()["units"], "dB (uncalibrated)");
+        assert_eq!(schema.metadata()["input_container"], "FLAC");
+        assert_eq!(schema.metadata()["input_normalisation"], "x / 8388607 (signed 24-bit), 20 valid bits left-justified");
+
+        let time = batch.column(0).as_any().downcast_ref::<TimestampMicrosecondArray>().unwrap();
+        assert_eq!((time.value(0), time.is_null(1)), (start.timestamp_micros(), true));
+        let level = batch.column(3).as_any().downcast_ref::<Float32Array>().unwrap();
+        assert_eq!(level.values().to_vec(), vec![-40.25f32, -41.0]);
+    }
+}
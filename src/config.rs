@@ -1,23 +1,24 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::Path;
 
 // Define enums for configuration options with specific values
-#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum AnalysisType {
     Psd,
     Broadband,
 }
 
-#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum Environment {
     Air,
     Wat, // Water
 }
 
-#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum CalibrationType {
     Ts, // Transducer Specs
@@ -25,25 +26,107 @@ pub enum CalibrationType {
     Rc, // Recorder + Hydrophone/Microphone
 }
 
-#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum WindowType {
     Hann,
     Hamming,
     Blackman,
     Rectangular, // Equivalent to 'None' in MATLAB
+    Kaiser,      // Shape set by kaiser_beta
+    Tukey,       // Taper fraction set by tukey_alpha
+    Flattop,     // Accurate tonal amplitudes
+    BlackmanHarris,
+    Nuttall,
+    Gaussian,    // Width set by gaussian_sigma
 }
 
-#[derive(Deserialize, Debug, Clone, PartialEq)]
+// Shape parameters for the parameterised window types
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct WindowParameters {
+    #[serde(default = "default_kaiser_beta")]
+    pub kaiser_beta: f64,    // Kaiser window beta
+    #[serde(default = "default_tukey_alpha")]
+    pub tukey_alpha: f64,    // Fraction of the Tukey window inside the cosine tapers (0 = rectangular, 1 = Hann)
+    #[serde(default = "default_gaussian_sigma")]
+    pub gaussian_sigma: f64, // Gaussian standard deviation relative to the half window length
+}
+
+impl Default for WindowParameters {
+    fn default() -> Self {
+        WindowParameters {
+            kaiser_beta: default_kaiser_beta(),
+            tukey_alpha: default_tukey_alpha(),
+            gaussian_sigma: default_gaussian_sigma(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterDesign {
+    Butterworth,
+    Chebyshev, // Chebyshev type I, passband ripple set by prefilter_ripple_db
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterResponse {
+    Highpass,
+    Lowpass,
+    Bandpass,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Detrend {
+    None,
+    Mean,   // Remove the segment mean (DC offset)
+    Linear, // Remove a least-squares straight line
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SpectralEstimator {
+    Welch,      // Single window per segment
+    Multitaper, // Average of DPSS (Slepian) tapered spectra per segment
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum WindowUnit {
     Seconds,
     Samples,
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RawSampleFormat {
+    Int,   // Signed (two's complement) integers
+    Uint,  // Unsigned (offset binary) integers
+    Float, // IEEE float
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ByteOrder {
+    Little,
+    Big,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WelchStatistic {
+    Mean,   // Arithmetic mean of linear power (classic Welch)
+    Median, // Median of linear power, bias-corrected for PSD
+    DbMean, // Mean of the dB values (geometric mean of power)
+    Min,
+    Max,
+}
+
 
 // Main configuration struct mirroring the TOML file structure
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct AnalysisConfig {
     // Input/Output Settings
     pub input_path: String,
@@ -53,7 +136,15 @@ pub struct AnalysisConfig {
     #[serde(default = "default_true")] // Default to creating summary unless specified otherwise
     pub create_batch_summary_file: bool,
     #[serde(default = "default_false")] // Default to not writing individual files in batch mode
-    pub write_individual_batch_csvs: bool,
+    pub write_individual_batch_csvs: bool, // Also applies to Parquet, Arrow IPC and NetCDF outputs
+    #[serde(default = "default_false")]
+    pub write_parquet: bool,               // Write each output as Parquet too
+    #[serde(default = "default_false")]
+    pub write_arrow_ipc: bool,             // Write each output as an Arrow IPC (Feather v2) file too
+    #[serde(default = "default_false")]
+    pub write_netcdf: bool,                // Write each output as a CF NetCDF file too
+    #[serde(default = "default_true")]
+    pub write_provenance: bool,            // Write a JSON sidecar with the settings and inputs of each output
 
     // Core Analysis Settings
     pub analysis_type: AnalysisType,
@@ -67,42 +158,154 @@ pub struct AnalysisConfig {
     pub preamp_gain: Option<f64>,           // Optional: dB
     pub adc_vpeak: Option<f64>,             // Optional: Volts
     pub system_sensitivity: Option<f64>,    // Optional: dB (End-to-end or Recorder sensitivity)
+    pub sensitivity_curve_path: Option<String>, // Optional: CSV/TOML table of system sensitivity (dB) vs frequency
 
     // DFT/Windowing Settings
     #[serde(default = "default_window_type")]
     pub window_type: WindowType,
+    #[serde(flatten)]
+    pub window_parameters: WindowParameters,
     #[serde(default = "default_window_length")]
     pub window_length: f64,
     #[serde(default = "default_window_unit")]
     pub window_unit: WindowUnit,
     #[serde(default = "default_overlap")]
     pub overlap_percentage: f64,
+    #[serde(default = "default_detrend")]
+    pub detrend: Detrend,                    // Detrending applied to each segment before windowing
+    #[serde(default = "default_spectral_estimator")]
+    pub spectral_estimator: SpectralEstimator,
+    #[serde(default = "default_multitaper_nw")]
+    pub multitaper_nw: f64,                  // Time-bandwidth product NW for multitaper
+    pub multitaper_tapers: Option<usize>,    // Optional: Number of tapers (default 2*NW - 1)
+    pub nfft: Option<usize>,                 // Optional: FFT length in samples (zero-pads the window)
+    #[serde(default = "default_false")]
+    pub nfft_power_of_two: bool,             // Round the FFT length up to the next power of two
 
     // Frequency Settings
     pub low_cutoff: f64,                     // Hz
     pub high_cutoff: f64,                    // Hz
 
+    // Resampling Settings
+    pub target_sample_rate: Option<u32>,       // Optional: Hz. Resample all input to this rate before analysis
+
+    // Raw (headerless) PCM Input Settings, for .bin/.raw files
+    pub raw_sample_rate: Option<u32>,          // Optional: Hz. Enables reading .bin/.raw files
+    #[serde(default = "default_raw_sample_format")]
+    pub raw_sample_format: RawSampleFormat,
+    #[serde(default = "default_raw_bits_per_sample")]
+    pub raw_bits_per_sample: u16,              // 8, 16, 24 or 32 (32 or 64 for float)
+    #[serde(default = "default_raw_byte_order")]
+    pub raw_byte_order: ByteOrder,
+    #[serde(default = "default_raw_channels")]
+    pub raw_channels: u16,                     // Number of interleaved channels
+    #[serde(default = "default_raw_channel")]
+    pub raw_channel: u16,                      // Channel to analyse (1-based)
+
+    // SoundTrap SUD Input Settings
+    #[serde(default = "default_false")]
+    pub read_sud: bool,                        // Read .sud files (experimental, not yet checked against a recorder's file)
+
+    // Pre-filter Settings (applied to the whole signal before segmentation)
+    pub prefilter_type: Option<FilterDesign>,  // Optional: enables the pre-filter
+    #[serde(default = "default_prefilter_response")]
+    pub prefilter_response: FilterResponse,
+    #[serde(default = "default_prefilter_order")]
+    pub prefilter_order: usize,                // Prototype order (band-pass filters have twice this order)
+    pub prefilter_low_hz: Option<f64>,         // Hz: high-pass corner, or lower band-pass corner
+    pub prefilter_high_hz: Option<f64>,        // Hz: low-pass corner, or upper band-pass corner
+    #[serde(default = "default_prefilter_ripple")]
+    pub prefilter_ripple_db: f64,              // Chebyshev passband ripple in dB
+    #[serde(default = "default_true")]
+    pub prefilter_zero_phase: bool,            // Forward-backward filtering (true) or causal (false)
+
+    // Quality Control Settings
+    #[serde(default = "default_false")]
+    pub quality_control: bool,                 // Scan recordings for clipping, dropouts, spikes, DC offset and RMS jumps
+    #[serde(default = "default_qa_block_seconds")]
+    pub qa_block_seconds: f64,                 // Length of the blocks the QA statistics are computed over
+    #[serde(default = "default_qa_clip_level")]
+    pub qa_clip_level: f64,                    // Fraction of full scale treated as clipped
+    #[serde(default = "default_qa_clip_run")]
+    pub qa_clip_run: usize,                    // Consecutive samples at the clip level that count as clipping
+    #[serde(default = "default_qa_dropout_run")]
+    pub qa_dropout_run: usize,                 // Consecutive identical samples that count as a dropout
+    #[serde(default = "default_qa_spike_factor")]
+    pub qa_spike_factor: f64,                  // Spike threshold as a multiple of the previous block's RMS
+    #[serde(default = "default_qa_rms_jump_db")]
+    pub qa_rms_jump_db: f64,                   // RMS change between consecutive blocks flagged as a jump
+    #[serde(default = "default_qa_dc_offset")]
+    pub qa_dc_offset: f64,                     // Block mean (fraction of full scale) flagged as DC offset
+
     // Optional Settings
     pub welch_factor: Option<usize>,         // Optional: Integer factor for Welch averaging
-    pub timestamp_format: Option<String>,    // Optional: Format string for timestamp parsing
+    pub averaging_interval: Option<String>,  // Optional: Clock-aligned averaging interval, e.g. "1min"
+    #[serde(default = "default_min_coverage")]
+    pub averaging_min_coverage: f64,         // Minimum fraction of an interval that must be covered
+    #[serde(default = "default_welch_statistic")]
+    pub welch_statistic: WelchStatistic,     // Statistic used to combine segments when averaging
+    pub timestamp_format: Option<String>,    // Optional: chrono or PAMGuide (yyyymmdd_HHMMSS) format of filename timestamps
+    pub timestamp_regex: Option<String>,     // Optional: Regex with named groups (year, month, day, hour, ...) for filename timestamps
+    #[serde(skip)]
+    pub timestamp_regex_compiled: Option<regex::Regex>, // timestamp_regex, compiled once by load_config
+    pub timestamp_timezone: Option<String>,  // Optional: Time zone of filename timestamps (default UTC), e.g. "+02:00" or "Europe/Berlin"
+    pub output_timezone: Option<String>,     // Optional: Time zone of the output time column (default UTC)
+    #[serde(default = "default_false")]
+    pub use_recorder_logs: bool,             // Take start times from SoundTrap .log.xml sidecars
+    #[serde(default = "default_false")]
+    pub prefer_embedded_timestamp: bool,     // Use WAV metadata (bext, iXML, GUANO, AudioMoth) before the filename timestamp
+    #[serde(default = "default_true")]
+    pub fill_gaps: bool,                     // Insert NaN rows for gaps between files in batch summaries
+    #[serde(default)]
+    pub netcdf_attributes: BTreeMap<String, toml::Value>, // Extra NetCDF global attributes (instrument, deployment, ...)
 }
 
 // Default value functions for serde
 fn default_true() -> bool { true }
 fn default_false() -> bool { false }
 fn default_window_type() -> WindowType { WindowType::Hann }
+fn default_kaiser_beta() -> f64 { 8.6 }
+fn default_tukey_alpha() -> f64 { 0.5 }
+fn default_gaussian_sigma() -> f64 { 0.4 }
 fn default_window_length() -> f64 { 1.0 }
 fn default_window_unit() -> WindowUnit { WindowUnit::Seconds }
 fn default_overlap() -> f64 { 50.0 }
+fn default_raw_sample_format() -> RawSampleFormat { RawSampleFormat::Int }
+fn default_raw_bits_per_sample() -> u16 { 16 }
+fn default_raw_byte_order() -> ByteOrder { ByteOrder::Little }
+fn default_raw_channels() -> u16 { 1 }
+fn default_raw_channel() -> u16 { 1 }
+fn default_prefilter_response() -> FilterResponse { FilterResponse::Highpass }
+fn default_prefilter_order() -> usize { 4 }
+fn default_prefilter_ripple() -> f64 { 0.5 }
+fn default_detrend() -> Detrend { Detrend::None }
+fn default_spectral_estimator() -> SpectralEstimator { SpectralEstimator::Welch }
+fn default_multitaper_nw() -> f64 { 4.0 }
+fn default_min_coverage() -> f64 { 0.0 }
+fn default_welch_statistic() -> WelchStatistic { WelchStatistic::Mean }
+fn default_qa_block_seconds() -> f64 { 1.0 }
+fn default_qa_clip_level() -> f64 { 0.999 }
+fn default_qa_clip_run() -> usize { 3 }
+fn default_qa_dropout_run() -> usize { 100 }
+fn default_qa_spike_factor() -> f64 { 10.0 }
+fn default_qa_rms_jump_db() -> f64 { 20.0 }
+fn default_qa_dc_offset() -> f64 { 0.01 }
 
 
 // Function to load configuration from a TOML file
 pub fn load_config(path: &Path) -> Result<AnalysisConfig, Box<dyn std::error::Error>> {
     let content = fs::read_to_string(path)?;
-    let config: AnalysisConfig = toml::from_str(&content)?;
+    let mut config: AnalysisConfig = toml::from_str(&content)?;
+
+    // Relative sensitivity curve paths are relative to the configuration file
+    if let Some(curve_path) = &config.sensitivity_curve_path {
+        let base = path.parent().unwrap_or(Path::new(""));
+        config.sensitivity_curve_path = Some(base.join(curve_path).to_string_lossy().into_owned());
+    }
 
     // Basic validation (more can be added)
-    if config.calibrated {
+    // A sensitivity curve replaces the scalar calibration settings
+    if config.calibrated && config.sensitivity_curve_path.is_none() {
         if config.calibration_type.is_none() {
             return Err("Calibration type must be specified when calibrated=true".into());
         }
@@ -125,13 +328,98 @@ pub fn load_config(path: &Path) -> Result<AnalysisConfig, Box<dyn std::error::Er
             }
         }
     }
+    if !config.calibrated && config.sensitivity_curve_path.is_some() {
+        eprintln!("Warning: sensitivity_curve_path is ignored because calibrated = false");
+    }
     if config.overlap_percentage < 0.0 || config.overlap_percentage >= 100.0 {
         return Err("overlap_percentage must be between 0.0 and 99.9".into());
     }
      if config.low_cutoff >= config.high_cutoff {
         return Err("low_cutoff must be less than high_cutoff".into());
     }
+    let window_params = &config.window_parameters;
+    if !(0.0..=1.0).contains(&window_params.tukey_alpha) {
+        return Err("tukey_alpha must be between 0.0 and 1.0".into());
+    }
+    if window_params.kaiser_beta < 0.0 || window_params.gaussian_sigma <= 0.0 {
+        return Err("kaiser_beta must be non-negative and gaussian_sigma positive".into());
+    }
+    if config.spectral_estimator == SpectralEstimator::Multitaper {
+        if config.multitaper_nw <= 0.0 {
+            return Err("multitaper_nw must be positive".into());
+        }
+        if config.multitaper_tapers == Some(0) {
+            return Err("multitaper_tapers must be at least 1".into());
+        }
+    }
+    if config.target_sample_rate == Some(0) {
+        return Err("target_sample_rate must be positive".into());
+    }
+    crate::audio_io::RawFormat::from_config(&config)?;
+    if config.prefilter_type.is_some() {
+        if config.prefilter_order == 0 || config.prefilter_order > 20 {
+            return Err("prefilter_order must be between 1 and 20".into());
+        }
+        let needs_low = config.prefilter_response != FilterResponse::Lowpass;
+        let needs_high = config.prefilter_response != FilterResponse::Highpass;
+        if (needs_low && config.prefilter_low_hz.is_none()) || (needs_high && config.prefilter_high_hz.is_none()) {
+            return Err("prefilter_low_hz (high-pass, band-pass) and prefilter_high_hz (low-pass, band-pass) must be set for the pre-filter".into());
+        }
+        if config.prefilter_ripple_db <= 0.0 {
+            return Err("prefilter_ripple_db must be positive".into());
+        }
+    }
+    if let Some(interval) = &config.averaging_interval {
+        if config.welch_factor.is_some() {
+            return Err("welch_factor and averaging_interval cannot both be set".into());
+        }
+        crate::utils::parse_duration_secs(interval)?;
+    }
+    if !(0.0..=1.0).contains(&config.averaging_min_coverage) {
+        return Err("averaging_min_coverage must be between 0.0 and 1.0".into());
+    }
+    for zone in [&config.timestamp_timezone, &config.output_timezone] {
+        crate::timestamp::TimeZoneSpec::from_setting(zone)?;
+    }
+    if let Some(pattern) = &config.timestamp_regex {
+        config.timestamp_regex_compiled = Some(crate::timestamp::compile_timestamp_regex(pattern)?);
+    }
+    crate::netcdf::check_attributes(&config.netcdf_attributes)?;
+    if config.write_netcdf {
+        let missing = crate::netcdf::missing_archive_attributes(&config.netcdf_attributes);
+        if !missing.is_empty() {
+            eprintln!(
+                "Warning: [netcdf_attributes] does not set {}, which NCEI archiving expects",
+                missing.join(", ")
+            );
+        }
+    }
+    if config.quality_control {
+        if config.qa_block_seconds <= 0.0 {
+            return Err("qa_block_seconds must be positive".into());
+        }
+        if config.qa_clip_level <= 0.0 || config.qa_clip_level > 1.0 {
+            return Err("qa_clip_level must be in (0.0, 1.0]".into());
+        }
+    }
 
 
     Ok(config)
 }
+
+// Builds a configuration for unit tests: the required settings of a PSD analysis, with any
+// settings in `overrides` (TOML) replacing or adding to them
+#[cfg(test)]
+pub(crate) fn test_config(overrides: &str) -> AnalysisConfig {
+    let mut table: toml::Table = toml::from_str(
+        r#"input_path = "in"
+output_dir = "out"
+analysis_type = "psd"
+environment = "wat"
+low_cutoff = 10.0
+high_cutoff = 20.0"#,
+    )
+    .unwrap();
+    table.extend(toml::from_str::<toml::Table>(overrides).unwrap());
+    table.try_into().unwrap()
+}
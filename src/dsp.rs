@@ -1,10 +1,32 @@
-use crate::config::WindowType;
+use crate::config::{Detrend, WelchStatistic, WindowParameters, WindowType};
 use rustfft::{FftPlanner, num_complex::Complex};
 use ndarray::{Array1, ArrayView1};
-use std::f32::consts::PI;
-
-/// Applies a window function in-place to a segment and returns the scaling factor alpha.
+use std::f64::consts::PI;
 
+/// Removes the mean or a least-squares linear trend from a segment in-place.
+pub fn detrend_segment(segment: &mut [f32], detrend: &Detrend) {
+    let n = segment.len();
+    if n == 0 || *detrend == Detrend::None {
+        return;
+    }
+    let mean = segment.iter().map(|&x| x as f64).sum::<f64>() / n as f64;
+    let slope = match detrend {
+        Detrend::Linear if n > 1 => {
+            // Fit against sample index centred on zero, so the intercept is the mean
+            let centre = (n as f64 - 1.0) / 2.0;
+            let (sum_ty, sum_tt) = segment.iter().enumerate().fold((0.0, 0.0), |(ty, tt), (i, &x)| {
+                let t = i as f64 - centre;
+                (ty + t * x as f64, tt + t * t)
+            });
+            sum_ty / sum_tt
+        }
+        _ => 0.0,
+    };
+    let centre = (n as f64 - 1.0) / 2.0;
+    for (i, x) in segment.iter_mut().enumerate() {
+        *x = (*x as f64 - mean - slope * (i as f64 - centre)) as f32;
+    }
+}
 
 /// Calculates the noise power bandwidth (B) for a given window function.
 /// Assumes the window values have already been scaled by alpha.
@@ -14,14 +36,24 @@ pub fn noise_power_bandwidth(window_view: ArrayView1<f32>, n_samples: usize) ->
     (1.0 / n_samples as f64) * sum_sq as f64
 }
 
-/// Calculates the FFT of a real-valued segment.
-pub fn calculate_fft(segment: &[f32]) -> Vec<Complex<f32>> {
-    let n = segment.len();
+/// Determines the FFT length for a window of `n_window_samples`.
+/// `nfft` zero-pads the window to a longer FFT; `power_of_two` rounds the length up to the next power of two.
+pub fn fft_length(n_window_samples: usize, nfft: Option<usize>, power_of_two: bool) -> Result<usize, String> {
+    let n_fft = nfft.unwrap_or(n_window_samples);
+    if n_fft < n_window_samples {
+        return Err(format!("nfft ({}) must not be shorter than the window length ({} samples)", n_fft, n_window_samples));
+    }
+    Ok(if power_of_two { n_fft.next_power_of_two() } else { n_fft })
+}
+
+/// Calculates the FFT of a real-valued segment, zero-padded to `nfft` points.
+pub fn calculate_fft(segment: &[f32], nfft: usize) -> Vec<Complex<f32>> {
     let mut planner = FftPlanner::new();
-    let fft = planner.plan_fft_forward(n);
+    let fft = planner.plan_fft_forward(nfft);
 
-    // Create complex buffer and copy real data into it
+    // Create complex buffer, copy real data into it and zero-pad the remainder
     let mut buffer: Vec<Complex<f32>> = segment.iter().map(|&x| Complex::new(x, 0.0)).collect();
+    buffer.resize(nfft, Complex::new(0.0, 0.0));
 
     // Perform FFT in-place
     fft.process(&mut buffer);
@@ -29,41 +61,372 @@ pub fn calculate_fft(segment: &[f32]) -> Vec<Complex<f32>> {
     buffer
 }
 
-/// Generates the window values for a given type and length, scaled by alpha.
-pub fn generate_scaled_window(win_type: &WindowType, n_samples: usize) -> (Array1<f32>, f64) {
-     let n_f32 = n_samples as f32;
-     let (window_values, alpha): (Vec<f32>, f64) = match win_type {
-        WindowType::Rectangular => {
-            (vec![1.0; n_samples], 1.0)
-        }
-        WindowType::Hann => {
-            let alpha = 0.5;
-            let window = (0..n_samples)
-                .map(|i| alpha as f32 - 0.5 * (2.0 * PI * i as f32 / n_f32).cos())
-                .collect();
-            (window, alpha)
-        }
-        WindowType::Hamming => {
-            let alpha = 0.54;
-            let window = (0..n_samples)
-                .map(|i| alpha as f32 - 0.46 * (2.0 * PI * i as f32 / n_f32).cos())
-                .collect();
-            (window, alpha)
+/// Amplitude and noise properties of a window function.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowProperties {
+    /// Coherent gain (alpha): the mean of the unscaled window, used to scale it to unit gain.
+    pub coherent_gain: f64,
+    /// Equivalent noise bandwidth in bins, N * sum(w^2) / (sum w)^2. Equal to the
+    /// `noise_power_bandwidth` of the scaled window.
+    pub enbw: f64,
+}
+
+/// Generates the window values for a given type and length, scaled by alpha (the coherent gain).
+/// All windows are periodic (DFT-even), as used for spectral analysis.
+pub fn generate_scaled_window(win_type: &WindowType, params: &WindowParameters, n_samples: usize) -> (Array1<f32>, WindowProperties) {
+    let n = n_samples as f64;
+    // Position of each sample in [-1, 1), centred on the window peak
+    let position = |i: usize| 2.0 * i as f64 / n - 1.0;
+
+    let window_values: Vec<f64> = match win_type {
+        WindowType::Rectangular => vec![1.0; n_samples],
+        WindowType::Hann => cosine_sum_window(&[0.5, 0.5], n_samples),
+        WindowType::Hamming => cosine_sum_window(&[0.54, 0.46], n_samples),
+        WindowType::Blackman => cosine_sum_window(&[0.42, 0.5, 0.08], n_samples),
+        WindowType::BlackmanHarris => cosine_sum_window(&[0.35875, 0.48829, 0.14128, 0.01168], n_samples),
+        WindowType::Nuttall => cosine_sum_window(&[0.355768, 0.487396, 0.144232, 0.012604], n_samples),
+        // Coefficients as MATLAB flattopwin, for accurate tonal amplitudes
+        WindowType::Flattop => cosine_sum_window(&[0.21557895, 0.41663158, 0.277263158, 0.083578947, 0.006947368], n_samples),
+        WindowType::Kaiser => {
+            let beta = params.kaiser_beta;
+            let norm = bessel_i0(beta);
+            (0..n_samples)
+                .map(|i| bessel_i0(beta * (1.0 - position(i).powi(2)).max(0.0).sqrt()) / norm)
+                .collect()
         }
-        WindowType::Blackman => {
-            let alpha = 0.42;
-            let window = (0..n_samples)
+        WindowType::Tukey => {
+            // Cosine tapers over a fraction alpha of the window, flat in between
+            let alpha = params.tukey_alpha;
+            (0..n_samples)
                 .map(|i| {
-                    alpha as f32 - 0.5 * (2.0 * PI * i as f32 / n_f32).cos()
-                        + 0.08 * (4.0 * PI * i as f32 / n_f32).cos()
+                    let edge_distance = 1.0 - position(i).abs();
+                    if alpha <= 0.0 || edge_distance >= alpha {
+                        1.0
+                    } else {
+                        0.5 * (1.0 - (PI * edge_distance / alpha).cos())
+                    }
                 })
-                .collect();
-            (window, alpha)
+                .collect()
+        }
+        WindowType::Gaussian => {
+            let sigma = params.gaussian_sigma;
+            (0..n_samples).map(|i| (-0.5 * (position(i) / sigma).powi(2)).exp()).collect()
         }
     };
 
+    let sum: f64 = window_values.iter().sum();
+    let sum_sq: f64 = window_values.iter().map(|&x| x * x).sum();
+    let alpha = sum / n;
+    let properties = WindowProperties {
+        coherent_gain: alpha,
+        enbw: n * sum_sq / sum.powi(2),
+    };
+
     // Scale by alpha
-    let scaled_window: Vec<f32> = window_values.iter().map(|&x| x / alpha as f32).collect();
+    let scaled_window: Vec<f32> = window_values.iter().map(|&x| (x / alpha) as f32).collect();
+
+    (Array1::from(scaled_window), properties)
+}
+
+/// Generalised cosine-sum window: w[n] = a0 - a1 cos(2 pi n/N) + a2 cos(4 pi n/N) - ...
+fn cosine_sum_window(coefficients: &[f64], n_samples: usize) -> Vec<f64> {
+    let n = n_samples as f64;
+    (0..n_samples)
+        .map(|i| {
+            coefficients
+                .iter()
+                .enumerate()
+                .map(|(k, &a)| {
+                    let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+                    sign * a * (2.0 * PI * k as f64 * i as f64 / n).cos()
+                })
+                .sum()
+        })
+        .collect()
+}
+
+/// Zeroth-order modified Bessel function of the first kind, by power series.
+pub fn bessel_i0(x: f64) -> f64 {
+    let half_x = x / 2.0;
+    let mut term = 1.0;
+    let mut sum = 1.0;
+    for k in 1..500 {
+        term *= (half_x / k as f64).powi(2);
+        sum += term;
+        if term < sum * 1e-16 {
+            break;
+        }
+    }
+    sum
+}
+
+/// Generates `n_tapers` discrete prolate spheroidal (Slepian) sequences of length `n_samples`
+/// with time-half-bandwidth product `nw`, for multitaper spectral estimation.
+/// Each taper is scaled so that sum(w[n]^2) = N, giving a noise power bandwidth of 1 bin,
+/// consistent with the alpha-scaled windows used by the single-window path.
+///
+/// The tapers are the eigenvectors of the symmetric tridiagonal matrix of Percival & Walden
+/// (1993, eq. 378), found by bisection (Sturm sequence) and inverse iteration.
+pub fn generate_dpss_tapers(n_samples: usize, nw: f64, n_tapers: usize) -> Result<Vec<Array1<f32>>, String> {
+    if n_samples < 2 {
+        return Err("Multitaper estimation needs at least 2 samples per window".to_string());
+    }
+    if nw <= 0.0 || nw >= n_samples as f64 / 2.0 {
+        return Err(format!("multitaper_nw must be between 0 and N/2 ({}), got {}", n_samples as f64 / 2.0, nw));
+    }
+    if n_tapers == 0 || n_tapers > n_samples {
+        return Err(format!("Invalid number of tapers {} for window length {}", n_tapers, n_samples));
+    }
+
+    let n = n_samples as f64;
+    let w = nw / n;
+    let cos_2pi_w = (2.0 * std::f64::consts::PI * w).cos();
+    let diag: Vec<f64> = (0..n_samples)
+        .map(|i| ((n - 1.0 - 2.0 * i as f64) / 2.0).powi(2) * cos_2pi_w)
+        .collect();
+    // off_diag[i] couples rows i and i + 1
+    let off_diag: Vec<f64> = (1..n_samples).map(|i| i as f64 * (n - i as f64) / 2.0).collect();
+
+    // Gershgorin bounds on the spectrum
+    let (mut lower, mut upper) = (f64::INFINITY, f64::NEG_INFINITY);
+    for i in 0..n_samples {
+        let radius = if i > 0 { off_diag[i - 1].abs() } else { 0.0 }
+            + if i + 1 < n_samples { off_diag[i].abs() } else { 0.0 };
+        lower = lower.min(diag[i] - radius);
+        upper = upper.max(diag[i] + radius);
+    }
+
+    let mut tapers = Vec::with_capacity(n_tapers);
+    for k in 0..n_tapers {
+        // The k-th largest eigenvalue has exactly N - 1 - k eigenvalues below it
+        let target_below = n_samples - 1 - k;
+        let (mut lo, mut hi) = (lower, upper);
+        for _ in 0..200 {
+            let mid = 0.5 * (lo + hi);
+            if mid <= lo || mid >= hi {
+                break;
+            }
+            if sturm_count(&diag, &off_diag, mid) > target_below {
+                hi = mid;
+            } else {
+                lo = mid;
+            }
+        }
+        let eigenvalue = 0.5 * (lo + hi);
+        let mut taper = tridiagonal_inverse_iteration(&diag, &off_diag, eigenvalue, k);
+
+        // Sign convention: symmetric tapers have a positive sum, antisymmetric tapers start positive
+        let sign_ref: f64 = if k % 2 == 0 {
+            taper.iter().sum()
+        } else {
+            taper.iter().enumerate().map(|(i, &v)| v * (n - 1.0 - 2.0 * i as f64)).sum()
+        };
+        let scale = n.sqrt() * if sign_ref < 0.0 { -1.0 } else { 1.0 };
+        for v in taper.iter_mut() {
+            *v *= scale;
+        }
+        tapers.push(Array1::from(taper.iter().map(|&v| v as f32).collect::<Vec<f32>>()));
+    }
+    Ok(tapers)
+}
+
+/// Counts the eigenvalues of a symmetric tridiagonal matrix that are smaller than `x`.
+fn sturm_count(diag: &[f64], off_diag: &[f64], x: f64) -> usize {
+    let mut count = 0;
+    let mut q = 1.0;
+    for i in 0..diag.len() {
+        let coupling = if i > 0 { off_diag[i - 1].powi(2) / q } else { 0.0 };
+        q = diag[i] - x - coupling;
+        if q == 0.0 {
+            q = f64::EPSILON * (diag[i].abs() + x.abs()).max(1.0);
+        }
+        if q < 0.0 {
+            count += 1;
+        }
+    }
+    count
+}
+
+/// Finds the unit-norm eigenvector for a known eigenvalue of a symmetric tridiagonal matrix.
+fn tridiagonal_inverse_iteration(diag: &[f64], off_diag: &[f64], eigenvalue: f64, seed: usize) -> Vec<f64> {
+    let n = diag.len();
+    // Deterministic pseudo-random start vector with components along every eigenvector
+    let mut state = 0x9E37_79B9_7F4A_7C15u64 ^ (seed as u64 + 1);
+    let mut x: Vec<f64> = (0..n)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 11) as f64 / (1u64 << 53) as f64 - 0.5
+        })
+        .collect();
+
+    let tiny = f64::EPSILON * diag.iter().chain(off_diag).fold(0.0f64, |m, v| m.max(v.abs()));
+    let mut c_prime = vec![0.0; n];
+    for _ in 0..3 {
+        // Thomas algorithm for (T - lambda I) y = x
+        let mut pivot = diag[0] - eigenvalue;
+        for i in 0..n {
+            if i > 0 {
+                pivot = diag[i] - eigenvalue - off_diag[i - 1] * c_prime[i - 1];
+                x[i] -= off_diag[i - 1] * x[i - 1];
+            }
+            if pivot.abs() < tiny {
+                pivot = tiny;
+            }
+            if i + 1 < n {
+                c_prime[i] = off_diag[i] / pivot;
+            }
+            x[i] /= pivot;
+        }
+        for i in (0..n - 1).rev() {
+            x[i] -= c_prime[i] * x[i + 1];
+        }
+
+        let norm = x.iter().map(|v| v * v).sum::<f64>().sqrt();
+        for v in x.iter_mut() {
+            *v /= norm;
+        }
+    }
+    x
+}
+
+/// Combines the power values of several segments into a single vector using the given statistic.
+/// When `median_bias_correction` is set, the median is divided by its expected value for
+/// exponentially distributed (chi-squared, 2 dof) power, which holds for individual PSD bins.
+pub fn average_segments(segments: &[Vec<f64>], statistic: &WelchStatistic, median_bias_correction: bool) -> Vec<f64> {
+    let n_segments = segments.len();
+    let n_values = segments.first().map_or(0, |seg| seg.len());
+    let median_bias = if median_bias_correction { median_bias_factor(n_segments) } else { 1.0 };
+
+    let mut column = vec![0.0; n_segments];
+    (0..n_values)
+        .map(|idx| {
+            for (value, seg) in column.iter_mut().zip(segments) {
+                *value = seg[idx];
+            }
+            match statistic {
+                WelchStatistic::Mean => column.iter().sum::<f64>() / n_segments as f64,
+                WelchStatistic::Median => median(&mut column) / median_bias,
+                WelchStatistic::DbMean => {
+                    let mean_db = column.iter().map(|&p| 10.0 * p.log10()).sum::<f64>() / n_segments as f64;
+                    10f64.powf(mean_db / 10.0)
+                }
+                WelchStatistic::Min => column.iter().cloned().fold(f64::INFINITY, f64::min),
+                WelchStatistic::Max => column.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+            }
+        })
+        .collect()
+}
+
+/// Returns the median of the values, reordering them in the process.
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    let n = values.len();
+    if n % 2 == 1 {
+        values[n / 2]
+    } else {
+        0.5 * (values[n / 2 - 1] + values[n / 2])
+    }
+}
+
+/// Expected sample median of `n` exponential variables with unit mean.
+/// Uses E[X_(r)] = sum_{i=n-r+1}^{n} 1/i for the r-th order statistic.
+fn median_bias_factor(n: usize) -> f64 {
+    let order_stat_mean = |r: usize| -> f64 { (n - r + 1..=n).map(|i| 1.0 / i as f64).sum() };
+    if n == 0 {
+        1.0
+    } else if n % 2 == 1 {
+        order_stat_mean(n / 2 + 1)
+    } else {
+        0.5 * (order_stat_mean(n / 2) + order_stat_mean(n / 2 + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_median_bias_factor() {
+        assert!((median_bias_factor(1) - 1.0).abs() < 1e-12);
+        assert!((median_bias_factor(3) - (1.0 - 1.0 / 2.0 + 1.0 / 3.0)).abs() < 1e-12);
+        // Approaches ln(2) for many segments
+        assert!((median_bias_factor(1001) - 2f64.ln()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_dpss_tapers() {
+        let n = 256;
+        let tapers = generate_dpss_tapers(n, 4.0, 7).unwrap();
+        for (k, taper) in tapers.iter().enumerate() {
+            // Unit noise power bandwidth
+            assert!((noise_power_bandwidth(taper.view(), n) - 1.0).abs() < 1e-4);
+            // Even tapers are symmetric, odd tapers antisymmetric
+            let parity = if k % 2 == 0 { 1.0 } else { -1.0 };
+            for i in 0..n {
+                assert!((taper[i] - parity * taper[n - 1 - i]).abs() < 1e-3);
+            }
+            // The k-th taper has k zero crossings
+            let crossings = taper.iter().zip(taper.iter().skip(1)).filter(|(a, b)| a.signum() != b.signum()).count();
+            assert_eq!(crossings, k);
+            // Mutually orthogonal
+            for other in tapers.iter().take(k) {
+                let dot: f32 = taper.iter().zip(other.iter()).map(|(a, b)| a * b).sum();
+                assert!(dot.abs() / (n as f32) < 1e-4);
+            }
+        }
+        // The zeroth taper peaks in the middle and is positive
+        assert!(tapers[0][n / 2] > tapers[0][0]);
+        assert!(tapers[0][0] > 0.0);
+    }
+
+    #[test]
+    fn test_window_properties() {
+        let params = WindowParameters::default();
+        let n = 4096;
+        // Reference ENBW values (bins) for the periodic windows
+        let cases = [
+            (WindowType::Rectangular, 1.0),
+            (WindowType::Hann, 1.5),
+            (WindowType::Hamming, 1.3628),
+            (WindowType::Blackman, 1.7268),
+            (WindowType::BlackmanHarris, 2.0044),
+            (WindowType::Flattop, 3.7702),
+        ];
+        for (win_type, expected_enbw) in cases {
+            let (window, props) = generate_scaled_window(&win_type, &params, n);
+            assert!((props.enbw - expected_enbw).abs() < 1e-3, "{:?}: {}", win_type, props.enbw);
+            assert!((noise_power_bandwidth(window.view(), n) - props.enbw).abs() < 1e-4);
+        }
+        let (_, hann) = generate_scaled_window(&WindowType::Hann, &params, n);
+        assert!((hann.coherent_gain - 0.5).abs() < 1e-12);
+        // A Tukey window with alpha = 1 is a Hann window
+        let tukey_params = WindowParameters { tukey_alpha: 1.0, ..params };
+        let (_, tukey) = generate_scaled_window(&WindowType::Tukey, &tukey_params, n);
+        assert!((tukey.enbw - 1.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_detrend_segment() {
+        let mut segment: Vec<f32> = (0..100).map(|i| 0.5 + 0.01 * i as f32).collect();
+        let mut demeaned = segment.clone();
+        detrend_segment(&mut demeaned, &Detrend::Mean);
+        assert!(demeaned.iter().sum::<f32>().abs() < 1e-4);
+        detrend_segment(&mut segment, &Detrend::Linear);
+        assert!(segment.iter().all(|x| x.abs() < 1e-5));
+    }
 
-    (Array1::from(scaled_window), alpha)
+    #[test]
+    fn test_average_segments() {
+        let segments = vec![vec![1.0, 10.0], vec![100.0, 10.0], vec![10.0, 10.0]];
+        let mean = average_segments(&segments, &WelchStatistic::Mean, false);
+        assert!((mean[0] - 37.0).abs() < 1e-12);
+        let db_mean = average_segments(&segments, &WelchStatistic::DbMean, false);
+        assert!((db_mean[0] - 10.0).abs() < 1e-9);
+        let median = average_segments(&segments, &WelchStatistic::Median, false);
+        assert_eq!(median, vec![10.0, 10.0]);
+        let max = average_segments(&segments, &WelchStatistic::Max, false);
+        assert_eq!(max, vec![100.0, 10.0]);
+    }
 }
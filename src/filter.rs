@@ -0,0 +1,534 @@
+use crate::config::{AnalysisConfig, FilterDesign, FilterResponse};
+use num_complex::Complex64;
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+/// A second-order IIR section, with the leading denominator coefficient normalised to 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Biquad {
+    pub b: [f64; 3],
+    pub a: [f64; 3],
+}
+
+/// Designs the configured pre-filter for sample rate `fs`. Returns None if no pre-filter is set.
+pub fn design_prefilter(config: &AnalysisConfig, fs: f64) -> Result<Option<Vec<Biquad>>, String> {
+    let design = match &config.prefilter_type {
+        Some(design) => design,
+        None => return Ok(None),
+    };
+    let sections = design_filter(
+        design,
+        &config.prefilter_response,
+        config.prefilter_order,
+        config.prefilter_low_hz,
+        config.prefilter_high_hz,
+        config.prefilter_ripple_db,
+        fs,
+    )?;
+    check_stable(&sections)?;
+    println!(
+        "  Applying {:?} {:?} pre-filter (order {}, {})",
+        design,
+        config.prefilter_response,
+        config.prefilter_order,
+        if config.prefilter_zero_phase { "zero-phase" } else { "causal" }
+    );
+    Ok(Some(sections))
+}
+
+/// Shortest stretch of the signal run through the backward pass of a zero-phase filter at once.
+const MIN_ZERO_PHASE_CHUNK: usize = 1 << 12;
+
+/// A zero-phase filter that runs block by block, matching `filtfilt` over the whole signal.
+/// The forward pass is causal and keeps its state between blocks. The backward pass runs over a
+/// chunk of the forward output plus an overlap, long enough for the filter's response to decay
+/// (to 1e-12), past the end of the chunk; only the chunk is kept. At the end of the signal
+/// the backward pass starts from the same odd extension as `filtfilt`. Memory use depends on
+/// the filter's decay time rather than the length of the signal.
+pub struct ZeroPhaseFilter {
+    sections: Vec<Biquad>,
+    zi: Vec<[f64; 2]>,
+    pad: usize,
+    overlap: usize,
+    chunk: usize,
+    head: Vec<f64>,                       // Samples held until the start extension can be built
+    forward_states: Option<Vec<[f64; 2]>>, // None until the forward pass has started
+    forward: VecDeque<f64>,               // Forward-filtered samples not yet output
+    tail: VecDeque<f64>,                  // The last pad + 1 input samples
+}
+
+impl ZeroPhaseFilter {
+    pub fn new(sections: Vec<Biquad>) -> ZeroPhaseFilter {
+        let zi = sosfilt_zi(&sections);
+        let pad = 3 * (2 * sections.len() + 1);
+        let overlap = decay_length(&sections, 1e-12).max(pad);
+        let chunk = overlap.max(MIN_ZERO_PHASE_CHUNK);
+        ZeroPhaseFilter {
+            sections,
+            zi,
+            pad,
+            overlap,
+            chunk,
+            head: Vec::new(),
+            forward_states: None,
+            forward: VecDeque::new(),
+            tail: VecDeque::new(),
+        }
+    }
+
+    /// Samples of the forward-filtered signal run through the backward pass past each chunk.
+    pub fn overlap(&self) -> usize {
+        self.overlap
+    }
+
+    /// Filters the next block of the signal, returning the samples whose backward pass is
+    /// complete (fewer or more than were passed in).
+    pub fn process(&mut self, block: &[f32]) -> Vec<f32> {
+        let mut signal: Vec<f64> = block.iter().map(|&x| x as f64).collect();
+        for &x in &signal {
+            self.tail.push_back(x);
+        }
+        while self.tail.len() > self.pad + 1 {
+            self.tail.pop_front();
+        }
+        if self.forward_states.is_none() {
+            self.head.append(&mut signal);
+            if self.head.len() <= self.pad {
+                return Vec::new();
+            }
+            // Run the forward pass over the odd extension before the start, as filtfilt does
+            let x0 = self.head[0];
+            let mut extended: Vec<f64> = (1..=self.pad).rev().map(|i| 2.0 * x0 - self.head[i]).collect();
+            let mut states = self.scaled_zi(extended[0]);
+            sosfilt_with_states(&self.sections, &mut extended, &mut states);
+            self.forward_states = Some(states);
+            signal = std::mem::take(&mut self.head);
+        }
+        if let Some(states) = &mut self.forward_states {
+            sosfilt_with_states(&self.sections, &mut signal, states);
+        }
+        self.forward.extend(signal);
+
+        let mut output = Vec::new();
+        while self.forward.len() >= self.chunk + self.overlap {
+            let mut stretch: Vec<f64> = self.forward.range(..self.chunk + self.overlap).rev().copied().collect();
+            let mut states = self.scaled_zi(stretch[0]);
+            sosfilt_with_states(&self.sections, &mut stretch, &mut states);
+            output.extend(stretch[self.overlap..].iter().rev().map(|&y| y as f32));
+            self.forward.drain(..self.chunk);
+        }
+        output
+    }
+
+    /// Filters the rest of the signal, after the last block.
+    pub fn finish(&mut self) -> Vec<f32> {
+        if self.forward_states.is_none() {
+            // Too short to start streaming: filter it whole, as filtfilt shortens its padding
+            let mut signal = std::mem::take(&mut self.head);
+            filtfilt(&self.sections, &mut signal);
+            return signal.into_iter().map(|y| y as f32).collect();
+        }
+        // Continue the forward pass over the odd extension past the end
+        let n = self.tail.len();
+        let last = self.tail[n - 1];
+        let mut extended: Vec<f64> = (1..=self.pad).map(|i| 2.0 * last - self.tail[n - 1 - i]).collect();
+        if let Some(states) = &mut self.forward_states {
+            sosfilt_with_states(&self.sections, &mut extended, states);
+        }
+        let mut stretch: Vec<f64> = self.forward.drain(..).chain(extended).rev().collect();
+        let mut states = self.scaled_zi(stretch[0]);
+        sosfilt_with_states(&self.sections, &mut stretch, &mut states);
+        stretch[self.pad..].iter().rev().map(|&y| y as f32).collect()
+    }
+
+    fn scaled_zi(&self, x0: f64) -> Vec<[f64; 2]> {
+        self.zi.iter().map(|z| [z[0] * x0, z[1] * x0]).collect()
+    }
+}
+
+/// Largest pole radius of cascaded sections: 1 or more for an unstable filter.
+fn pole_radius(sections: &[Biquad]) -> f64 {
+    sections
+        .iter()
+        .map(|section| {
+            let [_, a1, a2] = section.a;
+            let discriminant = Complex64::new(a1 * a1 - 4.0 * a2, 0.0).sqrt();
+            let p1 = (-a1 + discriminant) / 2.0;
+            let p2 = (-a1 - discriminant) / 2.0;
+            p1.norm().max(p2.norm())
+        })
+        .fold(0.0, f64::max)
+}
+
+/// Rejects a design with a pole on or outside the unit circle, such as one whose cutoff is too
+/// close to 0 Hz for the sample rate, whose response would never decay.
+fn check_stable(sections: &[Biquad]) -> Result<(), String> {
+    let radius = pole_radius(sections);
+    if radius >= 1.0 {
+        return Err(format!(
+            "Pre-filter design is unstable (largest pole radius {}); raise its cutoff or lower its order",
+            radius
+        ));
+    }
+    Ok(())
+}
+
+/// Samples for the impulse response of stable cascaded sections to decay below `tolerance`,
+/// from the largest pole radius (with a margin for repeated poles).
+fn decay_length(sections: &[Biquad], tolerance: f64) -> usize {
+    let radius = pole_radius(sections);
+    debug_assert!(radius < 1.0, "unstable filter");
+    if radius <= 0.0 {
+        return 0;
+    }
+    (2.0 * sections.len() as f64 * tolerance.ln() / radius.ln()).ceil() as usize
+}
+
+/// A causal filter that keeps its state between blocks, so a signal can be filtered block by
+/// block with the same result as filtering it whole.
+pub struct CausalFilter {
+    sections: Vec<Biquad>,
+    states: Vec<[f64; 2]>,
+}
+
+impl CausalFilter {
+    /// A filter starting at rest.
+    pub fn new(sections: Vec<Biquad>) -> CausalFilter {
+        let states = vec![[0.0; 2]; sections.len()];
+        CausalFilter { sections, states }
+    }
+
+    /// Filters the next block of the signal in-place.
+    pub fn process(&mut self, block: &mut [f32]) {
+        let mut signal: Vec<f64> = block.iter().map(|&x| x as f64).collect();
+        sosfilt_with_states(&self.sections, &mut signal, &mut self.states);
+        for (sample, &filtered) in block.iter_mut().zip(&signal) {
+            *sample = filtered as f32;
+        }
+    }
+}
+
+/// Designs a digital Butterworth or Chebyshev (type I) filter as cascaded second-order sections.
+/// The analog prototype is frequency-transformed with pre-warped corners and mapped to the
+/// z-plane with the bilinear transform. Band-pass filters have twice the prototype order.
+pub fn design_filter(
+    design: &FilterDesign,
+    response: &FilterResponse,
+    order: usize,
+    low_hz: Option<f64>,
+    high_hz: Option<f64>,
+    ripple_db: f64,
+    fs: f64,
+) -> Result<Vec<Biquad>, String> {
+    if order == 0 {
+        return Err("Pre-filter order must be at least 1".to_string());
+    }
+    let nyquist = fs / 2.0;
+    let check_corner = |name: &str, corner: Option<f64>| -> Result<f64, String> {
+        let f = corner.ok_or_else(|| format!("{} is required for a {:?} pre-filter", name, response))?;
+        if f <= 0.0 || f >= nyquist {
+            return Err(format!("{} ({} Hz) must be between 0 and the Nyquist frequency ({} Hz)", name, f, nyquist));
+        }
+        Ok(f)
+    };
+    // Pre-warped analog corner frequency in rad/s
+    let warp = |f: f64| 2.0 * fs * (PI * f / fs).tan();
+
+    let (poles, gain) = analog_prototype(design, order, ripple_db);
+    let degree = poles.len() as i32;
+
+    let (zeros, poles, gain): (Vec<Complex64>, Vec<Complex64>, f64) = match response {
+        FilterResponse::Lowpass => {
+            let wo = warp(check_corner("prefilter_high_hz", high_hz)?);
+            (Vec::new(), poles.iter().map(|&p| p * wo).collect(), gain * wo.powi(degree))
+        }
+        FilterResponse::Highpass => {
+            let wo = warp(check_corner("prefilter_low_hz", low_hz)?);
+            let prod_neg_p: Complex64 = poles.iter().map(|&p| -p).product();
+            (
+                vec![Complex64::new(0.0, 0.0); poles.len()],
+                poles.iter().map(|&p| wo / p).collect(),
+                gain * (Complex64::new(1.0, 0.0) / prod_neg_p).re,
+            )
+        }
+        FilterResponse::Bandpass => {
+            let low = check_corner("prefilter_low_hz", low_hz)?;
+            let high = check_corner("prefilter_high_hz", high_hz)?;
+            if low >= high {
+                return Err("prefilter_low_hz must be less than prefilter_high_hz".to_string());
+            }
+            let (w1, w2) = (warp(low), warp(high));
+            let bw = w2 - w1;
+            let wo = (w1 * w2).sqrt();
+            let mut bp_poles = Vec::with_capacity(2 * poles.len());
+            for &p in &poles {
+                let p_lp = p * bw / 2.0;
+                let root = (p_lp * p_lp - wo * wo).sqrt();
+                bp_poles.push(p_lp + root);
+                bp_poles.push(p_lp - root);
+            }
+            (vec![Complex64::new(0.0, 0.0); poles.len()], bp_poles, gain * bw.powi(degree))
+        }
+    };
+
+    let (zeros, poles, gain) = bilinear_zpk(&zeros, &poles, gain, fs);
+    Ok(zpk_to_sos(&zeros, &poles, gain))
+}
+
+/// Poles and gain of the normalised (1 rad/s) analog low-pass prototype.
+fn analog_prototype(design: &FilterDesign, order: usize, ripple_db: f64) -> (Vec<Complex64>, f64) {
+    let n = order as f64;
+    // theta_m = pi * m / (2N) for m = -N+1, -N+3, ..., N-1
+    let thetas: Vec<f64> = (0..order).map(|i| PI * (2.0 * i as f64 - n + 1.0) / (2.0 * n)).collect();
+    match design {
+        FilterDesign::Butterworth => {
+            let poles = thetas.iter().map(|&t| -Complex64::new(0.0, t).exp()).collect();
+            (poles, 1.0)
+        }
+        FilterDesign::Chebyshev => {
+            let eps = (10f64.powf(0.1 * ripple_db) - 1.0).sqrt();
+            let mu = (1.0 / eps).asinh() / n;
+            let poles: Vec<Complex64> = thetas.iter().map(|&t| -Complex64::new(mu, t).sinh()).collect();
+            let mut gain = poles.iter().map(|&p| -p).product::<Complex64>().re;
+            if order.is_multiple_of(2) {
+                gain /= (1.0 + eps * eps).sqrt();
+            }
+            (poles, gain)
+        }
+    }
+}
+
+/// Maps analog zeros, poles and gain to the z-plane with the bilinear transform.
+fn bilinear_zpk(zeros: &[Complex64], poles: &[Complex64], gain: f64, fs: f64) -> (Vec<Complex64>, Vec<Complex64>, f64) {
+    let fs2 = 2.0 * fs;
+    let mut z_zeros: Vec<Complex64> = zeros.iter().map(|&z| (fs2 + z) / (fs2 - z)).collect();
+    let z_poles: Vec<Complex64> = poles.iter().map(|&p| (fs2 + p) / (fs2 - p)).collect();
+    // Zeros at infinity map to the Nyquist frequency
+    z_zeros.resize(poles.len(), Complex64::new(-1.0, 0.0));
+
+    let num: Complex64 = zeros.iter().map(|&z| fs2 - z).product();
+    let den: Complex64 = poles.iter().map(|&p| fs2 - p).product();
+    (z_zeros, z_poles, gain * (num / den).re)
+}
+
+/// Groups digital zeros and poles into second-order sections. Complex poles are paired with
+/// their conjugates; the (real) zeros at z = +1 and z = -1 are spread across the sections.
+fn zpk_to_sos(zeros: &[Complex64], poles: &[Complex64], gain: f64) -> Vec<Biquad> {
+    let is_real = |c: &Complex64| c.im.abs() <= 1e-10 * c.norm().max(1.0);
+
+    let mut pole_pairs: Vec<(Complex64, Option<Complex64>)> = Vec::new();
+    let mut real_poles: Vec<f64> = Vec::new();
+    for p in poles {
+        if is_real(p) {
+            real_poles.push(p.re);
+        } else if p.im > 0.0 {
+            pole_pairs.push((*p, Some(p.conj())));
+        }
+    }
+    real_poles.sort_by(|a, b| a.total_cmp(b));
+    for pair in real_poles.chunks(2) {
+        pole_pairs.push((Complex64::new(pair[0], 0.0), pair.get(1).map(|&p| Complex64::new(p, 0.0))));
+    }
+
+    let mut zeros_pos: Vec<f64> = zeros.iter().filter(|z| z.re > 0.0).map(|z| z.re).collect();
+    let mut zeros_neg: Vec<f64> = zeros.iter().filter(|z| z.re <= 0.0).map(|z| z.re).collect();
+
+    let mut sections: Vec<Biquad> = pole_pairs
+        .iter()
+        .map(|&(p1, p2)| {
+            let a = match p2 {
+                Some(p2) => [1.0, -(p1 + p2).re, (p1 * p2).re],
+                None => [1.0, -p1.re, 0.0],
+            };
+            let n_zeros = if p2.is_some() { 2 } else { 1 };
+            let mut section_zeros: Vec<f64> = Vec::with_capacity(2);
+            for _ in 0..n_zeros {
+                let take_pos = match (zeros_pos.is_empty(), zeros_neg.is_empty()) {
+                    (false, true) => true,
+                    (true, _) => false,
+                    _ => section_zeros.last().is_none_or(|&z| z <= 0.0),
+                };
+                let zero = if take_pos { zeros_pos.pop() } else { zeros_neg.pop() };
+                section_zeros.extend(zero);
+            }
+            let b = match section_zeros.as_slice() {
+                [z1, z2] => [1.0, -(z1 + z2), z1 * z2],
+                [z1] => [1.0, -z1, 0.0],
+                _ => [1.0, 0.0, 0.0],
+            };
+            Biquad { b, a }
+        })
+        .collect();
+
+    if let Some(first) = sections.first_mut() {
+        for coeff in first.b.iter_mut() {
+            *coeff *= gain;
+        }
+    }
+    sections
+}
+
+/// Filters a signal in-place through cascaded sections (transposed direct form II).
+/// `initial_states` gives the starting state of each section; otherwise the filter starts at rest.
+fn sosfilt(sections: &[Biquad], signal: &mut [f64], initial_states: Option<&[[f64; 2]]>) {
+    let mut states = initial_states.map_or_else(|| vec![[0.0; 2]; sections.len()], |states| states.to_vec());
+    sosfilt_with_states(sections, signal, &mut states);
+}
+
+/// As `sosfilt`, updating `states` to the section states at the end of the signal.
+fn sosfilt_with_states(sections: &[Biquad], signal: &mut [f64], states: &mut [[f64; 2]]) {
+    for (section, state) in sections.iter().zip(states.iter_mut()) {
+        let [b0, b1, b2] = section.b;
+        let [_, a1, a2] = section.a;
+        let [mut z0, mut z1] = *state;
+        for x in signal.iter_mut() {
+            let y = b0 * *x + z0;
+            z0 = b1 * *x - a1 * y + z1;
+            z1 = b2 * *x - a2 * y;
+            *x = y;
+        }
+        *state = [z0, z1];
+    }
+}
+
+/// Steady-state section states for a unit step input, so filtering can start without a transient.
+fn sosfilt_zi(sections: &[Biquad]) -> Vec<[f64; 2]> {
+    let mut scale = 1.0;
+    sections
+        .iter()
+        .map(|section| {
+            let [b0, b1, b2] = section.b;
+            let [_, a1, a2] = section.a;
+            let dc_gain = (b0 + b1 + b2) / (1.0 + a1 + a2);
+            let z1 = b2 - a2 * dc_gain;
+            let z0 = b1 - a1 * dc_gain + z1;
+            let zi = [z0 * scale, z1 * scale];
+            scale *= dc_gain;
+            zi
+        })
+        .collect()
+}
+
+/// Zero-phase filtering: forward and backward passes over an odd extension of the signal,
+/// with steady-state initial conditions (as MATLAB/SciPy filtfilt).
+fn filtfilt(sections: &[Biquad], signal: &mut [f64]) {
+    let n = signal.len();
+    if n < 2 {
+        return;
+    }
+    let pad = (3 * (2 * sections.len() + 1)).min(n - 1);
+    let mut extended: Vec<f64> = Vec::with_capacity(n + 2 * pad);
+    extended.extend((1..=pad).rev().map(|i| 2.0 * signal[0] - signal[i]));
+    extended.extend_from_slice(signal);
+    extended.extend((1..=pad).map(|i| 2.0 * signal[n - 1] - signal[n - 1 - i]));
+
+    let zi = sosfilt_zi(sections);
+    let scaled_zi = |x0: f64| -> Vec<[f64; 2]> { zi.iter().map(|z| [z[0] * x0, z[1] * x0]).collect() };
+
+    let forward_zi = scaled_zi(extended[0]);
+    sosfilt(sections, &mut extended, Some(&forward_zi));
+    extended.reverse();
+    let backward_zi = scaled_zi(extended[0]);
+    sosfilt(sections, &mut extended, Some(&backward_zi));
+    extended.reverse();
+
+    signal.copy_from_slice(&extended[pad..pad + n]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+
+    fn magnitude_db(sections: &[Biquad], f: f64, fs: f64) -> f64 {
+        let z = Complex64::new(0.0, -2.0 * PI * f / fs).exp();
+        let h: Complex64 = sections
+            .iter()
+            .map(|s| (s.b[0] + s.b[1] * z + s.b[2] * z * z) / (s.a[0] + s.a[1] * z + s.a[2] * z * z))
+            .product();
+        20.0 * h.norm().log10()
+    }
+
+    #[test]
+    fn test_butterworth_responses() {
+        let fs = 48000.0;
+        let lp = design_filter(&FilterDesign::Butterworth, &FilterResponse::Lowpass, 4, None, Some(1000.0), 0.0, fs).unwrap();
+        assert_eq!(lp.len(), 2);
+        assert!(magnitude_db(&lp, 1.0, fs).abs() < 1e-6);
+        assert!((magnitude_db(&lp, 1000.0, fs) + 3.0103).abs() < 1e-3);
+        assert!(magnitude_db(&lp, 10000.0, fs) < -80.0);
+
+        let hp = design_filter(&FilterDesign::Butterworth, &FilterResponse::Highpass, 3, Some(10.0), None, 0.0, fs).unwrap();
+        assert!((magnitude_db(&hp, 10.0, fs) + 3.0103).abs() < 1e-3);
+        assert!(magnitude_db(&hp, 1000.0, fs).abs() < 1e-6);
+
+        let bp = design_filter(&FilterDesign::Butterworth, &FilterResponse::Bandpass, 2, Some(500.0), Some(2000.0), 0.0, fs).unwrap();
+        assert!((magnitude_db(&bp, 500.0, fs) + 3.0103).abs() < 1e-3);
+        assert!((magnitude_db(&bp, 2000.0, fs) + 3.0103).abs() < 1e-3);
+        assert!(magnitude_db(&bp, 1000.0, fs).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_chebyshev_ripple() {
+        let fs = 48000.0;
+        let lp = design_filter(&FilterDesign::Chebyshev, &FilterResponse::Lowpass, 4, None, Some(1000.0), 1.0, fs).unwrap();
+        // Passband stays within the ripple and the corner sits at -ripple dB
+        assert!((0..100).all(|i| magnitude_db(&lp, i as f64 * 10.0, fs) > -1.0 - 1e-6));
+        assert!((magnitude_db(&lp, 1000.0, fs) + 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_filtfilt_preserves_phase() {
+        let fs = 1000.0;
+        let sections = design_filter(&FilterDesign::Butterworth, &FilterResponse::Lowpass, 4, None, Some(200.0), 0.0, fs).unwrap();
+        let original: Vec<f64> = (0..2000).map(|i| (2.0 * PI * 20.0 * i as f64 / fs).sin() + 0.5).collect();
+        let mut filtered = original.clone();
+        filtfilt(&sections, &mut filtered);
+        let max_error = original.iter().zip(&filtered).map(|(a, b)| (a - b).abs()).fold(0.0, f64::max);
+        assert!(max_error < 1e-3, "max error {}", max_error);
+    }
+
+    #[test]
+    fn test_zero_phase_filter_streams() {
+        let fs = 1000.0;
+        let sections = design_filter(&FilterDesign::Chebyshev, &FilterResponse::Highpass, 4, Some(10.0), None, 0.5, fs).unwrap();
+        let signal: Vec<f32> = (0..60_000).map(|i| ((i as f32 * 0.37).sin() + 0.3 * (i as f32 * 0.011).sin() + 0.2) * 0.5).collect();
+        let mut whole: Vec<f64> = signal.iter().map(|&x| x as f64).collect();
+        filtfilt(&sections, &mut whole);
+
+        // Blocks of uneven length, with a first block shorter than the start extension
+        for block_len in [5, 777, 65_536] {
+            let mut filter = ZeroPhaseFilter::new(sections.clone());
+            let mut streamed: Vec<f32> = signal.chunks(block_len).flat_map(|block| filter.process(block)).collect();
+            streamed.extend(filter.finish());
+            assert_eq!(streamed.len(), signal.len());
+            let max_error = whole.iter().zip(&streamed).map(|(a, &b)| (a - b as f64).abs()).fold(0.0, f64::max);
+            assert!(max_error < 1e-5, "block length {}: max error {}", block_len, max_error);
+        }
+
+        // A signal shorter than the start extension is filtered whole
+        let mut filter = ZeroPhaseFilter::new(sections.clone());
+        let mut short: Vec<f32> = filter.process(&signal[..10]);
+        short.extend(filter.finish());
+        let mut expected: Vec<f64> = signal[..10].iter().map(|&x| x as f64).collect();
+        filtfilt(&sections, &mut expected);
+        assert!(short.iter().zip(&expected).all(|(&a, b)| (a as f64 - b).abs() < 1e-5));
+    }
+
+    #[test]
+    fn test_unstable_prefilter_rejected() {
+        let config = test_config(
+            r#"prefilter_type = "butterworth"
+prefilter_low_hz = 1e-12"#,
+        );
+        // The poles of a 1e-12 Hz high-pass filter round to the unit circle
+        let error = design_prefilter(&config, 48000.0).unwrap_err();
+        assert!(error.starts_with("Pre-filter design is unstable"), "{}", error);
+
+        let config = test_config(
+            r#"prefilter_type = "butterworth"
+prefilter_low_hz = 1.0"#,
+        );
+        assert!(design_prefilter(&config, 48000.0).unwrap().is_some());
+    }
+}
@@ -1,9 +1,22 @@
 mod config;
 mod audio_io;
+mod columnar;
+mod netcdf;
+mod archive;
 mod dsp;
+mod filter;
+mod resample;
 mod analysis;
+mod provenance;
+mod qa;
+mod recorder_log;
+mod stream;
+mod sud;
+mod timestamp;
 mod utils;
+mod wav_metadata;
 mod broadband_test;
+mod calibrate;
 
 use clap::Parser;
 use std::path::PathBuf;
@@ -28,6 +41,26 @@ struct Args {
     /// Path to WAV file for broadband test (required if --broadband-test is used)
     #[arg(long)]
     test_wav: Option<PathBuf>,
+
+    /// Derive the end-to-end system sensitivity from a calibrator tone recording
+    #[arg(long)]
+    calibrate: bool,
+
+    /// Check the configured calibration against a calibrator tone instead of deriving a new one
+    #[arg(long)]
+    check_calibration: bool,
+
+    /// Path to the calibrator tone WAV file (required if --calibrate or --check-calibration is used)
+    #[arg(long)]
+    tone_wav: Option<PathBuf>,
+
+    /// Known level of the calibrator tone in dB (required if --calibrate or --check-calibration is used)
+    #[arg(long)]
+    reference_spl: Option<f64>,
+
+    /// Expected calibrator tone frequency in Hz (default: strongest tone in the recording)
+    #[arg(long)]
+    tone_frequency: Option<f64>,
 }
 
 fn main() {
@@ -58,6 +91,28 @@ fn main() {
         }
     }
 
+    // Check if we should derive or check a calibration from a tone recording
+    if args.calibrate || args.check_calibration {
+        let (tone_wav, reference_spl) = match (&args.tone_wav, args.reference_spl) {
+            (Some(path), Some(spl)) => (path, spl),
+            _ => {
+                eprintln!("Error: --tone-wav and --reference-spl are required when using --calibrate or --check-calibration");
+                process::exit(1);
+            }
+        };
+
+        match calibrate::run_calibration(tone_wav, &args.config, reference_spl, args.tone_frequency, args.check_calibration) {
+            Ok(_) => {
+                println!("Calibration completed successfully.");
+                return;
+            },
+            Err(e) => {
+                eprintln!("Calibration failed: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+
     // Load configuration
     let config_path = args.config;
     let config = match config::load_config(&config_path) {
@@ -84,7 +139,9 @@ fn main() {
     }
 
     // Check if input is file or directory and call appropriate handler
-    let result = if input_path.is_file() {
+    let result = if input_path.is_file() && archive::is_archive(&input_path) {
+        analysis::process_directory(&input_path, &config)
+    } else if input_path.is_file() {
         analysis::process_single_file(&input_path, &config)
     } else if input_path.is_dir() {
         analysis::process_directory(&input_path, &config)
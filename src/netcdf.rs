@@ -0,0 +1,531 @@
+//! NetCDF versions of the output tables, following the CF-1.8 and ACDD-1.3 conventions and the
+//! layout of the passive acoustic sound level products archived at NCEI (e.g. MANTA): a `psd`
+//! (time, frequency) or `spl` (time) variable with coordinate variables, units and fill values,
+//! and global attributes for the analysis, the calibration, and the instrument and deployment
+//! (taken from the `[netcdf_attributes]` table of the configuration).
+//!
+//! Files are NetCDF-4 (HDF5) when built with the `netcdf4` cargo feature, which links the
+//! netCDF-C and HDF5 libraries. The default build writes the classic 64-bit offset format
+//! (CDF-2) in pure Rust instead, with the same variables and attributes; `nccopy -k nc4`
+//! converts those files where NetCDF-4 is required.
+
+use crate::config::{AnalysisConfig, AnalysisType, Environment};
+use crate::provenance::{self, RecordingProvenance};
+use crate::qa;
+use crate::timestamp::{RowTime, TimeZoneSpec};
+use crate::utils::{self, Calibration};
+
+use chrono::Utc;
+use ndarray::Array2;
+use netcdf3::{DataSet, FileWriter, Version, NC_FILL_F32, NC_FILL_F64};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+const TIME_UNITS: &str = "seconds since 1970-01-01T00:00:00Z";
+
+/// Global attributes that the NCEI passive acoustic archive (MANTA) expects to describe the
+/// instrument, platform, deployment and location; none of them can be derived from the audio.
+const ARCHIVE_ATTRIBUTES: [&str; 7] = [
+    "instrument",
+    "platform",
+    "deployment",
+    "geospatial_lat_min",
+    "geospatial_lat_max",
+    "geospatial_lon_min",
+    "geospatial_lon_max",
+];
+
+enum AttrValue {
+    Text(String),
+    Int(Vec<i32>),
+    Double(Vec<f64>),
+}
+
+impl AttrValue {
+    /// Converts a value from the `[netcdf_attributes]` table. Strings, numbers and booleans (as
+    /// "true"/"false") are accepted, and arrays of numbers.
+    fn from_toml(value: &toml::Value) -> Option<AttrValue> {
+        match value {
+            toml::Value::String(s) => Some(AttrValue::Text(s.clone())),
+            toml::Value::Boolean(b) => Some(AttrValue::Text(b.to_string())),
+            toml::Value::Integer(i) => Some(i32::try_from(*i).map_or(AttrValue::Double(vec![*i as f64]), |i| AttrValue::Int(vec![i]))),
+            toml::Value::Float(f) => Some(AttrValue::Double(vec![*f])),
+            toml::Value::Array(values) => values
+                .iter()
+                .map(|v| v.as_float().or_else(|| v.as_integer().map(|i| i as f64)))
+                .collect::<Option<Vec<f64>>>()
+                .filter(|values| !values.is_empty())
+                .map(AttrValue::Double),
+            _ => None,
+        }
+    }
+}
+
+/// Checks the `[netcdf_attributes]` table of the configuration: names must be valid NetCDF
+/// names and values strings, numbers, booleans or arrays of numbers.
+pub fn check_attributes(attributes: &BTreeMap<String, toml::Value>) -> Result<(), String> {
+    for (name, value) in attributes {
+        if !netcdf3::is_valid_name(name) {
+            return Err(format!("netcdf_attributes: '{}' is not a valid NetCDF attribute name", name));
+        }
+        if AttrValue::from_toml(value).is_none() {
+            return Err(format!("netcdf_attributes: '{}' must be a string, number, boolean or array of numbers", name));
+        }
+    }
+    Ok(())
+}
+
+/// Lists the attributes expected by the NCEI archive that the `[netcdf_attributes]` table lacks.
+pub fn missing_archive_attributes(attributes: &BTreeMap<String, toml::Value>) -> Vec<&'static str> {
+    ARCHIVE_ATTRIBUTES.into_iter().filter(|name| !attributes.contains_key(*name)).collect()
+}
+
+/// Units of the output levels in ASCII, as NetCDF tools expect (e.g. "dB re 1 uPa^2/Hz").
+fn ascii_units(config: &AnalysisConfig) -> String {
+    utils::level_units(config).replace('µ', "u").replace('²', "^2")
+}
+
+/// Global attributes: conventions and provenance, time coverage, the analysis settings, the
+/// calibration and the storage format of the `recordings`, followed by (and overridden by) the
+/// `[netcdf_attributes]` of the configuration.
+fn global_attributes(
+    times: &[RowTime],
+    config: &AnalysisConfig,
+    recordings: &[&RecordingProvenance],
+) -> Result<Vec<(String, AttrValue)>, String> {
+    let text = |name: &str, value: String| (name.to_string(), AttrValue::Text(value));
+    let double = |name: &str, value: f64| (name.to_string(), AttrValue::Double(vec![value]));
+    let product = match config.analysis_type {
+        AnalysisType::Psd => "power spectral density",
+        AnalysisType::Broadband => "broadband sound pressure level",
+    };
+    let environment = match config.environment {
+        Environment::Air => "air",
+        Environment::Wat => "water",
+    };
+    let now = Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string();
+    let source = format!("pamguide_rust {}", env!("CARGO_PKG_VERSION"));
+
+    let mut attributes = vec![
+        text("Conventions", "CF-1.8, ACDD-1.3".to_string()),
+        text("title", format!("Passive acoustic {} in {}", product, environment)),
+        text("source", source.clone()),
+        text("history", format!("{} created by {}", now, source)),
+        text("date_created", now),
+    ];
+    let absolute: Vec<_> = times
+        .iter()
+        .filter_map(|time| match time {
+            RowTime::Absolute(t) => Some(*t),
+            RowTime::Relative(_) => None,
+        })
+        .collect();
+    if let (Some(first), Some(last)) = (absolute.first(), absolute.last()) {
+        attributes.push(text("time_coverage_start", TimeZoneSpec::Utc.format(*first)));
+        attributes.push(text("time_coverage_end", TimeZoneSpec::Utc.format(*last)));
+    }
+    attributes.extend([
+        text("analysis_type", product.to_string()),
+        text("environment", environment.to_string()),
+        text("window_type", format!("{:?}", config.window_type).to_lowercase()),
+        double("window_length", config.window_length),
+        text("window_unit", format!("{:?}", config.window_unit).to_lowercase()),
+        double("overlap_percentage", config.overlap_percentage),
+        double("low_cutoff_hz", config.low_cutoff),
+        double("high_cutoff_hz", config.high_cutoff),
+        text("calibrated", config.calibrated.to_string()),
+    ]);
+    if config.calibrated {
+        let calibration = Calibration::from_config(config)?;
+        if let Some(calibration_type) = &config.calibration_type {
+            attributes.push(text("calibration_type", format!("{:?}", calibration_type).to_uppercase()));
+        }
+        if let Calibration::Scalar(sensitivity_db) = calibration {
+            attributes.push(double("system_sensitivity_db", sensitivity_db));
+        }
+        attributes.push(text("calibration", calibration.describe()));
+    }
+    for (name, value) in provenance::input_format_metadata(recordings) {
+        attributes.push(text(name, value));
+    }
+
+    for (name, value) in &config.netcdf_attributes {
+        let value = AttrValue::from_toml(value).ok_or_else(|| format!("Unsupported value for NetCDF attribute '{}'", name))?;
+        match attributes.iter_mut().find(|(existing, _)| existing == name) {
+            Some(attribute) => attribute.1 = value,
+            None => attributes.push((name.clone(), value)),
+        }
+    }
+    Ok(attributes)
+}
+
+/// The values of a NetCDF variable, in row-major order.
+enum VarValues {
+    F64(Vec<f64>),
+    F32(Vec<f32>),
+    I32(Vec<i32>),
+}
+
+/// The values of the variables of a data set, by name, in the order they are written.
+type DataSetValues = Vec<(&'static str, VarValues)>;
+
+/// Builds the NetCDF data set of an output table (a header row of frequencies, then one row of
+/// levels per time, as for `write_csv`), with the values of each of its variables. Times are
+/// seconds since 1970 (UTC), or seconds from the start of the file when the start time is
+/// unknown. A table mixing the two (a batch in which only some files have start times) is laid
+/// out along a `row` dimension instead, with `time` and `time_offset` auxiliary coordinates,
+/// each filled where the other applies, as CF does not allow missing coordinate values.
+fn output_data_set(
+    data: &Array2<f64>,
+    times: &[RowTime],
+    qa_flags: Option<&[u32]>,
+    config: &AnalysisConfig,
+    recordings: &[&RecordingProvenance],
+) -> Result<(DataSet, DataSetValues), Box<dyn std::error::Error>> {
+    let absolute = times.iter().all(|time| matches!(time, RowTime::Absolute(_)));
+    let relative = times.iter().all(|time| matches!(time, RowTime::Relative(_)));
+    let mixed = !absolute && !relative;
+    let epoch_secs = |time: &RowTime| match time {
+        RowTime::Absolute(t) => t.timestamp() as f64 + t.timestamp_subsec_nanos() as f64 * 1e-9,
+        RowTime::Relative(_) => NC_FILL_F64,
+    };
+    let offset_secs = |time: &RowTime| match time {
+        RowTime::Absolute(_) => NC_FILL_F64,
+        RowTime::Relative(offset) => *offset,
+    };
+    let mut values = Vec::new();
+
+    let mut data_set = DataSet::new();
+    let row_dim = if mixed { "row" } else { "time" };
+    data_set.set_unlimited_dim(row_dim, times.len())?;
+    if !relative {
+        data_set.add_var_f64("time", &[row_dim])?;
+        data_set.add_var_attr_string("time", "standard_name", "time")?;
+        data_set.add_var_attr_string("time", "long_name", "start time of the analysis row")?;
+        data_set.add_var_attr_string("time", "units", TIME_UNITS)?;
+        data_set.add_var_attr_string("time", "calendar", "standard")?;
+        data_set.add_var_attr_string("time", "axis", "T")?;
+        values.push(("time", VarValues::F64(times.iter().map(epoch_secs).collect())));
+    }
+    if relative || mixed {
+        let name = if mixed { "time_offset" } else { "time" };
+        data_set.add_var_f64(name, &[row_dim])?;
+        data_set.add_var_attr_string(name, "long_name", "start time of the analysis row from the start of the recording")?;
+        data_set.add_var_attr_string(name, "units", "s")?;
+        if !mixed {
+            data_set.add_var_attr_string(name, "standard_name", "time")?;
+            data_set.add_var_attr_string(name, "axis", "T")?;
+        }
+        values.push((name, VarValues::F64(times.iter().map(offset_secs).collect())));
+    }
+    if mixed {
+        data_set.add_var_attr_f64("time", "_FillValue", vec![NC_FILL_F64])?;
+        data_set.add_var_attr_f64("time_offset", "_FillValue", vec![NC_FILL_F64])?;
+    }
+
+    let level_dims: Vec<&str> = match config.analysis_type {
+        AnalysisType::Psd => {
+            data_set.add_fixed_dim("frequency", data.ncols())?;
+            data_set.add_var_f64("frequency", &["frequency"])?;
+            data_set.add_var_attr_string("frequency", "long_name", "frequency")?;
+            data_set.add_var_attr_string("frequency", "standard_name", "sound_frequency")?;
+            data_set.add_var_attr_string("frequency", "units", "Hz")?;
+            data_set.add_var_attr_string("frequency", "axis", "Y")?;
+            values.push(("frequency", VarValues::F64(data.row(0).to_vec())));
+            vec![row_dim, "frequency"]
+        }
+        AnalysisType::Broadband => vec![row_dim],
+    };
+
+    if let Some(flags) = qa_flags {
+        let masks: Vec<i32> = qa::FLAG_NAMES.iter().map(|(flag, _)| *flag as i32).collect();
+        let meanings: Vec<&str> = qa::FLAG_NAMES.iter().map(|(_, name)| *name).collect();
+        data_set.add_var_i32("quality_flag", &[row_dim])?;
+        data_set.add_var_attr_string("quality_flag", "long_name", "recording quality flags")?;
+        data_set.add_var_attr_i32("quality_flag", "flag_masks", masks)?;
+        data_set.add_var_attr_string("quality_flag", "flag_meanings", meanings.join(" "))?;
+        values.push(("quality_flag", VarValues::I32(flags.iter().map(|&f| f as i32).collect())));
+    }
+
+    // The level variable is defined last, so the small variables come first in the file
+    let (name, long_name) = match config.analysis_type {
+        AnalysisType::Psd => ("psd", "power spectral density"),
+        AnalysisType::Broadband => ("spl", "broadband sound pressure level"),
+    };
+    data_set.add_var_f32(name, &level_dims)?;
+    data_set.add_var_attr_string(name, "long_name", long_name)?;
+    data_set.add_var_attr_string(name, "units", ascii_units(config))?;
+    // Missing levels (NaN, e.g. gap-filled rows) are written as the default NetCDF fill value, as a
+    // NaN attribute would break variable lookups in netcdf3
+    data_set.add_var_attr_f32(name, "_FillValue", vec![NC_FILL_F32])?;
+    if config.analysis_type == AnalysisType::Broadband {
+        data_set.add_var_attr_f64(name, "low_frequency_hz", vec![config.low_cutoff])?;
+        data_set.add_var_attr_f64(name, "high_frequency_hz", vec![config.high_cutoff])?;
+    }
+    if mixed {
+        data_set.add_var_attr_string(name, "coordinates", "time time_offset")?;
+    }
+    if qa_flags.is_some() {
+        data_set.add_var_attr_string(name, "ancillary_variables", "quality_flag")?;
+    }
+    let levels: Vec<f32> = data
+        .rows()
+        .into_iter()
+        .skip(1)
+        .flat_map(|row| row.to_vec())
+        .map(|v| if v.is_nan() { NC_FILL_F32 } else { v as f32 })
+        .collect();
+    values.push((name, VarValues::F32(levels)));
+
+    for (name, value) in global_attributes(times, config, recordings)? {
+        match value {
+            AttrValue::Text(text) => data_set.add_global_attr_string(&name, text)?,
+            AttrValue::Int(values) => data_set.add_global_attr_i32(&name, values)?,
+            AttrValue::Double(values) => data_set.add_global_attr_f64(&name, values)?,
+        }
+    }
+    Ok((data_set, values))
+}
+
+/// Writes an output table as a NetCDF file: NetCDF-4 when built with the `netcdf4` feature,
+/// otherwise classic 64-bit offset (CDF-2).
+pub fn write_netcdf(
+    path: &Path,
+    data: &Array2<f64>,
+    times: &[RowTime],
+    qa_flags: Option<&[u32]>,
+    config: &AnalysisConfig,
+    recordings: &[&RecordingProvenance],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (data_set, values) = output_data_set(data, times, qa_flags, config, recordings)?;
+    #[cfg(feature = "netcdf4")]
+    {
+        netcdf4::write(path, &data_set, &values)
+    }
+    #[cfg(not(feature = "netcdf4"))]
+    {
+        write_cdf2(path, &data_set, &values)
+    }
+}
+
+/// Writes a data set in the classic 64-bit offset format with the netcdf3 crate.
+#[cfg_attr(feature = "netcdf4", allow(dead_code))]
+fn write_cdf2(path: &Path, data_set: &DataSet, values: &[(&str, VarValues)]) -> Result<(), Box<dyn std::error::Error>> {
+    // netcdf3's write errors do not implement Display
+    let write_error = |e: netcdf3::WriteError| format!("Failed to write NetCDF file {}: {:?}", path.display(), e);
+
+    let mut writer = FileWriter::open(path).map_err(write_error)?;
+    writer.set_def(data_set, Version::Offset64Bit, 0).map_err(write_error)?;
+    for (name, value) in values {
+        match value {
+            VarValues::F64(v) => writer.write_var_f64(name, v),
+            VarValues::F32(v) => writer.write_var_f32(name, v),
+            VarValues::I32(v) => writer.write_var_i32(name, v),
+        }
+        .map_err(write_error)?;
+    }
+    writer.close().map_err(write_error)?;
+    Ok(())
+}
+
+/// NetCDF-4 (HDF5) output through the netCDF-C library, for archives such as NCEI's that
+/// require it. The data set built for the classic format is copied definition by definition,
+/// so both formats have the same variables and attributes.
+#[cfg(feature = "netcdf4")]
+mod netcdf4 {
+    use super::VarValues;
+    use netcdf3::{DataSet, DataType};
+    use std::path::Path;
+
+    pub fn write(path: &Path, data_set: &DataSet, values: &[(&str, VarValues)]) -> Result<(), Box<dyn std::error::Error>> {
+        let mut file = netcdf::create(path)?;
+        for dim in data_set.get_dims() {
+            if dim.is_unlimited() {
+                file.add_unlimited_dimension(&dim.name())?;
+            } else {
+                file.add_dimension(&dim.name(), dim.size())?;
+            }
+        }
+        for attr in data_set.get_global_attrs() {
+            match attr.data_type() {
+                DataType::U8 => file.add_attribute(attr.name(), attr.get_as_string().unwrap_or_default())?,
+                DataType::I32 => file.add_attribute(attr.name(), attr.get_i32().unwrap_or_default().to_vec())?,
+                _ => file.add_attribute(attr.name(), attr.get_f64().unwrap_or_default().to_vec())?,
+            };
+        }
+        for (name, value) in values {
+            let var = data_set.get_var(name).ok_or_else(|| format!("No NetCDF variable {}", name))?;
+            let dims = var.dim_names();
+            let dims: Vec<&str> = dims.iter().map(String::as_str).collect();
+            let mut variable = match value {
+                VarValues::F64(_) => file.add_variable::<f64>(name, &dims)?,
+                VarValues::F32(_) => file.add_variable::<f32>(name, &dims)?,
+                VarValues::I32(_) => file.add_variable::<i32>(name, &dims)?,
+            };
+            for attr in var.get_attrs() {
+                // The fill value is a property of the variable rather than an attribute in NetCDF-4
+                match (attr.name(), attr.data_type()) {
+                    ("_FillValue", DataType::F32) => variable.set_fill_value(attr.get_f32().unwrap_or_default()[0])?,
+                    ("_FillValue", _) => variable.set_fill_value(attr.get_f64().unwrap_or_default()[0])?,
+                    (_, DataType::U8) => {
+                        variable.put_attribute(attr.name(), attr.get_as_string().unwrap_or_default())?;
+                    }
+                    (_, DataType::I32) => {
+                        variable.put_attribute(attr.name(), attr.get_i32().unwrap_or_default().to_vec())?;
+                    }
+                    (_, DataType::F32) => {
+                        variable.put_attribute(attr.name(), attr.get_f32().unwrap_or_default().to_vec())?;
+                    }
+                    _ => {
+                        variable.put_attribute(attr.name(), attr.get_f64().unwrap_or_default().to_vec())?;
+                    }
+                }
+            }
+            let shape: Vec<usize> = var.get_dims().iter().map(|dim| dim.size()).collect();
+            let extents: Vec<std::ops::Range<usize>> = shape.iter().map(|&len| 0..len).collect();
+            match value {
+                VarValues::F64(v) => variable.put_values(v, extents)?,
+                VarValues::F32(v) => variable.put_values(v, extents)?,
+                VarValues::I32(v) => variable.put_values(v, extents)?,
+            }
+        }
+        file.close()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use chrono::TimeZone;
+    use ndarray::array;
+    #[cfg(not(feature = "netcdf4"))]
+    use {crate::audio_io::AudioInfo, netcdf3::FileReader};
+
+    #[test]
+    #[cfg(not(feature = "netcdf4"))]
+    fn test_write_netcdf_psd() {
+        let config = test_config(
+            r#"calibrated = false
+
+[netcdf_attributes]
+title = "Test deployment"
+instrument = "SoundTrap ST600"
+geospatial_lat_min = -41.25"#,
+        );
+        let data = array![[10.0, 20.0], [-30.5, -40.25], [f64::NAN, -41.0]];
+        let start = Utc.with_ymd_and_hms(2024, 7, 17, 16, 47, 21).unwrap();
+        let times = [RowTime::Absolute(start), RowTime::Absolute(start + chrono::Duration::milliseconds(1500))];
+        let path = std::env::temp_dir().join(format!("pamguide_netcdf_test_{}.nc", std::process::id()));
+        let recording = RecordingProvenance {
+            format: Some(AudioInfo {
+                container: "SUD",
+                encoding: "X3",
+                bits_per_sample: 16,
+                valid_bits: 16,
+                sample_rate: 48000,
+                num_samples: 96000,
+                normalisation: "x / 32767 (signed 16-bit)".to_string(),
+            }),
+            ..Default::default()
+        };
+        write_netcdf(&path, &data, &times, Some(&[0, qa::FLAG_CLIPPING]), &config, &[&recording]).unwrap();
+
+        let mut reader = FileReader::open(&path).unwrap();
+        let data_set = reader.data_set();
+        assert_eq!(data_set.dim_size("frequency"), Some(2));
+        assert_eq!(data_set.get_var_attr_as_string("time", "units").unwrap(), TIME_UNITS);
+        assert_eq!(data_set.get_var_attr_as_string("psd", "units").unwrap(), "dB (uncalibrated)");
+        assert_eq!(data_set.get_global_attr_as_string("title").unwrap(), "Test deployment");
+        assert_eq!(data_set.get_global_attr_as_string("instrument").unwrap(), "SoundTrap ST600");
+        assert_eq!(data_set.get_global_attr_f64("geospatial_lat_min"), Some(&[-41.25][..]));
+        assert_eq!(data_set.get_global_attr_as_string("time_coverage_end").unwrap(), "2024-07-17T16:47:22.500Z");
+        assert_eq!(data_set.get_global_attr_as_string("input_encoding").unwrap(), "X3");
+        assert_eq!(data_set.get_global_attr_as_string("input_normalisation").unwrap(), "x / 32767 (signed 16-bit)");
+
+        assert_eq!(reader.read_var_f64("time").unwrap(), vec![start.timestamp() as f64, start.timestamp() as f64 + 1.5]);
+        assert_eq!(reader.read_var_f64("frequency").unwrap(), vec![10.0, 20.0]);
+        assert_eq!(reader.read_var_i32("quality_flag").unwrap(), vec![0, 1]);
+        let psd = reader.read_var_f32("psd").unwrap();
+        assert_eq!((psd[0], psd[1], psd[3]), (-30.5, -40.25, -41.0));
+        assert_eq!(psd[2], NC_FILL_F32);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[cfg(not(feature = "netcdf4"))]
+    fn test_write_netcdf_mixed_start_times() {
+        let config = test_config(r#"analysis_type = "broadband""#);
+        // A batch summary in which only the first file has a start time
+        let data = array![[0.0], [101.5], [102.0]];
+        let start = Utc.with_ymd_and_hms(2024, 7, 17, 16, 47, 21).unwrap();
+        let times = [RowTime::Absolute(start), RowTime::Relative(0.5)];
+        let path = std::env::temp_dir().join(format!("pamguide_netcdf_mixed_test_{}.nc", std::process::id()));
+        write_netcdf(&path, &data, &times, None, &config, &[]).unwrap();
+
+        let mut reader = FileReader::open(&path).unwrap();
+        let data_set = reader.data_set();
+        assert_eq!(data_set.dim_size("row"), Some(2));
+        assert!(!data_set.has_dim("time"));
+        assert_eq!(data_set.get_var_attr_as_string("spl", "coordinates").unwrap(), "time time_offset");
+        assert_eq!(reader.read_var_f64("time").unwrap(), vec![start.timestamp() as f64, NC_FILL_F64]);
+        assert_eq!(reader.read_var_f64("time_offset").unwrap(), vec![NC_FILL_F64, 0.5]);
+        assert_eq!(reader.read_var_f32("spl").unwrap(), vec![101.5, 102.0]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "netcdf4")]
+    fn test_write_netcdf4_round_trip() {
+        use netcdf::AttributeValue;
+
+        let config = test_config(
+            r#"calibrated = false
+
+[netcdf_attributes]
+instrument = "SoundTrap ST600"
+geospatial_lat_min = -41.25"#,
+        );
+        let data = array![[10.0, 20.0], [-30.5, -40.25], [f64::NAN, -41.0]];
+        let start = Utc.with_ymd_and_hms(2024, 7, 17, 16, 47, 21).unwrap();
+        let times = [RowTime::Absolute(start), RowTime::Absolute(start + chrono::Duration::milliseconds(1500))];
+        let path = std::env::temp_dir().join(format!("pamguide_netcdf4_test_{}.nc", std::process::id()));
+        write_netcdf(&path, &data, &times, Some(&[0, qa::FLAG_CLIPPING]), &config, &[]).unwrap();
+
+        // Read back through netCDF-C, which also confirms the file is NetCDF-4 rather than CDF-2
+        let file = netcdf::open(&path).unwrap();
+        assert_eq!(file.dimension("frequency").unwrap().len(), 2);
+        let global = |name: &str| file.attribute(name).unwrap().value().unwrap();
+        assert_eq!(global("instrument"), AttributeValue::Str("SoundTrap ST600".to_string()));
+        assert_eq!(global("geospatial_lat_min"), AttributeValue::Double(-41.25));
+        assert_eq!(global("time_coverage_end"), AttributeValue::Str("2024-07-17T16:47:22.500Z".to_string()));
+
+        let time = file.variable("time").unwrap();
+        assert_eq!(time.attribute_value("units").unwrap().unwrap(), AttributeValue::Str(TIME_UNITS.to_string()));
+        assert_eq!(time.get_values::<f64, _>(..).unwrap(), vec![start.timestamp() as f64, start.timestamp() as f64 + 1.5]);
+        assert_eq!(file.variable("frequency").unwrap().get_values::<f64, _>(..).unwrap(), vec![10.0, 20.0]);
+        assert_eq!(file.variable("quality_flag").unwrap().get_values::<i32, _>(..).unwrap(), vec![0, 1]);
+        let psd = file.variable("psd").unwrap();
+        assert_eq!(psd.attribute_value("units").unwrap().unwrap(), AttributeValue::Str("dB (uncalibrated)".to_string()));
+        assert_eq!(psd.fill_value::<f32>().unwrap(), Some(NC_FILL_F32));
+        assert_eq!(psd.get_values::<f32, _>(..).unwrap(), vec![-30.5, -40.25, NC_FILL_F32, -41.0]);
+        drop(file);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_missing_archive_attributes() {
+        let config = test_config(
+            r#"[netcdf_attributes]
+instrument = "SoundTrap ST600"
+platform = "mooring"
+geospatial_lat_min = -41.25
+geospatial_lat_max = -41.25"#,
+        );
+        assert_eq!(
+            missing_archive_attributes(&config.netcdf_attributes),
+            vec!["deployment", "geospatial_lon_min", "geospatial_lon_max"]
+        );
+    }
+}
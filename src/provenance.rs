@@ -0,0 +1,196 @@
+//! JSON provenance sidecars for the output tables: the resolved configuration, the software
+//! version, the values derived from it for each recording (window and step in samples, FFT
+//! length, noise bandwidth, bin spacing, selected frequency range, sensitivity), the size
+//! and SHA-256 hash of every input file and how its samples were stored and normalised, so
+//! that any output can be traced back to its inputs and settings.
+
+use crate::audio_io::AudioInfo;
+use crate::config::AnalysisConfig;
+
+use chrono::Utc;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Values derived from the configuration in analysing a recording.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct AnalysisParameters {
+    pub sample_rate_hz: f64,         // After resampling
+    pub sensitivity_db: Option<f64>, // None with a frequency-dependent sensitivity curve
+    pub calibration: String,
+    pub n_window_samples: usize,
+    pub n_step: usize,
+    pub n_fft: usize,
+    pub noise_bw: f64,               // Noise power bandwidth of the window, in bins
+    pub delf: f64,                   // Hz between FFT bins
+    pub frequency_range_hz: [f64; 2], // First and last selected FFT bin
+    pub n_frequencies: usize,
+}
+
+/// An input file (or archive entry), identified by its size and SHA-256 hash.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct InputFile {
+    pub path: String,
+    pub size_bytes: u64,
+    pub sha256: String,
+}
+
+/// Provenance of the analysis of one recording.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct RecordingProvenance {
+    pub input: Option<InputFile>, // Only hashed when provenance sidecars are written
+    pub format: Option<AudioInfo>, // How the input's samples were stored and normalised
+    pub analysis: AnalysisParameters,
+}
+
+#[derive(Serialize)]
+struct Sidecar<'a> {
+    software: &'static str,
+    version: &'static str,
+    processed_at: String,
+    outputs: Vec<String>,
+    config: &'a AnalysisConfig,
+    recordings: &'a [&'a RecordingProvenance],
+}
+
+/// A reader that hashes the bytes read through it.
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R) -> HashingReader<R> {
+        HashingReader { inner, hasher: Sha256::new(), size: 0 }
+    }
+
+    /// Reads (and hashes) the rest of the input, which the audio decoder may have left unread,
+    /// and describes it as `path`.
+    pub fn finish(mut self, path: &Path) -> io::Result<InputFile> {
+        io::copy(&mut self, &mut io::sink())?;
+        Ok(InputFile {
+            path: path.display().to_string(),
+            size_bytes: self.size,
+            sha256: format!("{:x}", self.hasher.finalize()),
+        })
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+}
+
+/// Hashes a file on disk. Inputs are hashed as they are decoded, so this is only needed to
+/// check that in tests.
+#[cfg(test)]
+pub fn hash_file(path: &Path) -> io::Result<InputFile> {
+    HashingReader::new(fs::File::open(path)?).finish(path)
+}
+
+/// The storage format of the inputs behind an output table, as metadata for the columnar and
+/// NetCDF outputs. Each value lists the distinct values over the recordings, separated by "; ".
+/// Empty if no recording's format is known.
+pub fn input_format_metadata(recordings: &[&RecordingProvenance]) -> Vec<(&'static str, String)> {
+    let formats: Vec<&AudioInfo> = recordings.iter().filter_map(|r| r.format.as_ref()).collect();
+    if formats.is_empty() {
+        return Vec::new();
+    }
+    let distinct = |field: fn(&AudioInfo) -> String| {
+        let mut values: Vec<String> = Vec::new();
+        for value in formats.iter().map(|&format| field(format)) {
+            if !values.contains(&value) {
+                values.push(value);
+            }
+        }
+        values.join("; ")
+    };
+    vec![
+        ("input_container", distinct(|f| f.container.to_string())),
+        ("input_encoding", distinct(|f| f.encoding.to_string())),
+        ("input_bits_per_sample", distinct(|f| f.bits_per_sample.to_string())),
+        ("input_valid_bits", distinct(|f| f.valid_bits.to_string())),
+        ("input_normalisation", distinct(|f| f.normalisation.clone())),
+    ]
+}
+
+/// Writes the provenance sidecar of the output tables `outputs` to `path`.
+pub fn write_sidecar(
+    path: &Path,
+    outputs: &[PathBuf],
+    config: &AnalysisConfig,
+    recordings: &[&RecordingProvenance],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let sidecar = Sidecar {
+        software: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        processed_at: Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        outputs: outputs.iter().map(|p| p.display().to_string()).collect(),
+        config,
+        recordings,
+    };
+    let file = io::BufWriter::new(fs::File::create(path)?);
+    serde_json::to_writer_pretty(file, &sidecar)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+
+    #[test]
+    fn test_write_sidecar() {
+        let config = test_config("");
+        let dir = std::env::temp_dir().join(format!("pamguide_provenance_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input_path = dir.join("input.bin");
+        fs::write(&input_path, b"abc").unwrap();
+        let format = AudioInfo {
+            container: "RIFF",
+            encoding: "PCM",
+            bits_per_sample: 24,
+            valid_bits: 20,
+            sample_rate: 48000,
+            num_samples: 96000,
+            normalisation: "x / 8388607 (signed 24-bit), 20 valid bits left-justified".to_string(),
+        };
+        let recording = RecordingProvenance {
+            input: Some(hash_file(&input_path).unwrap()),
+            format: Some(format.clone()),
+            analysis: AnalysisParameters { n_window_samples: 48000, n_step: 24000, ..Default::default() },
+        };
+        let sidecar_path = dir.join("output.json");
+        write_sidecar(&sidecar_path, &[dir.join("output.csv")], &config, &[&recording]).unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&sidecar_path).unwrap()).unwrap();
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["config"]["analysis_type"], "psd");
+        assert_eq!(json["config"]["window_unit"], "seconds");
+        assert_eq!(json["config"]["overlap_percentage"], 50.0);
+        let input = &json["recordings"][0]["input"];
+        assert_eq!(input["size_bytes"], 3);
+        assert_eq!(input["sha256"], "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(json["recordings"][0]["analysis"]["n_step"], 24000);
+        assert_eq!(json["recordings"][0]["format"]["valid_bits"], 20);
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Formats that differ between the recordings of a batch are listed in turn
+        let flac = RecordingProvenance {
+            format: Some(AudioInfo { container: "FLAC", normalisation: "x / 8388607 (signed 24-bit)".to_string(), valid_bits: 24, ..format }),
+            ..recording.clone()
+        };
+        let metadata = input_format_metadata(&[&recording, &flac, &recording]);
+        assert_eq!(metadata[0], ("input_container", "RIFF; FLAC".to_string()));
+        assert_eq!(metadata[2], ("input_bits_per_sample", "24".to_string()));
+        assert_eq!(metadata[3], ("input_valid_bits", "20; 24".to_string()));
+        assert!(input_format_metadata(&[&RecordingProvenance::default()]).is_empty());
+    }
+}
@@ -0,0 +1,383 @@
+use crate::config::AnalysisConfig;
+
+use std::fs;
+use std::path::Path;
+
+// Bit flags describing recording problems in a block of samples or an analysis row
+pub const FLAG_CLIPPING: u32 = 1;
+pub const FLAG_DROPOUT: u32 = 2;
+pub const FLAG_SPIKE: u32 = 4;
+pub const FLAG_DC_OFFSET: u32 = 8;
+pub const FLAG_RMS_JUMP: u32 = 16;
+
+pub const FLAG_NAMES: [(u32, &str); 5] = [
+    (FLAG_CLIPPING, "clipping"),
+    (FLAG_DROPOUT, "dropout"),
+    (FLAG_SPIKE, "spike"),
+    (FLAG_DC_OFFSET, "dc_offset"),
+    (FLAG_RMS_JUMP, "rms_jump"),
+];
+
+/// Formats a flag bitmask as a '|' separated list of names (empty if no flags are set).
+pub fn flag_names(flags: u32) -> String {
+    FLAG_NAMES
+        .iter()
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+/// Detection thresholds for the QA pass.
+#[derive(Debug, Clone, Copy)]
+pub struct QaThresholds {
+    pub block_samples: usize,
+    pub clip_level: f32,
+    pub clip_run: usize,
+    pub dropout_run: usize,
+    pub spike_factor: f32,
+    pub rms_jump_db: f64,
+    pub dc_offset: f64,
+}
+
+impl QaThresholds {
+    pub fn from_config(config: &AnalysisConfig, fs: f64) -> QaThresholds {
+        QaThresholds {
+            block_samples: ((config.qa_block_seconds * fs).round() as usize).max(1),
+            clip_level: config.qa_clip_level as f32,
+            clip_run: config.qa_clip_run.max(1),
+            dropout_run: config.qa_dropout_run.max(2),
+            spike_factor: config.qa_spike_factor as f32,
+            rms_jump_db: config.qa_rms_jump_db,
+            dc_offset: config.qa_dc_offset,
+        }
+    }
+}
+
+/// Statistics and flags for one QA block.
+#[derive(Debug, Clone, Default)]
+pub struct QaBlock {
+    pub n_samples: usize,
+    pub mean: f64,
+    pub rms: f64,
+    pub peak: f32,
+    pub clipped_samples: usize,
+    pub dropout_samples: usize,
+    pub spikes: usize,
+    pub flags: u32,
+}
+
+/// QA results for one file, as consecutive blocks of `block_seconds`.
+#[derive(Debug, Clone)]
+pub struct QaReport {
+    pub fs: f64,
+    pub block_seconds: f64,
+    pub blocks: Vec<QaBlock>,
+}
+
+impl QaReport {
+    /// Combined flags of all blocks overlapping the time span [start_secs, end_secs).
+    pub fn flags_between(&self, start_secs: f64, end_secs: f64) -> u32 {
+        if self.blocks.is_empty() || end_secs <= start_secs {
+            return 0;
+        }
+        let first = ((start_secs / self.block_seconds).floor().max(0.0) as usize).min(self.blocks.len() - 1);
+        let last = (((end_secs / self.block_seconds).ceil() as usize).saturating_sub(1)).min(self.blocks.len() - 1);
+        self.blocks[first..=last].iter().fold(0, |acc, block| acc | block.flags)
+    }
+
+    /// Whole-file statistics: a block summarising all samples, with the union of all flags.
+    pub fn summary(&self) -> QaBlock {
+        let n_samples: usize = self.blocks.iter().map(|b| b.n_samples).sum();
+        let weight = |value: fn(&QaBlock) -> f64| -> f64 {
+            self.blocks.iter().map(|b| value(b) * b.n_samples as f64).sum::<f64>() / n_samples.max(1) as f64
+        };
+        QaBlock {
+            n_samples,
+            mean: weight(|b| b.mean),
+            rms: weight(|b| b.rms * b.rms).sqrt(),
+            peak: self.blocks.iter().map(|b| b.peak).fold(0.0, f32::max),
+            clipped_samples: self.blocks.iter().map(|b| b.clipped_samples).sum(),
+            dropout_samples: self.blocks.iter().map(|b| b.dropout_samples).sum(),
+            spikes: self.blocks.iter().map(|b| b.spikes).sum(),
+            flags: self.blocks.iter().fold(0, |acc, b| acc | b.flags),
+        }
+    }
+
+    pub fn flagged_blocks(&self) -> usize {
+        self.blocks.iter().filter(|b| b.flags != 0).count()
+    }
+}
+
+/// Scans normalised samples for clipping (runs at full scale), dropouts (runs of repeated
+/// values, including digital silence), spikes, DC offset and abrupt RMS changes. Samples can be
+/// pushed in chunks of any size; runs are tracked across chunk and block boundaries.
+pub struct QaScanner {
+    thresholds: QaThresholds,
+    blocks: Vec<QaBlock>,
+    current: QaBlock,
+    sum: f64,
+    sum_sq: f64,
+    previous_rms: Option<f64>,
+    previous_sample: Option<f32>,
+    clip_run: usize,
+    repeat_run: usize,
+}
+
+impl QaScanner {
+    pub fn new(thresholds: QaThresholds) -> QaScanner {
+        QaScanner {
+            thresholds,
+            blocks: Vec::new(),
+            current: QaBlock::default(),
+            sum: 0.0,
+            sum_sq: 0.0,
+            previous_rms: None,
+            previous_sample: None,
+            clip_run: 0,
+            repeat_run: 1,
+        }
+    }
+
+    pub fn push(&mut self, samples: &[f32]) {
+        for &x in samples {
+            self.push_sample(x);
+        }
+    }
+
+    fn push_sample(&mut self, x: f32) {
+        let th = self.thresholds;
+        let abs_x = x.abs();
+
+        // Clipping: a run of samples at (or beyond) the clip level
+        if abs_x >= th.clip_level {
+            self.clip_run += 1;
+            if self.clip_run == th.clip_run {
+                self.credit_run(th.clip_run, FLAG_CLIPPING, |block| &mut block.clipped_samples);
+            } else if self.clip_run > th.clip_run {
+                self.credit_run(1, FLAG_CLIPPING, |block| &mut block.clipped_samples);
+            }
+        } else {
+            self.clip_run = 0;
+        }
+
+        // Dropouts: a run of identical values (exact zeros or a stuck converter)
+        if self.previous_sample == Some(x) {
+            self.repeat_run += 1;
+            if self.repeat_run == th.dropout_run {
+                self.credit_run(th.dropout_run, FLAG_DROPOUT, |block| &mut block.dropout_samples);
+            } else if self.repeat_run > th.dropout_run {
+                self.credit_run(1, FLAG_DROPOUT, |block| &mut block.dropout_samples);
+            }
+        } else {
+            self.repeat_run = 1;
+        }
+        self.previous_sample = Some(x);
+
+        // Spikes: samples far above the RMS level of the preceding block
+        if let Some(reference_rms) = self.previous_rms {
+            if reference_rms > 0.0 && abs_x as f64 > th.spike_factor as f64 * reference_rms {
+                self.current.spikes += 1;
+                self.current.flags |= FLAG_SPIKE;
+            }
+        }
+
+        self.current.n_samples += 1;
+        self.current.peak = self.current.peak.max(abs_x);
+        self.sum += x as f64;
+        self.sum_sq += (x as f64).powi(2);
+        if self.current.n_samples == th.block_samples {
+            self.close_block();
+        }
+    }
+
+    /// Counts the last `run` samples, ending with the sample being pushed, towards `count` of the
+    /// blocks they fall in, and flags those blocks.
+    fn credit_run(&mut self, run: usize, flag: u32, count: fn(&mut QaBlock) -> &mut usize) {
+        let in_current = run.min(self.current.n_samples + 1);
+        *count(&mut self.current) += in_current;
+        self.current.flags |= flag;
+        let mut remaining = run - in_current;
+        for block in self.blocks.iter_mut().rev() {
+            if remaining == 0 {
+                break;
+            }
+            let in_block = remaining.min(block.n_samples);
+            *count(block) += in_block;
+            block.flags |= flag;
+            remaining -= in_block;
+        }
+    }
+
+    fn close_block(&mut self) {
+        let th = self.thresholds;
+        let mut block = std::mem::take(&mut self.current);
+        let n = block.n_samples as f64;
+        block.mean = self.sum / n;
+        block.rms = (self.sum_sq / n).sqrt();
+        if block.mean.abs() > th.dc_offset {
+            block.flags |= FLAG_DC_OFFSET;
+        }
+        if let Some(previous_rms) = self.previous_rms {
+            let jump_db = 20.0 * (block.rms.max(1e-12) / previous_rms.max(1e-12)).log10();
+            if jump_db.abs() > th.rms_jump_db {
+                block.flags |= FLAG_RMS_JUMP;
+            }
+        }
+        self.previous_rms = Some(block.rms);
+        self.sum = 0.0;
+        self.sum_sq = 0.0;
+        self.blocks.push(block);
+    }
+
+    /// Completes the scan, closing any partial final block.
+    pub fn finish(mut self, fs: f64) -> QaReport {
+        if self.current.n_samples > 0 {
+            self.close_block();
+        }
+        QaReport {
+            fs,
+            block_seconds: self.thresholds.block_samples as f64 / fs,
+            blocks: self.blocks,
+        }
+    }
+}
+
+/// Prints a one-line summary of the problems found in a file.
+pub fn print_summary(report: &QaReport) {
+    let summary = report.summary();
+    if summary.flags == 0 {
+        println!("  QA: no problems detected");
+    } else {
+        println!(
+            "  QA warning: {} in {} of {} blocks ({} clipped, {} dropout samples, {} spikes)",
+            flag_names(summary.flags),
+            report.flagged_blocks(),
+            report.blocks.len(),
+            summary.clipped_samples,
+            summary.dropout_samples,
+            summary.spikes
+        );
+    }
+}
+
+fn to_dbfs(rms: f64) -> f64 {
+    20.0 * rms.max(1e-12).log10()
+}
+
+/// Writes the per-block QA results for one file.
+pub fn write_file_report(path: &Path, report: &QaReport) -> Result<(), Box<dyn std::error::Error>> {
+    let file = fs::File::create(path)?;
+    let mut wtr = csv::Writer::from_writer(file);
+    wtr.write_record(["start_s", "end_s", "mean", "rms_dbfs", "peak", "clipped_samples", "dropout_samples", "spikes", "flags"])?;
+    let mut start_sample = 0;
+    for block in &report.blocks {
+        let start_secs = start_sample as f64 / report.fs;
+        let end_secs = (start_sample + block.n_samples) as f64 / report.fs;
+        wtr.write_record([
+            format!("{:.3}", start_secs),
+            format!("{:.3}", end_secs),
+            format!("{:.6}", block.mean),
+            format!("{:.2}", to_dbfs(block.rms)),
+            format!("{:.4}", block.peak),
+            block.clipped_samples.to_string(),
+            block.dropout_samples.to_string(),
+            block.spikes.to_string(),
+            flag_names(block.flags),
+        ])?;
+        start_sample += block.n_samples;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Writes one summary row per file for a batch.
+pub fn write_batch_report(path: &Path, reports: &[(String, QaReport)]) -> Result<(), Box<dyn std::error::Error>> {
+    let file = fs::File::create(path)?;
+    let mut wtr = csv::Writer::from_writer(file);
+    wtr.write_record([
+        "file", "duration_s", "mean", "rms_dbfs", "peak", "clipped_samples", "dropout_samples", "spikes",
+        "flagged_blocks", "total_blocks", "flags",
+    ])?;
+    for (name, report) in reports {
+        let summary = report.summary();
+        wtr.write_record([
+            name.clone(),
+            format!("{:.3}", summary.n_samples as f64 / report.fs),
+            format!("{:.6}", summary.mean),
+            format!("{:.2}", to_dbfs(summary.rms)),
+            format!("{:.4}", summary.peak),
+            summary.clipped_samples.to_string(),
+            summary.dropout_samples.to_string(),
+            summary.spikes.to_string(),
+            report.flagged_blocks().to_string(),
+            report.blocks.len().to_string(),
+            flag_names(summary.flags),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thresholds() -> QaThresholds {
+        QaThresholds {
+            block_samples: 100,
+            clip_level: 0.999,
+            clip_run: 3,
+            dropout_run: 50,
+            spike_factor: 10.0,
+            rms_jump_db: 20.0,
+            dc_offset: 0.05,
+        }
+    }
+
+    fn noise(n: usize, seed: u32) -> Vec<f32> {
+        let mut state = seed;
+        (0..n)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 8) as f32 / (1u32 << 24) as f32 * 0.2 - 0.1
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_detects_problems_in_blocks() {
+        let mut audio = noise(1000, 1);
+        audio[150..155].fill(1.0); // Clipping in block 1
+        audio[298..301].fill(-1.0); // Clipping across blocks 2 and 3
+        audio[330..420].fill(0.0); // Dropout in blocks 3 and 4
+        audio[650] = 0.9; // Spike in block 6
+        for x in audio[800..900].iter_mut() {
+            *x += 0.8; // DC offset and RMS jump in block 8
+        }
+
+        let mut scanner = QaScanner::new(thresholds());
+        for chunk in audio.chunks(37) {
+            scanner.push(chunk);
+        }
+        let report = scanner.finish(100.0);
+
+        assert_eq!(report.blocks.len(), 10);
+        assert_eq!(report.blocks[0].flags, 0);
+        assert_eq!(report.blocks[1].flags & FLAG_CLIPPING, FLAG_CLIPPING);
+        assert_eq!(report.blocks[1].clipped_samples, 5);
+        // Runs crossing a block boundary are counted in the blocks their samples fall in
+        assert_eq!((report.blocks[2].clipped_samples, report.blocks[3].clipped_samples), (2, 1));
+        assert_eq!(report.blocks[2].flags & FLAG_CLIPPING, FLAG_CLIPPING);
+        assert_eq!(report.blocks[3].flags & FLAG_DROPOUT, FLAG_DROPOUT);
+        assert_eq!(report.blocks[4].flags & FLAG_DROPOUT, FLAG_DROPOUT);
+        assert_eq!((report.blocks[3].dropout_samples, report.blocks[4].dropout_samples), (70, 20));
+        assert_eq!(report.blocks[6].flags, FLAG_SPIKE);
+        assert_eq!(report.blocks[8].flags & FLAG_DC_OFFSET, FLAG_DC_OFFSET);
+        assert_eq!(report.blocks[8].flags & FLAG_RMS_JUMP, FLAG_RMS_JUMP);
+
+        // Blocks are 1 s long here, so [5.5, 7.5) covers blocks 5 to 7
+        assert_eq!(report.flags_between(5.5, 7.5), FLAG_SPIKE);
+        assert_eq!(flag_names(FLAG_CLIPPING | FLAG_SPIKE), "clipping|spike");
+    }
+}
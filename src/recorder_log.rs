@@ -0,0 +1,109 @@
+use chrono::{Duration, NaiveDateTime};
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+
+/// The sampling start time of one recording, read from a recorder's sidecar log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecorderLog {
+    pub start_time: NaiveDateTime, // UTC start of sampling
+    pub source: &'static str,
+}
+
+/// A break in sampling: `duration_secs` of time is missing before sample `sample`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleGap {
+    pub sample: u64,
+    pub duration_secs: f64,
+}
+
+/// Looks for a SoundTrap `<stem>.log.xml` next to an audio file and reads its sampling start
+/// time. Returns Ok(None) if there is no sidecar.
+pub fn find_recorder_log(audio_path: &Path) -> Result<Option<RecorderLog>, String> {
+    let stem = audio_path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    let soundtrap_path = audio_path.with_file_name(format!("{}.log.xml", stem));
+    if !soundtrap_path.is_file() {
+        return Ok(None);
+    }
+    let xml = fs::read_to_string(&soundtrap_path).map_err(|e| format!("{}: {}", soundtrap_path.display(), e))?;
+    parse_soundtrap_log(&xml)
+        .map(Some)
+        .ok_or_else(|| format!("No sampling start time in {}", soundtrap_path.display()))
+}
+
+/// The attributes of every element in an XML document, as (element, [(name, value)]).
+/// This is a lightweight scan rather than a full XML parser, which is sufficient for the
+/// flat, machine-written logs read here.
+fn xml_elements(xml: &str) -> Vec<(String, Vec<(String, String)>)> {
+    let tag_regex = Regex::new(r#"<([A-Za-z_][\w.-]*)((?:\s+[\w.:-]+\s*=\s*"[^"]*")*)\s*/?>"#).unwrap();
+    let attr_regex = Regex::new(r#"([\w.:-]+)\s*=\s*"([^"]*)""#).unwrap();
+    tag_regex
+        .captures_iter(xml)
+        .map(|caps| {
+            let attrs = attr_regex
+                .captures_iter(&caps[2])
+                .map(|a| (a[1].to_string(), a[2].to_string()))
+                .collect();
+            (caps[1].to_string(), attrs)
+        })
+        .collect()
+}
+
+/// Parses a duration with a unit suffix, e.g. "170 us", "12 ms" or "0.5 s" (microseconds
+/// if no unit is given).
+fn parse_duration_with_unit(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let split = value.find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-')).unwrap_or(value.len());
+    let number: f64 = value[..split].parse().ok()?;
+    let scale = match value[split..].trim() {
+        "" | "us" => 1e-6,
+        "ms" => 1e-3,
+        "s" => 1.0,
+        "ns" => 1e-9,
+        _ => return None,
+    };
+    Some(number * scale)
+}
+
+fn parse_iso_datetime(value: &str) -> Option<NaiveDateTime> {
+    let value = value.trim().trim_end_matches('Z');
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f"))
+        .ok()
+}
+
+/// Reads the sampling start time from a SoundTrap `.log.xml`: the WavFileHandler records give
+/// it in UTC to the second (`SamplingStartTimeUTC`) plus a sub-second part
+/// (`SamplingStartTimeSubS`, e.g. "170 us"). Sample counts and sampling gaps in the log are
+/// not read.
+pub fn parse_soundtrap_log(xml: &str) -> Option<RecorderLog> {
+    let attr = |attrs: &[(String, String)], name: &str| -> Option<String> {
+        attrs.iter().find(|(n, _)| n == name).map(|(_, v)| v.clone())
+    };
+    let start_time = xml_elements(xml).into_iter().find_map(|(_, attrs)| {
+        let start = attr(&attrs, "SamplingStartTimeUTC").and_then(|v| parse_iso_datetime(&v))?;
+        let sub_secs = attr(&attrs, "SamplingStartTimeSubS").and_then(|v| parse_duration_with_unit(&v)).unwrap_or(0.0);
+        Some(start + Duration::nanoseconds((sub_secs * 1e9).round() as i64))
+    })?;
+    Some(RecorderLog { start_time, source: "SoundTrap log" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datetime(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f").unwrap()
+    }
+
+    #[test]
+    fn test_parse_soundtrap_log() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ST>
+  <EVENT Time="2024-07-17T16:47:20"><HARDWARE_ID>5678</HARDWARE_ID></EVENT>
+  <PROC_EVENT ID="3"><WavFileHandler SamplingStartTimeLocal="2024-07-17T18:47:21" SamplingStartTimeUTC="2024-07-17T16:47:21" SamplingStartTimeSubS="250000 us" /></PROC_EVENT>
+</ST>"#;
+        let log = parse_soundtrap_log(xml).unwrap();
+        assert_eq!(log.start_time, datetime("2024-07-17 16:47:21.25"));
+    }
+}
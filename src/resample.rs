@@ -0,0 +1,176 @@
+use crate::dsp;
+use rayon::prelude::*;
+use std::f64::consts::PI;
+
+/// Kaiser window beta for the anti-aliasing filter (as SciPy resample_poly)
+const KAISER_BETA: f64 = 5.0;
+/// Filter half-length in multiples of the larger of the up/down factors
+const HALF_LENGTH_FACTOR: usize = 10;
+
+/// Resamples a signal from `fs_in` to `fs_out` with a polyphase FIR filter, taking the input
+/// block by block and keeping only the input history that later output samples depend on.
+/// The rate change is reduced to the smallest integer ratio up/down; the signal is upsampled by
+/// `up`, low-pass filtered and downsampled by `down`, computing only the output samples that
+/// are kept. The windowed-sinc anti-aliasing filter cuts off at the lower of the two Nyquist
+/// frequencies, and the output is aligned with the input (the filter delay is removed).
+pub struct StreamResampler {
+    up: usize,
+    down: usize,
+    half_len: usize,
+    taps: Vec<f64>,
+    history: Vec<f32>,    // Input samples from index `history_start` on
+    history_start: usize,
+    n_in: usize,          // Input samples received so far
+    next_out: usize,      // Index of the next output sample
+}
+
+impl StreamResampler {
+    pub fn new(fs_in: u32, fs_out: u32) -> Result<StreamResampler, String> {
+        if fs_in == 0 || fs_out == 0 {
+            return Err("Sample rates must be positive for resampling".to_string());
+        }
+        let divisor = gcd(fs_in as usize, fs_out as usize);
+        Ok(StreamResampler::with_factors(fs_out as usize / divisor, fs_in as usize / divisor))
+    }
+
+    fn with_factors(up: usize, down: usize) -> StreamResampler {
+        let max_rate = up.max(down);
+        let half_len = HALF_LENGTH_FACTOR * max_rate;
+        let taps = design_lowpass(2 * half_len + 1, 1.0 / max_rate as f64, up as f64);
+        StreamResampler { up, down, half_len, taps, history: Vec::new(), history_start: 0, n_in: 0, next_out: 0 }
+    }
+
+    /// Adds the next block of input and returns the output samples that can now be computed.
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        self.history.extend_from_slice(input);
+        self.n_in += input.len();
+        // Output k needs input up to index (k * down + half_len) / up
+        let available = (self.n_in * self.up).saturating_sub(self.half_len).div_ceil(self.down);
+        let output = self.compute(self.next_out..available.max(self.next_out));
+        self.trim_history();
+        output
+    }
+
+    /// Returns the remaining output samples at the end of the input (which is zero-padded).
+    pub fn finish(&mut self) -> Vec<f32> {
+        let n_out = (self.n_in * self.up).div_ceil(self.down);
+        self.compute(self.next_out..n_out.max(self.next_out))
+    }
+
+    fn compute(&mut self, range: std::ops::Range<usize>) -> Vec<f32> {
+        self.next_out = range.end;
+        let (up, down, half_len, n_in) = (self.up, self.down, self.half_len, self.n_in);
+        let (taps, history, history_start) = (&self.taps, &self.history, self.history_start);
+        range
+            .into_par_iter()
+            .map(|k| {
+                // Position in the upsampled signal, shifted by the filter delay
+                let t = k * down + half_len;
+                let phase = t % up;
+                let base = t / up;
+                let mut acc = 0.0f64;
+                let mut tap = phase;
+                let mut idx = base;
+                while tap < taps.len() {
+                    if idx < n_in {
+                        acc += taps[tap] * history[idx - history_start] as f64;
+                    }
+                    if idx == 0 {
+                        break;
+                    }
+                    tap += up;
+                    idx -= 1;
+                }
+                acc as f32
+            })
+            .collect()
+    }
+
+    /// Drops input samples that no remaining output sample depends on.
+    fn trim_history(&mut self) {
+        let base = (self.next_out * self.down + self.half_len) / self.up;
+        let oldest_needed = base.saturating_sub(self.taps.len() / self.up + 1).min(self.n_in);
+        if oldest_needed > self.history_start {
+            self.history.drain(..oldest_needed - self.history_start);
+            self.history_start = oldest_needed;
+        }
+    }
+}
+
+/// Kaiser-windowed sinc low-pass filter with cutoff `cutoff` (relative to Nyquist),
+/// normalised to unity DC gain and then multiplied by `gain`.
+fn design_lowpass(n_taps: usize, cutoff: f64, gain: f64) -> Vec<f64> {
+    let centre = (n_taps - 1) as f64 / 2.0;
+    let norm = dsp::bessel_i0(KAISER_BETA);
+    let mut taps: Vec<f64> = (0..n_taps)
+        .map(|j| {
+            let m = j as f64 - centre;
+            let x = cutoff * m;
+            let sinc = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+            let window = dsp::bessel_i0(KAISER_BETA * (1.0 - (m / centre).powi(2)).max(0.0).sqrt()) / norm;
+            cutoff * sinc * window
+        })
+        .collect();
+    let sum: f64 = taps.iter().sum();
+    for tap in taps.iter_mut() {
+        *tap *= gain / sum;
+    }
+    taps
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Resamples a whole signal.
+    fn resample(input: &[f32], fs_in: u32, fs_out: u32) -> Result<Vec<f32>, String> {
+        let mut resampler = StreamResampler::new(fs_in, fs_out)?;
+        let mut output = resampler.process(input);
+        output.extend(resampler.finish());
+        Ok(output)
+    }
+
+    fn rms(x: &[f32]) -> f64 {
+        (x.iter().map(|&v| (v as f64).powi(2)).sum::<f64>() / x.len() as f64).sqrt()
+    }
+
+    #[test]
+    fn test_resample_preserves_tone() {
+        let fs_in = 48000;
+        let tone: Vec<f32> = (0..48000).map(|i| (2.0 * PI * 1000.0 * i as f64 / fs_in as f64).sin() as f32).collect();
+
+        let down = resample(&tone, fs_in, 16000).unwrap();
+        assert_eq!(down.len(), 16000);
+        // Compare away from the edges against the ideal resampled tone
+        let expected: Vec<f32> = (0..16000).map(|i| (2.0 * PI * 1000.0 * i as f64 / 16000.0).sin() as f32).collect();
+        let max_error = down[1000..15000].iter().zip(&expected[1000..15000]).map(|(a, b)| (a - b).abs()).fold(0.0, f32::max);
+        assert!(max_error < 1e-3, "max error {}", max_error);
+
+        let up = resample(&tone, fs_in, 44100).unwrap();
+        assert_eq!(up.len(), 44100);
+        assert!((rms(&up[1000..43000]) - 0.5f64.sqrt()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_stream_resampler_matches_whole_signal() {
+        let signal: Vec<f32> = (0..10007).map(|i| ((i * 7919) % 1000) as f32 / 1000.0 - 0.5).collect();
+        let whole = resample(&signal, 48000, 44100).unwrap();
+        let mut resampler = StreamResampler::new(48000, 44100).unwrap();
+        let mut streamed: Vec<f32> = signal.chunks(333).flat_map(|block| resampler.process(block)).collect();
+        streamed.extend(resampler.finish());
+        assert_eq!(streamed, whole);
+    }
+
+    #[test]
+    fn test_resample_rejects_aliases() {
+        // A 7 kHz tone is above the 4 kHz Nyquist frequency of the output and must be removed
+        let fs_in = 48000;
+        let tone: Vec<f32> = (0..48000).map(|i| (2.0 * PI * 7000.0 * i as f64 / fs_in as f64).sin() as f32).collect();
+        let down = resample(&tone, fs_in, 8000).unwrap();
+        assert!(rms(&down[500..7500]) < 1e-3);
+    }
+}
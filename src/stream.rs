@@ -0,0 +1,343 @@
+use crate::audio_io::{AudioInfo, AudioReader, RawFormat};
+use crate::config::AnalysisConfig;
+use crate::filter::{self, CausalFilter, ZeroPhaseFilter};
+use crate::qa::{QaReport, QaScanner, QaThresholds};
+use crate::recorder_log::SampleGap;
+use crate::resample::StreamResampler;
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::path::Path;
+
+/// Samples read from the file per block, at the file's sample rate.
+const BLOCK_SAMPLES: usize = 1 << 16;
+/// Zero-phase pre-filter overlaps (in samples) above which its memory use is reported.
+const LARGE_ZERO_PHASE_OVERLAP: usize = 1 << 22;
+
+/// A signal delivered as consecutive blocks of samples.
+pub trait SampleSource {
+    /// The next block of samples, or None at the end of the signal.
+    fn next_block(&mut self) -> Result<Option<Vec<f32>>, Box<dyn Error>>;
+
+    /// Sampling gaps found in the blocks delivered since the last call, as the sample index at
+    /// which each gap occurs and its duration in seconds. Most sources have none.
+    fn take_gaps(&mut self) -> Vec<(u64, f64)> {
+        Vec::new()
+    }
+}
+
+enum Prefilter {
+    None,
+    Causal(CausalFilter),
+    ZeroPhase(ZeroPhaseFilter),
+}
+
+/// Reads an audio file block by block, running the QA pass over the samples as read and then
+/// resampling (if a target sample rate is set) and pre-filtering them. Memory use depends on
+/// the block size (and the decay time of a zero-phase pre-filter) rather than the file length.
+pub struct AudioStream<'a> {
+    reader: AudioReader<'a>,
+    qa_scanner: Option<QaScanner>,
+    resampler: Option<StreamResampler>,
+    prefilter: Prefilter,
+    source_fs: f64,  // Sample rate of the file
+    fs: f64,         // Sample rate of the delivered blocks
+    source_samples: u64,
+    gaps: Vec<SampleGap>,   // Recorded in the file, at source sample indices
+    new_gaps: usize,        // Gaps not yet taken
+    finished: bool,
+}
+
+impl AudioStream<'static> {
+    pub fn open(path: &Path, config: &AnalysisConfig) -> Result<AudioStream<'static>, Box<dyn Error>> {
+        AudioStream::new(AudioReader::open(path, RawFormat::from_config(config)?.as_ref())?, config)
+    }
+}
+
+impl<'a> AudioStream<'a> {
+    pub fn new(reader: AudioReader<'a>, config: &AnalysisConfig) -> Result<AudioStream<'a>, Box<dyn Error>> {
+        let source_rate = reader.info().sample_rate;
+        let source_fs = source_rate as f64;
+
+        let qa_scanner = config
+            .quality_control
+            .then(|| QaScanner::new(QaThresholds::from_config(config, source_fs)));
+        let (resampler, fs) = match config.target_sample_rate.filter(|&target| target != source_rate) {
+            Some(target_fs) => {
+                println!("  Resampling from {} Hz to {} Hz", source_rate, target_fs);
+                (Some(StreamResampler::new(source_rate, target_fs)?), target_fs as f64)
+            }
+            None => (None, source_fs),
+        };
+        let prefilter = match filter::design_prefilter(config, fs)? {
+            Some(sections) if config.prefilter_zero_phase => {
+                let zero_phase = ZeroPhaseFilter::new(sections);
+                let overlap = zero_phase.overlap();
+                if overlap > LARGE_ZERO_PHASE_OVERLAP {
+                    // The backward pass holds a chunk of at least the overlap plus the overlap itself
+                    println!(
+                        "  Zero-phase pre-filter decays slowly: its backward pass holds {} samples ({:.0} s) at a time (prefilter_zero_phase = false filters causally)",
+                        2 * overlap,
+                        2.0 * overlap as f64 / fs
+                    );
+                }
+                Prefilter::ZeroPhase(zero_phase)
+            }
+            Some(sections) => Prefilter::Causal(CausalFilter::new(sections)),
+            None => Prefilter::None,
+        };
+
+        Ok(AudioStream { reader, qa_scanner, resampler, prefilter, source_fs, fs, source_samples: 0, gaps: Vec::new(), new_gaps: 0, finished: false })
+    }
+
+    /// Sample rate of the delivered blocks.
+    pub fn fs(&self) -> f64 {
+        self.fs
+    }
+
+    /// The sampling gaps recorded in the file (in SUD chunk times) among the samples read.
+    pub fn gaps(&self) -> &[SampleGap] {
+        &self.gaps
+    }
+
+    /// Reads all remaining samples.
+    pub fn read_to_end(&mut self) -> Result<Vec<f32>, Box<dyn Error>> {
+        let mut samples = Vec::new();
+        while let Some(block) = self.next_block()? {
+            samples.extend(block);
+        }
+        Ok(samples)
+    }
+
+    /// The format of the file, with the number of samples read, and the QA report of the
+    /// samples read (if quality control is enabled).
+    pub fn finish(self) -> (AudioInfo, Option<QaReport>) {
+        let mut info = self.reader.info().clone();
+        info.num_samples = self.source_samples;
+        let qa_report = self.qa_scanner.map(|scanner| scanner.finish(self.source_fs));
+        (info, qa_report)
+    }
+}
+
+impl SampleSource for AudioStream<'_> {
+    fn next_block(&mut self) -> Result<Option<Vec<f32>>, Box<dyn Error>> {
+        while !self.finished {
+            let block = self.reader.read_block(BLOCK_SAMPLES)?;
+            let at_end = block.is_empty();
+            self.finished = at_end;
+            self.source_samples += block.len() as u64;
+            let gaps = self.reader.take_gaps();
+            self.new_gaps += gaps.len();
+            self.gaps.extend(gaps);
+            if let Some(scanner) = &mut self.qa_scanner {
+                scanner.push(&block);
+            }
+
+            let mut samples = match &mut self.resampler {
+                Some(resampler) if at_end => resampler.finish(),
+                Some(resampler) => resampler.process(&block),
+                None => block,
+            };
+            match &mut self.prefilter {
+                Prefilter::None => {}
+                Prefilter::Causal(causal) => causal.process(&mut samples),
+                Prefilter::ZeroPhase(zero_phase) => {
+                    let mut filtered = zero_phase.process(&samples);
+                    if at_end {
+                        filtered.extend(zero_phase.finish());
+                    }
+                    samples = filtered;
+                }
+            }
+            if !samples.is_empty() {
+                return Ok(Some(samples));
+            }
+        }
+        Ok(None)
+    }
+
+    fn take_gaps(&mut self) -> Vec<(u64, f64)> {
+        let new = &self.gaps[self.gaps.len() - std::mem::take(&mut self.new_gaps)..];
+        // Gap positions are in the samples of the file, before any resampling
+        new.iter().map(|gap| ((gap.sample as f64 * self.fs / self.source_fs).round() as u64, gap.duration_secs)).collect()
+    }
+}
+
+/// An analysis window, starting `start` samples into the signal. `gap_secs` is the sampling gap
+/// time before it, so the window starts `start / fs + gap_secs` seconds into the recording.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub start: u64,
+    pub gap_secs: f64,
+    pub samples: Vec<f32>,
+}
+
+/// Overlapping analysis windows cut from a sample source. Samples are carried over between
+/// blocks, so only about one window plus one block is held in memory.
+pub struct Segments<'a> {
+    source: &'a mut dyn SampleSource,
+    window: usize,
+    step: usize,
+    buffer: Vec<f32>, // Samples from the start of the next segment on
+    position: u64,    // Index of the first buffered sample
+    gaps: VecDeque<(u64, f64)>, // Sampling gaps not yet reached: (sample index, duration in seconds)
+    gap_secs: f64,    // Gap time before the buffered samples
+    samples_read: u64,
+    exhausted: bool,
+}
+
+impl<'a> Segments<'a> {
+    /// Windows of `window` samples starting every `step` samples (`0 < step <= window`).
+    pub fn new(source: &'a mut dyn SampleSource, window: usize, step: usize) -> Segments<'a> {
+        Segments {
+            source,
+            window,
+            step,
+            buffer: Vec::new(),
+            position: 0,
+            gaps: VecDeque::new(),
+            gap_secs: 0.0,
+            samples_read: 0,
+            exhausted: false,
+        }
+    }
+
+    /// Breaks in sampling, as the sample index at which each gap occurs and its duration in
+    /// seconds, in addition to those the source finds. No window spans a gap: segmentation
+    /// restarts at the first sample after it.
+    #[cfg(test)]
+    pub fn with_gaps(mut self, mut gaps: Vec<(u64, f64)>) -> Segments<'a> {
+        gaps.sort_by_key(|&(sample, _)| sample);
+        self.gaps = gaps.into();
+        self
+    }
+
+    pub fn window_len(&self) -> usize {
+        self.window
+    }
+
+    pub fn step(&self) -> usize {
+        self.step
+    }
+
+    /// Samples taken from the source so far (all of them once the segments are exhausted).
+    pub fn samples_read(&self) -> u64 {
+        self.samples_read
+    }
+}
+
+impl Iterator for Segments<'_> {
+    type Item = Result<Segment, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            while self.buffer.len() < self.window {
+                if self.exhausted {
+                    return None;
+                }
+                match self.source.next_block() {
+                    Ok(Some(block)) => {
+                        self.samples_read += block.len() as u64;
+                        self.buffer.extend(block);
+                        let gaps = self.source.take_gaps();
+                        if !gaps.is_empty() {
+                            self.gaps.extend(gaps);
+                            self.gaps.make_contiguous().sort_by_key(|&(sample, _)| sample);
+                        }
+                    }
+                    Ok(None) => self.exhausted = true,
+                    Err(e) => {
+                        self.exhausted = true;
+                        return Some(Err(e));
+                    }
+                }
+            }
+            // A gap at or before the window start is passed; one inside it restarts the windows there
+            match self.gaps.front() {
+                Some(&(sample, duration_secs)) if sample <= self.position => {
+                    self.gap_secs += duration_secs;
+                    self.gaps.pop_front();
+                }
+                Some(&(sample, _)) if sample < self.position + self.window as u64 => {
+                    self.buffer.drain(..(sample - self.position) as usize);
+                    self.position = sample;
+                }
+                _ => break,
+            }
+        }
+        let segment = Segment { start: self.position, gap_secs: self.gap_secs, samples: self.buffer[..self.window].to_vec() };
+        self.buffer.drain(..self.step);
+        self.position += self.step as u64;
+        Some(Ok(segment))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Blocks(std::vec::IntoIter<Vec<f32>>);
+
+    impl SampleSource for Blocks {
+        fn next_block(&mut self) -> Result<Option<Vec<f32>>, Box<dyn Error>> {
+            Ok(self.0.next())
+        }
+    }
+
+    type Gaps = Vec<(u64, f64)>;
+
+    /// Blocks, each with the sampling gaps found in it.
+    struct GapBlocks(std::vec::IntoIter<(Vec<f32>, Gaps)>, Gaps);
+
+    impl SampleSource for GapBlocks {
+        fn next_block(&mut self) -> Result<Option<Vec<f32>>, Box<dyn Error>> {
+            Ok(self.0.next().map(|(block, gaps)| {
+                self.1 = gaps;
+                block
+            }))
+        }
+
+        fn take_gaps(&mut self) -> Gaps {
+            std::mem::take(&mut self.1)
+        }
+    }
+
+    #[test]
+    fn test_segments_span_blocks() {
+        let signal: Vec<f32> = (0..100).map(|i| i as f32).collect();
+        let blocks: Vec<Vec<f32>> = signal.chunks(7).map(|b| b.to_vec()).collect();
+        let mut source = Blocks(blocks.into_iter());
+        let segments: Vec<Segment> = Segments::new(&mut source, 10, 4).map(|s| s.unwrap()).collect();
+
+        // Every full window, including those straddling block boundaries
+        assert_eq!(segments.len(), (100 - 10) / 4 + 1);
+        for (i, segment) in segments.iter().enumerate() {
+            assert_eq!(segment.start, i as u64 * 4);
+            assert_eq!(segment.samples.as_slice(), &signal[i * 4..i * 4 + 10]);
+        }
+    }
+
+    #[test]
+    fn test_segments_restart_after_gaps() {
+        let signal: Vec<f32> = (0..100).map(|i| i as f32).collect();
+        let mut source = Blocks(vec![signal.clone()].into_iter());
+        let segments: Vec<Segment> = Segments::new(&mut source, 10, 4)
+            .with_gaps(vec![(30, 2.0), (52, 0.5)])
+            .map(|s| s.unwrap())
+            .collect();
+
+        let starts: Vec<(u64, f64)> = segments.iter().map(|s| (s.start, s.gap_secs)).collect();
+        assert_eq!(
+            starts,
+            vec![(0, 0.0), (4, 0.0), (8, 0.0), (12, 0.0), (16, 0.0), (20, 0.0), (30, 2.0), (34, 2.0), (38, 2.0), (42, 2.0),
+                 (52, 2.5), (56, 2.5), (60, 2.5), (64, 2.5), (68, 2.5), (72, 2.5), (76, 2.5), (80, 2.5), (84, 2.5), (88, 2.5)]
+        );
+        assert!(segments.iter().all(|s| s.samples[0] == s.start as f32));
+
+        // The same gaps found by the source as it reads the blocks that contain them
+        let blocks = signal.chunks(25).zip([vec![], vec![(30, 2.0)], vec![(52, 0.5)], vec![]]);
+        let mut source = GapBlocks(blocks.map(|(block, gaps)| (block.to_vec(), gaps)).collect::<Vec<_>>().into_iter(), Vec::new());
+        let found: Vec<Segment> = Segments::new(&mut source, 10, 4).map(|s| s.unwrap()).collect();
+        assert_eq!(found, segments);
+    }
+}
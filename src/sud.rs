@@ -0,0 +1,457 @@
+//! SoundTrap SUD files: a sequence of chunks, each a 20-byte big-endian header followed by its
+//! data. Chunk 0 holds XML describing the other chunk streams; the audio stream is compressed
+//! with X3 (Johnson, Hurst & Partan 2013, JASA 133:1387). Each audio chunk header carries the
+//! UTC time of its first sample, so the recording start time and any sampling gaps can be read
+//! from the file itself.
+//!
+//! No public specification of SUD is available, and this follows the layout used by other
+//! open decoders. The assumptions are:
+//! - Chunk header fields: magic 0xA952, chunk id, data length (bytes) and sample count (16 bits
+//!   each), time in Unix seconds and microsecond offset (32 bits each), data and header CRCs
+//!   (16 bits each). The CRCs are not checked; chunks are located by their magic number, so the
+//!   file header before the first chunk is skipped.
+//! - The X3 stream is the chunk id of the first `CFG` element in the metadata that names an X3
+//!   codec. Its `FS`, `NBITS`, `NCHS`, `BLKLEN` and `CODES` settings are used where present,
+//!   defaulting to 16-bit mono, 20-sample blocks and the codes RICE0, RICE2, RICE3, BFP.
+//! - Each X3 chunk starts with the first sample verbatim (NBITS bits), followed by blocks of
+//!   first differences. A block starts with a 2-bit code selector indexing `CODES`. Rice codes
+//!   with parameter k write each difference, zigzag-mapped to u = 2|d| - (d < 0), as u >> k zero
+//!   bits, a one bit and the k low bits of u. A BFP block gives its bit width minus one in 4
+//!   bits, then each difference as a signed value of that width; a width of NBITS means the
+//!   block holds samples rather than differences. Bits are packed MSB first.
+
+use crate::recorder_log::SampleGap;
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use std::collections::VecDeque;
+use std::io::{BufReader, Read};
+
+const CHUNK_MAGIC: [u8; 2] = [0xA9, 0x52];
+const CHUNK_HEADER_LEN: usize = 20;
+const METADATA_CHUNK_ID: u16 = 0;
+/// Chunk timing differences below this are clock jitter rather than sampling gaps.
+const GAP_TOLERANCE_SECS: f64 = 0.001;
+
+#[derive(Debug, Clone, PartialEq)]
+struct ChunkHeader {
+    chunk_id: u16,
+    data_len: usize,
+    sample_count: usize,
+    time_s: u32,
+    time_offset_us: u32,
+}
+
+impl ChunkHeader {
+    fn parse(bytes: &[u8; CHUNK_HEADER_LEN]) -> Option<ChunkHeader> {
+        if bytes[0..2] != CHUNK_MAGIC {
+            return None;
+        }
+        let u16_at = |i: usize| u16::from_be_bytes([bytes[i], bytes[i + 1]]);
+        let u32_at = |i: usize| u32::from_be_bytes(bytes[i..i + 4].try_into().unwrap());
+        Some(ChunkHeader {
+            chunk_id: u16_at(2),
+            data_len: u16_at(4) as usize,
+            sample_count: u16_at(6) as usize,
+            time_s: u32_at(8),
+            time_offset_us: u32_at(12),
+        })
+    }
+
+    /// The time of the chunk's first sample.
+    fn time(&self) -> Result<DateTime<Utc>, String> {
+        if self.time_offset_us >= 1_000_000 {
+            return Err(format!("Invalid microsecond offset {} in SUD chunk header", self.time_offset_us));
+        }
+        DateTime::from_timestamp(self.time_s as i64, self.time_offset_us * 1000)
+            .ok_or_else(|| format!("Invalid time {} in SUD chunk header", self.time_s))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum X3Code {
+    Rice(u32),
+    Bfp,
+}
+
+/// Parameters of the X3 audio stream in a SUD file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct X3Config {
+    chunk_id: u16,
+    pub sample_rate: u32,
+    pub nbits: u16,
+    pub channels: u16,
+    block_len: usize,
+    codes: Vec<X3Code>,
+}
+
+/// Finds the X3 stream settings in the XML of the metadata chunks.
+fn parse_x3_config(xml: &str) -> Option<X3Config> {
+    let cfg_regex = Regex::new(r#"(?s)<CFG\b([^>]*)>(.*?)</CFG>"#).unwrap();
+    let attr = |attrs: &str, name: &str| -> Option<String> {
+        Regex::new(&format!(r#"\b{}\s*=\s*"([^"]*)""#, name)).unwrap().captures(attrs).map(|c| c[1].trim().to_string())
+    };
+    let element = |body: &str, name: &str| -> Option<String> {
+        Regex::new(&format!(r#"(?s)<{}\b[^>]*>([^<]*)</{}>"#, name, name)).unwrap().captures(body).map(|c| c[1].trim().to_string())
+    };
+    let first_fs = cfg_regex.captures_iter(xml).find_map(|c| element(&c[2], "FS")).and_then(|fs| fs.parse().ok());
+
+    let cfg = cfg_regex.captures_iter(xml).find(|c| {
+        let codec = Regex::new(r#"(?i)<CODEC\b[^>]*TYPE\s*=\s*"X3""#).unwrap();
+        codec.is_match(&c[2]) || attr(&c[1], "FTYPE").is_some_and(|t| t.to_ascii_lowercase().starts_with("x3"))
+    })?;
+    let (attrs, body) = (&cfg[1], &cfg[2]);
+    let number = |name: &str| element(body, name).and_then(|v| v.parse::<u32>().ok());
+
+    let codes = match element(body, "CODES") {
+        Some(list) => list
+            .split(',')
+            .map(|code| {
+                let code = code.trim().to_ascii_uppercase();
+                if code == "BFP" { Some(X3Code::Bfp) } else { code.strip_prefix("RICE")?.parse().ok().map(X3Code::Rice) }
+            })
+            .collect::<Option<Vec<_>>>()?,
+        None => vec![X3Code::Rice(0), X3Code::Rice(2), X3Code::Rice(3), X3Code::Bfp],
+    };
+    Some(X3Config {
+        chunk_id: attr(attrs, "ID")?.parse().ok()?,
+        sample_rate: number("FS").or(first_fs)?,
+        nbits: number("NBITS").unwrap_or(16) as u16,
+        channels: number("NCHS").unwrap_or(1) as u16,
+        block_len: number("BLKLEN").unwrap_or(20) as usize,
+        codes,
+    })
+}
+
+/// Reads the next chunk header at or after the current position, skipping any bytes before
+/// the next chunk magic (such as the file header). Returns None at the end of the file.
+fn next_chunk_header<R: Read>(reader: &mut R) -> Result<Option<ChunkHeader>, std::io::Error> {
+    let mut header = [0u8; CHUNK_HEADER_LEN];
+    let mut filled = 0;
+    loop {
+        while filled < CHUNK_HEADER_LEN {
+            let n = reader.read(&mut header[filled..])?;
+            if n == 0 {
+                return Ok(None);
+            }
+            filled += n;
+        }
+        if let Some(chunk) = ChunkHeader::parse(&header) {
+            return Ok(Some(chunk));
+        }
+        // Resynchronise on the next candidate magic byte
+        let skip = header[1..].iter().position(|&b| b == CHUNK_MAGIC[0]).map_or(CHUNK_HEADER_LEN, |i| i + 1);
+        header.copy_within(skip.., 0);
+        filled = CHUNK_HEADER_LEN - skip;
+    }
+}
+
+/// Decodes the audio of a SUD file chunk by chunk, reading it front to back. The time of the
+/// first sample, and any sampling gaps (where a chunk starts later than the samples before it
+/// account for), are taken from the chunk headers as they are read.
+pub struct SudReader<'a> {
+    reader: BufReader<Box<dyn Read + 'a>>,
+    config: X3Config,
+    pending: VecDeque<(ChunkHeader, Vec<u8>)>, // Chunks read while looking for the X3 settings
+    start_time: DateTime<Utc>,
+    gaps: Vec<SampleGap>, // Found since the last call to `take_gaps`
+    samples_decoded: u64,
+    expected_secs: f64, // Time after the start at which the next chunk should start
+}
+
+impl<'a> SudReader<'a> {
+    /// Reads the metadata and the header of the first audio chunk from `input`.
+    pub fn new(input: Box<dyn Read + 'a>) -> Result<SudReader<'a>, Box<dyn std::error::Error>> {
+        let mut reader = BufReader::new(input);
+        let mut metadata = String::new();
+        let mut pending = VecDeque::new();
+        let mut config = None;
+        let mut first_audio_chunk = None;
+        while first_audio_chunk.is_none() {
+            let Some((chunk, data)) = read_chunk(&mut reader)? else { break };
+            if chunk.chunk_id == METADATA_CHUNK_ID {
+                metadata.push_str(&String::from_utf8_lossy(&data));
+                config = parse_x3_config(&metadata);
+            } else {
+                pending.push_back((chunk, data));
+            }
+            if let Some(config) = &config {
+                first_audio_chunk = pending.iter().map(|(chunk, _)| chunk).find(|chunk| is_audio(chunk, config)).cloned();
+            }
+        }
+        let config = config.ok_or("No X3 audio stream described in the SUD metadata")?;
+        if config.channels != 1 {
+            return Err(format!(
+                "Unsupported channel count: {}. Only mono files are currently supported.",
+                config.channels
+            ).into());
+        }
+        if !(2..=32).contains(&config.nbits) || config.block_len == 0 || config.codes.len() != 4 {
+            return Err(format!("Unsupported X3 settings in SUD metadata: {:?}", config).into());
+        }
+        let start_time = first_audio_chunk.ok_or("No audio chunks in the SUD file")?.time()?;
+        Ok(SudReader { reader, config, pending, start_time, gaps: Vec::new(), samples_decoded: 0, expected_secs: 0.0 })
+    }
+
+    pub fn config(&self) -> &X3Config {
+        &self.config
+    }
+
+    /// The time of the first sample.
+    pub fn start_time(&self) -> DateTime<Utc> {
+        self.start_time
+    }
+
+    /// Sampling gaps in the chunks decoded since the last call, at the index of the first
+    /// sample after each gap.
+    pub fn take_gaps(&mut self) -> Vec<SampleGap> {
+        std::mem::take(&mut self.gaps)
+    }
+
+    /// Decodes the next audio chunk, returning its samples, or None at the end of the file.
+    pub fn next_chunk(&mut self) -> Result<Option<Vec<i32>>, Box<dyn std::error::Error>> {
+        loop {
+            let next = match self.pending.pop_front() {
+                Some(pending) => Some(pending),
+                None => read_chunk(&mut self.reader)?,
+            };
+            let Some((chunk, data)) = next else { return Ok(None) };
+            if is_audio(&chunk, &self.config) {
+                let samples = decode_x3_chunk(&data, chunk.sample_count, &self.config)?;
+                self.record_timing(&chunk)?;
+                return Ok(Some(samples));
+            }
+        }
+    }
+
+    fn record_timing(&mut self, chunk: &ChunkHeader) -> Result<(), String> {
+        let chunk_secs = (chunk.time()? - self.start_time).num_microseconds().unwrap_or(i64::MAX) as f64 * 1e-6;
+        if self.samples_decoded > 0 && chunk_secs - self.expected_secs > GAP_TOLERANCE_SECS {
+            self.gaps.push(SampleGap { sample: self.samples_decoded, duration_secs: chunk_secs - self.expected_secs });
+        }
+        self.samples_decoded += chunk.sample_count as u64;
+        self.expected_secs = chunk_secs + chunk.sample_count as f64 / self.config.sample_rate as f64;
+        Ok(())
+    }
+}
+
+fn is_audio(chunk: &ChunkHeader, config: &X3Config) -> bool {
+    chunk.chunk_id == config.chunk_id && chunk.sample_count > 0
+}
+
+/// Reads the next chunk and its data, or None at the end of the file (including a truncated
+/// final chunk).
+fn read_chunk<R: Read>(reader: &mut R) -> Result<Option<(ChunkHeader, Vec<u8>)>, std::io::Error> {
+    let Some(chunk) = next_chunk_header(reader)? else { return Ok(None) };
+    let mut data = vec![0u8; chunk.data_len];
+    match reader.read_exact(&mut data) {
+        Ok(()) => Ok(Some((chunk, data))),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads bits MSB first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize, // In bits
+}
+
+impl BitReader<'_> {
+    fn read(&mut self, n_bits: u32) -> Result<u32, String> {
+        let mut value = 0u32;
+        for _ in 0..n_bits {
+            value = (value << 1) | self.read_bit()?;
+        }
+        Ok(value)
+    }
+
+    fn read_signed(&mut self, n_bits: u32) -> Result<i32, String> {
+        let value = self.read(n_bits)?;
+        Ok(((value << (32 - n_bits)) as i32) >> (32 - n_bits))
+    }
+
+    fn read_bit(&mut self) -> Result<u32, String> {
+        let byte = self.data.get(self.position / 8).ok_or("X3 chunk ended early")?;
+        let bit = (byte >> (7 - self.position % 8)) & 1;
+        self.position += 1;
+        Ok(bit as u32)
+    }
+}
+
+/// The sample after the last one decoded, `difference` later.
+fn next_sample(samples: &[i32], difference: i32) -> Result<i32, String> {
+    samples[samples.len() - 1].checked_add(difference).ok_or_else(|| "Sample out of range in X3 chunk".to_string())
+}
+
+/// Decodes the samples of one mono X3 chunk.
+fn decode_x3_chunk(data: &[u8], sample_count: usize, config: &X3Config) -> Result<Vec<i32>, String> {
+    let nbits = config.nbits as u32;
+    // No difference between nbits-bit samples needs a longer unary quotient
+    let max_quotient = 1u32.checked_shl(nbits).unwrap_or(u32::MAX);
+    let mut bits = BitReader { data, position: 0 };
+    let mut samples = Vec::with_capacity(sample_count);
+    samples.push(bits.read_signed(nbits)?);
+    while samples.len() < sample_count {
+        let n = config.block_len.min(sample_count - samples.len());
+        match config.codes[bits.read(2)? as usize] {
+            X3Code::Rice(k) => {
+                for _ in 0..n {
+                    let mut quotient = 0u32;
+                    while bits.read_bit()? == 0 {
+                        quotient += 1;
+                        if quotient > max_quotient {
+                            return Err("Invalid Rice code in X3 chunk".to_string());
+                        }
+                    }
+                    let u = quotient
+                        .checked_shl(k)
+                        .filter(|&high| high >> k == quotient)
+                        .ok_or("Invalid Rice code in X3 chunk")?
+                        | bits.read(k)?;
+                    let difference = (u >> 1) as i32 ^ -((u & 1) as i32);
+                    samples.push(next_sample(&samples, difference)?);
+                }
+            }
+            X3Code::Bfp => {
+                let width = bits.read(4)? + 1;
+                for _ in 0..n {
+                    let value = bits.read_signed(width)?;
+                    let sample = if width >= nbits { value } else { next_sample(&samples, value)? };
+                    samples.push(sample);
+                }
+            }
+        }
+    }
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes bits MSB first.
+    #[derive(Default)]
+    struct BitWriter {
+        bytes: Vec<u8>,
+        n_bits: usize,
+    }
+
+    impl BitWriter {
+        fn write(&mut self, value: u32, n_bits: u32) {
+            for i in (0..n_bits).rev() {
+                if self.n_bits.is_multiple_of(8) {
+                    self.bytes.push(0);
+                }
+                let bit = ((value >> i) & 1) as u8;
+                *self.bytes.last_mut().unwrap() |= bit << (7 - self.n_bits % 8);
+                self.n_bits += 1;
+            }
+        }
+    }
+
+    /// Encodes a chunk with the default codes, choosing Rice codes for small differences.
+    fn encode_x3_chunk(samples: &[i32], block_len: usize) -> Vec<u8> {
+        let mut bits = BitWriter::default();
+        bits.write(samples[0] as u32 & 0xFFFF, 16);
+        for (b, block) in samples[1..].chunks(block_len).enumerate() {
+            let previous = samples[b * block_len];
+            let differences: Vec<i32> = block.iter().scan(previous, |p, &x| { let d = x - *p; *p = x; Some(d) }).collect();
+            let max = differences.iter().map(|d| d.abs()).max().unwrap();
+            if max < 8 {
+                let (selector, k) = if max < 3 { (0, 0) } else { (1, 2) };
+                bits.write(selector, 2);
+                for d in differences {
+                    let u = if d >= 0 { 2 * d as u32 } else { (-2 * d - 1) as u32 };
+                    bits.write(1, (u >> k) + 1);
+                    bits.write(u & ((1 << k) - 1), k);
+                }
+            } else {
+                bits.write(3, 2);
+                bits.write(15, 4); // 16-bit samples
+                for &x in block {
+                    bits.write(x as u32 & 0xFFFF, 16);
+                }
+            }
+        }
+        bits.bytes
+    }
+
+    fn chunk(chunk_id: u16, data: &[u8], sample_count: u16, time_s: u32, time_offset_us: u32) -> Vec<u8> {
+        let mut bytes = CHUNK_MAGIC.to_vec();
+        for field in [chunk_id, data.len() as u16, sample_count] {
+            bytes.extend_from_slice(&field.to_be_bytes());
+        }
+        bytes.extend_from_slice(&time_s.to_be_bytes());
+        bytes.extend_from_slice(&time_offset_us.to_be_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn test_decode_sud_file() {
+        let xml = r#"<?xml version="1.0"?><CFG TIME="2024-07-17T16:47:21" ID="2" FTYPE="wav"><FS>8000</FS><NBITS>16</NBITS></CFG>
+<CFG TIME="2024-07-17T16:47:21" ID="3" FTYPE="x3v2"><SRC ID="2"/><CODEC TYPE="X3"><BLKLEN>16</BLKLEN><CODES N="4">RICE0,RICE2,RICE3,BFP</CODES><NBITS>16</NBITS></CODEC></CFG>"#;
+        let first: Vec<i32> = (0..100).map(|i| ((i as f64 * 0.05).sin() * 40.0) as i32).collect();
+        let second: Vec<i32> = (0..50).map(|i| if i % 7 == 0 { -32768 } else { 12345 - i }).collect();
+
+        let mut file = b"file header".to_vec();
+        file.extend(chunk(0, xml.as_bytes(), 0, 0, 0));
+        file.extend(chunk(3, &encode_x3_chunk(&first, 16), 100, 1_721_234_841, 250_000));
+        file.extend(chunk(1, b"unrelated", 0, 0, 0));
+        // Starts 2 s later than the first chunk's 100 samples account for
+        file.extend(chunk(3, &encode_x3_chunk(&second, 16), 50, 1_721_234_843, 262_500));
+
+        let mut reader = SudReader::new(Box::new(std::io::Cursor::new(file))).unwrap();
+        assert_eq!((reader.config().sample_rate, reader.config().block_len), (8000, 16));
+        assert_eq!(reader.start_time().timestamp_micros(), 1_721_234_841_250_000);
+        assert_eq!(reader.next_chunk().unwrap().unwrap(), first);
+        assert_eq!(reader.take_gaps(), vec![]);
+        assert_eq!(reader.next_chunk().unwrap().unwrap(), second);
+        let gaps = reader.take_gaps();
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(gaps[0].sample, 100);
+        assert!((gaps[0].duration_secs - 2.0).abs() < 1e-9);
+        assert_eq!(reader.next_chunk().unwrap(), None);
+    }
+
+    #[test]
+    fn test_invalid_chunk_time() {
+        let xml = r#"<CFG ID="3" FTYPE="x3v2"><FS>8000</FS></CFG>"#;
+        let samples = vec![0; 10];
+        let mut file = chunk(0, xml.as_bytes(), 0, 0, 0);
+        file.extend(chunk(3, &encode_x3_chunk(&samples, 20), 10, 1_721_234_841, 0));
+        file.extend(chunk(3, &encode_x3_chunk(&samples, 20), 10, 1_721_234_841, 4_294_968));
+        let mut reader = SudReader::new(Box::new(std::io::Cursor::new(file))).unwrap();
+        assert_eq!(reader.next_chunk().unwrap().unwrap(), samples);
+        let error = reader.next_chunk().unwrap_err();
+        assert_eq!(error.to_string(), "Invalid microsecond offset 4294968 in SUD chunk header");
+    }
+
+    #[test]
+    fn test_decode_x3_out_of_range() {
+        let config = X3Config {
+            chunk_id: 3,
+            sample_rate: 8000,
+            nbits: 32,
+            channels: 1,
+            block_len: 4,
+            codes: vec![X3Code::Rice(0), X3Code::Rice(2), X3Code::Rice(3), X3Code::Bfp],
+        };
+        // A difference of +1 after the largest 32-bit sample
+        let mut bits = BitWriter::default();
+        bits.write(i32::MAX as u32, 32);
+        bits.write(0, 2);
+        bits.write(1, 3);
+        assert_eq!(decode_x3_chunk(&bits.bytes, 2, &config), Err("Sample out of range in X3 chunk".to_string()));
+
+        // A Rice quotient that overflows 32 bits when shifted by the parameter
+        let config = X3Config { codes: vec![X3Code::Rice(31); 4], ..config };
+        let mut bits = BitWriter::default();
+        bits.write(0, 32);
+        bits.write(0, 2);
+        bits.write(1, 3);
+        bits.write(0, 31);
+        assert_eq!(decode_x3_chunk(&bits.bytes, 2, &config), Err("Invalid Rice code in X3 chunk".to_string()));
+    }
+}
@@ -0,0 +1,292 @@
+use crate::config::AnalysisConfig;
+
+use chrono::{DateTime, Duration, FixedOffset, LocalResult, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use regex::Regex;
+use std::path::Path;
+
+/// A time zone for interpreting or writing timestamps: UTC, a fixed offset such as "+02:00",
+/// or an IANA zone name such as "Europe/Berlin" (which follows daylight saving time).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeZoneSpec {
+    Utc,
+    Fixed(FixedOffset),
+    Named(Tz),
+}
+
+impl TimeZoneSpec {
+    pub fn parse(s: &str) -> Result<TimeZoneSpec, String> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("utc") || s.eq_ignore_ascii_case("z") || s.eq_ignore_ascii_case("gmt") {
+            return Ok(TimeZoneSpec::Utc);
+        }
+        if s.starts_with('+') || s.starts_with('-') {
+            let digits: String = s[1..].chars().filter(|c| c.is_ascii_digit()).collect();
+            let (hours, minutes) = match digits.len() {
+                1 | 2 => (digits.parse::<i32>().ok(), Some(0)),
+                4 => (digits[..2].parse::<i32>().ok(), digits[2..].parse::<i32>().ok()),
+                _ => (None, None),
+            };
+            let sign = if s.starts_with('-') { -1 } else { 1 };
+            return hours
+                .zip(minutes)
+                .and_then(|(h, m)| FixedOffset::east_opt(sign * (h * 3600 + m * 60)))
+                .map(TimeZoneSpec::Fixed)
+                .ok_or_else(|| format!("Invalid UTC offset '{}' (expected e.g. +02:00)", s));
+        }
+        s.parse::<Tz>()
+            .map(TimeZoneSpec::Named)
+            .map_err(|_| format!("Unknown time zone '{}' (use UTC, an offset like +02:00, or an IANA name)", s))
+    }
+
+    /// Reads an optional time zone setting, defaulting to UTC.
+    pub fn from_setting(setting: &Option<String>) -> Result<TimeZoneSpec, String> {
+        setting.as_deref().map_or(Ok(TimeZoneSpec::Utc), TimeZoneSpec::parse)
+    }
+
+    /// Converts a local time in this zone to UTC. Times repeated when clocks go back resolve to
+    /// the earlier instant; times skipped when clocks go forward are an error.
+    pub fn to_utc(self, local: NaiveDateTime) -> Result<DateTime<Utc>, String> {
+        let resolve = |result: LocalResult<DateTime<Utc>>| match result {
+            LocalResult::Single(t) | LocalResult::Ambiguous(t, _) => Ok(t),
+            LocalResult::None => Err(format!("{} does not exist in the configured time zone", local)),
+        };
+        match self {
+            TimeZoneSpec::Utc => Ok(local.and_utc()),
+            TimeZoneSpec::Fixed(offset) => resolve(offset.from_local_datetime(&local).map(|t| t.with_timezone(&Utc))),
+            TimeZoneSpec::Named(tz) => resolve(tz.from_local_datetime(&local).map(|t| t.with_timezone(&Utc))),
+        }
+    }
+
+    /// The zone as "UTC", a UTC offset ("+02:00") or an IANA name, as used in Arrow schemas.
+    pub fn name(&self) -> String {
+        match self {
+            TimeZoneSpec::Utc => "UTC".to_string(),
+            TimeZoneSpec::Fixed(offset) => offset.to_string(),
+            TimeZoneSpec::Named(tz) => tz.name().to_string(),
+        }
+    }
+
+    /// Formats a time as ISO 8601 in this zone, with a 'Z' suffix for UTC or the UTC offset.
+    pub fn format(&self, time: DateTime<Utc>) -> String {
+        const FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+        const FORMAT_WITH_OFFSET: &str = "%Y-%m-%dT%H:%M:%S%.f%:z";
+        match self {
+            TimeZoneSpec::Utc => format!("{}Z", time.format(FORMAT)),
+            TimeZoneSpec::Fixed(offset) => time.with_timezone(offset).format(FORMAT_WITH_OFFSET).to_string(),
+            TimeZoneSpec::Named(tz) => time.with_timezone(tz).format(FORMAT_WITH_OFFSET).to_string(),
+        }
+    }
+}
+
+/// The time of an output row: an absolute UTC time when the file start time is known, or an
+/// offset in seconds from the start of the file otherwise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RowTime {
+    Absolute(DateTime<Utc>),
+    Relative(f64),
+}
+
+impl RowTime {
+    /// The time `offset_secs` after the file start (or after the start of the file, if unknown).
+    pub fn at_offset(file_start: Option<DateTime<Utc>>, offset_secs: f64) -> RowTime {
+        match file_start {
+            Some(start) => RowTime::Absolute(start + secs_to_duration(offset_secs)),
+            None => RowTime::Relative(offset_secs),
+        }
+    }
+
+    pub fn format(&self, zone: &TimeZoneSpec) -> String {
+        match self {
+            RowTime::Absolute(t) => zone.format(*t),
+            RowTime::Relative(offset) => format!("{:.3}", offset),
+        }
+    }
+}
+
+/// Converts seconds to a chrono Duration at nanosecond resolution.
+pub fn secs_to_duration(secs: f64) -> Duration {
+    Duration::nanoseconds((secs * 1e9).round() as i64)
+}
+
+/// Seconds from `from` to `to` (negative if `to` is earlier).
+pub fn secs_between(from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+    let delta = to - from;
+    delta.num_seconds() as f64 + delta.subsec_nanos() as f64 * 1e-9
+}
+
+/// Extracts the recording start time from a filename, using `timestamp_regex` if set and
+/// `timestamp_format` otherwise. Returns Ok(None) if neither is configured. The time is local to
+/// `timestamp_timezone`; see `TimeZoneSpec::to_utc`.
+pub fn timestamp_from_filename(path: &Path, config: &AnalysisConfig) -> Result<Option<NaiveDateTime>, String> {
+    let stem = match path.file_stem() {
+        Some(stem) => stem.to_string_lossy(),
+        None => return Err(format!("No filename in {}", path.display())),
+    };
+    if let Some(pattern) = &config.timestamp_regex {
+        // Configurations not read by load_config have no compiled regex
+        let compiled;
+        let regex = match &config.timestamp_regex_compiled {
+            Some(regex) => regex,
+            None => {
+                compiled = compile_timestamp_regex(pattern)?;
+                &compiled
+            }
+        };
+        return parse_with_regex(&stem, regex)
+            .map(Some)
+            .ok_or_else(|| format!("timestamp_regex did not match '{}'", stem));
+    }
+    match &config.timestamp_format {
+        Some(format) => find_timestamp(&stem, format)
+            .map(Some)
+            .ok_or_else(|| format!("No '{}' timestamp found in '{}'", format, stem)),
+        None => Ok(None),
+    }
+}
+
+/// Converts a MATLAB PAMGuide style format (yyyy, yy, mm, dd, HH, MM, SS, FFF) to chrono
+/// format codes. Formats that already contain '%' are treated as chrono formats.
+pub fn convert_pamguide_format_to_chrono(pg_format: &str) -> String {
+    if pg_format.contains('%') {
+        return pg_format.to_string();
+    }
+    const TOKENS: [(&str, &str); 8] = [
+        ("yyyy", "%Y"),
+        ("yy", "%y"),
+        ("mm", "%m"),
+        ("dd", "%d"),
+        ("HH", "%H"),
+        ("MM", "%M"),
+        ("SS", "%S"),
+        ("FFF", "%3f"),
+    ];
+    let mut chrono_format = String::with_capacity(pg_format.len() * 2);
+    let mut rest = pg_format;
+    'outer: while let Some(c) = rest.chars().next() {
+        for (token, code) in TOKENS {
+            if let Some(after) = rest.strip_prefix(token) {
+                chrono_format.push_str(code);
+                rest = after;
+                continue 'outer;
+            }
+        }
+        chrono_format.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    chrono_format
+}
+
+/// Searches for a timestamp in `format` (chrono or PAMGuide style) anywhere in `name`.
+/// Matches may not start in the middle of a number, and text after the stamp is ignored.
+pub fn find_timestamp(name: &str, format: &str) -> Option<NaiveDateTime> {
+    let chrono_format = convert_pamguide_format_to_chrono(format);
+    let mut previous: Option<char> = None;
+    for (offset, c) in name.char_indices() {
+        let mid_number = c.is_ascii_digit() && previous.is_some_and(|p| p.is_ascii_digit());
+        previous = Some(c);
+        if mid_number {
+            continue;
+        }
+        if let Ok((datetime, _remainder)) = NaiveDateTime::parse_and_remainder(&name[offset..], &chrono_format) {
+            return Some(datetime);
+        }
+    }
+    None
+}
+
+/// Compiles a `timestamp_regex`, checking that it has the required named groups.
+pub fn compile_timestamp_regex(pattern: &str) -> Result<Regex, String> {
+    let regex = Regex::new(pattern).map_err(|e| format!("Invalid timestamp_regex: {}", e))?;
+    let names: Vec<&str> = regex.capture_names().flatten().collect();
+    for required in ["year", "month", "day"] {
+        if !names.contains(&required) {
+            return Err(format!("timestamp_regex must have a named group (?P<{}>...)", required));
+        }
+    }
+    Ok(regex)
+}
+
+/// Builds a timestamp from the named groups year, month, day and optional hour, minute,
+/// second and fraction (the digits after the decimal point). Two-digit years are 20xx.
+pub fn parse_with_regex(name: &str, regex: &Regex) -> Option<NaiveDateTime> {
+    let caps = regex.captures(name)?;
+    let field = |group: &str| -> Option<u32> {
+        caps.name(group).map_or(Some(0), |m| m.as_str().parse().ok())
+    };
+    let year_str = caps.name("year")?.as_str();
+    let mut year: i32 = year_str.parse().ok()?;
+    if year_str.len() == 2 {
+        year += 2000;
+    }
+    let nanos = match caps.name("fraction") {
+        Some(m) => {
+            let digits: String = m.as_str().chars().chain(std::iter::repeat('0')).take(9).collect();
+            digits.parse().ok()?
+        }
+        None => 0,
+    };
+    NaiveDate::from_ymd_opt(year, field("month")?, field("day")?)?
+        .and_hms_nano_opt(field("hour")?, field("minute")?, field("second")?, nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datetime(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f").unwrap()
+    }
+
+    #[test]
+    fn test_find_timestamp_formats() {
+        assert_eq!(convert_pamguide_format_to_chrono("yyyymmdd_HHMMSS.FFF"), "%Y%m%d_%H%M%S.%3f");
+        assert_eq!(convert_pamguide_format_to_chrono("%Y%m%dT%H%M%SZ"), "%Y%m%dT%H%M%SZ");
+
+        let expected = datetime("2024-07-17 16:47:21");
+        // AudioMoth, SoundTrap and AMAR style names
+        assert_eq!(find_timestamp("20240717_164721", "yyyymmdd_HHMMSS"), Some(expected));
+        assert_eq!(find_timestamp("5678.240717164721", "yymmddHHMMSS"), Some(expected));
+        assert_eq!(find_timestamp("AMAR394.20240717T164721Z", "%Y%m%dT%H%M%SZ"), Some(expected));
+        assert_eq!(find_timestamp("site_A_20240717T164721_ch1", "yyyymmddTHHMMSS"), Some(expected));
+        assert_eq!(
+            find_timestamp("rec_20240717-164721.250", "yyyymmdd-HHMMSS.FFF"),
+            Some(datetime("2024-07-17 16:47:21.250"))
+        );
+        assert_eq!(find_timestamp("no_stamp_here", "yyyymmdd_HHMMSS"), None);
+    }
+
+    #[test]
+    fn test_parse_with_regex() {
+        let regex = compile_timestamp_regex(
+            r"(?P<year>\d{2})(?P<month>\d{2})(?P<day>\d{2})-(?P<hour>\d{2})h(?P<minute>\d{2})m(?P<second>\d{2})(?:\.(?P<fraction>\d+))?",
+        )
+        .unwrap();
+        assert_eq!(parse_with_regex("deploy3_240717-16h47m21.5", &regex), Some(datetime("2024-07-17 16:47:21.5")));
+        assert_eq!(parse_with_regex("deploy3_240717-16h47m21", &regex), Some(datetime("2024-07-17 16:47:21")));
+        assert!(compile_timestamp_regex(r"(?P<year>\d{4})").is_err());
+    }
+
+    #[test]
+    fn test_time_zones() {
+        let local = datetime("2024-07-17 18:47:21.25");
+        let utc = datetime("2024-07-17 16:47:21.25").and_utc();
+
+        let berlin = TimeZoneSpec::parse("Europe/Berlin").unwrap();
+        assert_eq!(berlin.to_utc(local), Ok(utc));
+        assert_eq!(berlin.format(utc), "2024-07-17T18:47:21.250+02:00");
+        // Winter time in the same zone is UTC+1
+        assert_eq!(berlin.to_utc(datetime("2024-01-17 17:47:21")), Ok(datetime("2024-01-17 16:47:21").and_utc()));
+
+        let fixed = TimeZoneSpec::parse("+02:00").unwrap();
+        assert_eq!(fixed.to_utc(local), Ok(utc));
+        assert_eq!(TimeZoneSpec::parse("-0530").unwrap().format(utc), "2024-07-17T11:17:21.250-05:30");
+        assert_eq!(TimeZoneSpec::Utc.format(utc), "2024-07-17T16:47:21.250Z");
+        assert!(TimeZoneSpec::parse("Mars/Olympus_Mons").is_err());
+
+        // Row times keep nanosecond precision
+        let row = RowTime::at_offset(Some(utc), 0.000_123_4);
+        assert_eq!(row.format(&TimeZoneSpec::Utc), "2024-07-17T16:47:21.250123400Z");
+        assert_eq!(RowTime::at_offset(None, 1.5).format(&TimeZoneSpec::Utc), "1.500");
+    }
+}
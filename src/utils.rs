@@ -1,4 +1,7 @@
-use crate::config::{AnalysisConfig, CalibrationType, Environment};
+use crate::config::{AnalysisConfig, AnalysisType, CalibrationType, Environment};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
 
 /// Calculates the overall system sensitivity correction factor (S) in dB.
 /// Returns 0.0 if calibration is not enabled.
@@ -48,6 +51,143 @@ pub fn calculate_system_sensitivity_db(config: &AnalysisConfig) -> Result<f64, S
     }
 }
 
+/// System sensitivity used to calibrate levels: a single value, or a curve over frequency.
+#[derive(Debug, Clone)]
+pub enum Calibration {
+    Scalar(f64),
+    Curve(SensitivityCurve),
+}
+
+impl Calibration {
+    /// Builds the calibration from the config. A sensitivity curve takes precedence over the
+    /// scalar calibration settings; without calibration the sensitivity is 0 dB.
+    pub fn from_config(config: &AnalysisConfig) -> Result<Calibration, String> {
+        match (&config.sensitivity_curve_path, config.calibrated) {
+            (Some(path), true) => Ok(Calibration::Curve(SensitivityCurve::load(Path::new(path))?)),
+            _ => Ok(Calibration::Scalar(calculate_system_sensitivity_db(config)?)),
+        }
+    }
+
+    /// Sensitivity (S) in dB at the given frequency.
+    pub fn sensitivity_db_at(&self, frequency: f64) -> f64 {
+        match self {
+            Calibration::Scalar(sensitivity_db) => *sensitivity_db,
+            Calibration::Curve(curve) => curve.interpolate(frequency),
+        }
+    }
+
+    /// Linear power gains that apply the calibration to each frequency bin.
+    pub fn bin_gains(&self, frequencies: &[f64]) -> Vec<f64> {
+        frequencies.iter().map(|&f| 10f64.powf(-self.sensitivity_db_at(f) / 10.0)).collect()
+    }
+
+    /// Human-readable summary for log output.
+    pub fn describe(&self) -> String {
+        match self {
+            Calibration::Scalar(sensitivity_db) => format!("{:.2} dB", sensitivity_db),
+            Calibration::Curve(curve) => format!(
+                "frequency-dependent, {} points from {} Hz to {} Hz",
+                curve.frequencies.len(),
+                curve.frequencies[0],
+                curve.frequencies[curve.frequencies.len() - 1]
+            ),
+        }
+    }
+}
+
+/// End-to-end system sensitivity (S, in the same units as `calculate_system_sensitivity_db`)
+/// tabulated against frequency.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SensitivityCurve {
+    #[serde(rename = "frequency_hz")]
+    pub frequencies: Vec<f64>,
+    pub sensitivity_db: Vec<f64>,
+}
+
+impl SensitivityCurve {
+    /// Loads a curve from a CSV file (frequency_hz, sensitivity_db columns; a header row is
+    /// optional) or a TOML file with `frequency_hz` and `sensitivity_db` arrays.
+    pub fn load(path: &Path) -> Result<SensitivityCurve, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read sensitivity curve '{}': {}", path.display(), e))?;
+        let is_toml = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("toml"));
+        let mut points: Vec<(f64, f64)> = if is_toml {
+            let curve: SensitivityCurve = toml::from_str(&content)
+                .map_err(|e| format!("Invalid sensitivity curve '{}': {}", path.display(), e))?;
+            if curve.frequencies.len() != curve.sensitivity_db.len() {
+                return Err(format!("Sensitivity curve '{}': frequency_hz and sensitivity_db differ in length", path.display()));
+            }
+            curve.frequencies.into_iter().zip(curve.sensitivity_db).collect()
+        } else {
+            let mut reader = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .comment(Some(b'#'))
+                .trim(csv::Trim::All)
+                .from_reader(content.as_bytes());
+            let mut points = Vec::new();
+            for (line, record) in reader.records().enumerate() {
+                let record = record.map_err(|e| format!("Invalid sensitivity curve '{}': {}", path.display(), e))?;
+                let parsed = (record.get(0).map(str::parse::<f64>), record.get(1).map(str::parse::<f64>));
+                match parsed {
+                    (Some(Ok(f)), Some(Ok(s))) => points.push((f, s)),
+                    _ if line == 0 => {} // Header row
+                    _ => return Err(format!("Invalid sensitivity curve '{}': bad row {}", path.display(), line + 1)),
+                }
+            }
+            points
+        };
+
+        if points.is_empty() {
+            return Err(format!("Sensitivity curve '{}' has no points", path.display()));
+        }
+        if points.iter().any(|&(f, s)| f <= 0.0 || !f.is_finite() || !s.is_finite()) {
+            return Err(format!("Sensitivity curve '{}' must have positive frequencies and finite values", path.display()));
+        }
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let (frequencies, sensitivity_db) = points.into_iter().unzip();
+        Ok(SensitivityCurve { frequencies, sensitivity_db })
+    }
+
+    /// Interpolates the sensitivity linearly in log-frequency, holding the end values
+    /// constant outside the tabulated range.
+    pub fn interpolate(&self, frequency: f64) -> f64 {
+        let freqs = &self.frequencies;
+        let values = &self.sensitivity_db;
+        let last = freqs.len() - 1;
+        if frequency <= freqs[0] {
+            return values[0];
+        }
+        if frequency >= freqs[last] {
+            return values[last];
+        }
+        let upper = freqs.partition_point(|&f| f <= frequency);
+        let lower = upper - 1;
+        let t = (frequency.ln() - freqs[lower].ln()) / (freqs[upper].ln() - freqs[lower].ln());
+        values[lower] + t * (values[upper] - values[lower])
+    }
+}
+
+/// Reference pressure used with `power_to_db` for the analysis environment.
+pub fn reference_pressure(environment: &Environment) -> f64 {
+    match environment {
+        Environment::Air => 20.0,
+        Environment::Wat => 1.0,
+    }
+}
+
+/// Units of the output levels, e.g. "dB re 1 µPa²/Hz" for a calibrated PSD in water.
+pub fn level_units(config: &AnalysisConfig) -> String {
+    if !config.calibrated {
+        return "dB (uncalibrated)".to_string();
+    }
+    let pref = reference_pressure(&config.environment);
+    match (&config.analysis_type, pref == 1.0) {
+        (AnalysisType::Psd, true) => "dB re 1 µPa²/Hz".to_string(),
+        (AnalysisType::Psd, false) => format!("dB re ({} µPa)²/Hz", pref),
+        (AnalysisType::Broadband, _) => format!("dB re {} µPa", pref),
+    }
+}
+
 /// Converts a linear power value to decibels relative to a reference.
 #[inline]
 pub fn power_to_db(value: f64, reference: f64) -> f64 {
@@ -60,3 +200,60 @@ pub fn power_to_db(value: f64, reference: f64) -> f64 {
     }
 }
 
+
+/// Parses a duration such as "500ms", "30s", "1min", "10min", "1h" or "1d" into seconds.
+/// A bare number is interpreted as seconds.
+pub fn parse_duration_secs(text: &str) -> Result<f64, String> {
+    let trimmed = text.trim();
+    let split_idx = trimmed
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split_idx);
+    let value: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration '{}': expected a number followed by a unit", text))?;
+
+    let multiplier = match unit.trim() {
+        "" | "s" | "sec" | "secs" => 1.0,
+        "ms" => 1e-3,
+        "m" | "min" | "mins" => 60.0,
+        "h" | "hr" | "hour" | "hours" => 3600.0,
+        "d" | "day" | "days" => 86400.0,
+        other => return Err(format!("Invalid duration unit '{}' in '{}'", other, text)),
+    };
+
+    let secs = value * multiplier;
+    if secs <= 0.0 {
+        return Err(format!("Duration '{}' must be positive", text));
+    }
+    // Averaging intervals are counted in whole nanoseconds
+    if (secs * 1e9).round() < 1.0 {
+        return Err(format!("Duration '{}' is shorter than a nanosecond", text));
+    }
+    Ok(secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sensitivity_curve_interpolation() {
+        let curve = SensitivityCurve {
+            frequencies: vec![10.0, 100.0, 1000.0],
+            sensitivity_db: vec![-170.0, -165.0, -166.0],
+        };
+        assert_eq!(curve.interpolate(1.0), -170.0);
+        assert_eq!(curve.interpolate(100.0), -165.0);
+        assert!((curve.interpolate(10f64.powf(1.5)) + 167.5).abs() < 1e-9);
+        assert_eq!(curve.interpolate(5000.0), -166.0);
+    }
+
+    #[test]
+    fn test_parse_duration_secs() {
+        assert_eq!(parse_duration_secs("1min"), Ok(60.0));
+        assert_eq!(parse_duration_secs("500ms"), Ok(0.5));
+        assert!(parse_duration_secs("0s").is_err());
+        assert!(parse_duration_secs("0.0000000001s").is_err());
+    }
+}
@@ -0,0 +1,258 @@
+use crate::audio_io::{self, RiffChunk};
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use regex::Regex;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Timing-related metadata embedded in a WAV file.
+#[derive(Debug, Clone, Default)]
+pub struct WavMetadata {
+    pub sample_rate: Option<u32>,
+    pub bext: Option<BextInfo>,
+    pub ixml: Option<String>,
+    pub comment: Option<String>, // LIST/INFO ICMT
+    pub guano: Option<String>,
+}
+
+/// A start time read from WAV metadata. BWF and iXML times are local recorder time, as are
+/// GUANO timestamps without a UTC offset; `is_utc` is set for times known to be UTC.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmbeddedStartTime {
+    pub time: NaiveDateTime,
+    pub source: &'static str,
+    pub is_utc: bool,
+}
+
+/// The timing fields of a Broadcast WAV (BWF) `bext` chunk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BextInfo {
+    pub origination_date: String,
+    pub origination_time: String,
+    pub time_reference: u64, // Samples since midnight
+}
+
+/// Reads the metadata chunks of a WAV file without reading the audio samples.
+pub fn read_wav_metadata(path: &Path) -> Result<WavMetadata, Box<dyn std::error::Error>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let chunks = audio_io::read_riff_chunks(&mut reader)?;
+    Ok(WavMetadata::from_chunks(&chunks))
+}
+
+impl WavMetadata {
+    pub fn from_chunks(chunks: &[RiffChunk]) -> WavMetadata {
+        let text = |data: &[u8]| String::from_utf8_lossy(data).trim_end_matches('\0').trim().to_string();
+        let mut metadata = WavMetadata::default();
+        for chunk in chunks {
+            match &chunk.id {
+                b"fmt " if chunk.data.len() >= 8 => {
+                    metadata.sample_rate = Some(u32::from_le_bytes(chunk.data[4..8].try_into().unwrap()));
+                }
+                b"bext" => metadata.bext = BextInfo::parse(&chunk.data),
+                b"iXML" => metadata.ixml = Some(text(&chunk.data)),
+                b"ICMT" => metadata.comment = Some(text(&chunk.data)),
+                b"guan" => metadata.guano = Some(text(&chunk.data)),
+                _ => {}
+            }
+        }
+        metadata
+    }
+
+    /// The recording start time and the metadata it came from. Sources are tried in order:
+    /// bext, iXML, GUANO, AudioMoth comment.
+    pub fn start_time(&self) -> Option<EmbeddedStartTime> {
+        let local = |time: NaiveDateTime| (time, false);
+        let sources: [(&'static str, Option<(NaiveDateTime, bool)>); 4] = [
+            ("bext", self.bext.as_ref().and_then(|bext| bext.start_time(self.sample_rate)).map(local)),
+            ("iXML", self.ixml.as_deref().and_then(|xml| ixml_start_time(xml, self.sample_rate)).map(local)),
+            ("GUANO", self.guano.as_deref().and_then(guano_start_time)),
+            ("AudioMoth comment", self.comment.as_deref().and_then(audiomoth_start_time).map(|t| (t, true))),
+        ];
+        sources
+            .into_iter()
+            .find_map(|(source, found)| found.map(|(time, is_utc)| EmbeddedStartTime { time, source, is_utc }))
+    }
+}
+
+impl BextInfo {
+    // Offsets of the timing fields in the bext chunk (after description, originator and reference)
+    const DATE_OFFSET: usize = 256 + 32 + 32;
+    const TIME_OFFSET: usize = Self::DATE_OFFSET + 10;
+    const TIME_REFERENCE_OFFSET: usize = Self::TIME_OFFSET + 8;
+
+    fn parse(data: &[u8]) -> Option<BextInfo> {
+        let field = |range: std::ops::Range<usize>| String::from_utf8_lossy(&data[range]).trim_end_matches('\0').to_string();
+        if data.len() < Self::TIME_REFERENCE_OFFSET + 8 {
+            return None;
+        }
+        let low = u32::from_le_bytes(data[Self::TIME_REFERENCE_OFFSET..Self::TIME_REFERENCE_OFFSET + 4].try_into().unwrap());
+        let high = u32::from_le_bytes(data[Self::TIME_REFERENCE_OFFSET + 4..Self::TIME_REFERENCE_OFFSET + 8].try_into().unwrap());
+        Some(BextInfo {
+            origination_date: field(Self::DATE_OFFSET..Self::TIME_OFFSET),
+            origination_time: field(Self::TIME_OFFSET..Self::TIME_REFERENCE_OFFSET),
+            time_reference: ((high as u64) << 32) | low as u64,
+        })
+    }
+
+    /// The origination date plus the time reference (samples since midnight) if it is set,
+    /// which gives sub-second precision, or else the origination time.
+    fn start_time(&self, sample_rate: Option<u32>) -> Option<NaiveDateTime> {
+        let date = parse_loose_date(&self.origination_date)?;
+        match sample_rate.filter(|&fs| fs > 0 && self.time_reference > 0) {
+            Some(fs) => Some(date.and_time(NaiveTime::MIN) + samples_to_duration(self.time_reference, fs)),
+            None => Some(date.and_time(parse_loose_time(&self.origination_time)?)),
+        }
+    }
+}
+
+/// Parses a date as "yyyy-mm-dd", allowing any single separator character (the BWF
+/// specification permits '-', '_', ':', ' ' and '.').
+fn parse_loose_date(s: &str) -> Option<NaiveDate> {
+    let s = s.trim();
+    if s.len() < 10 || !s.is_char_boundary(10) {
+        return None;
+    }
+    let digits = |range: std::ops::Range<usize>| s.get(range)?.parse::<u32>().ok();
+    NaiveDate::from_ymd_opt(digits(0..4)? as i32, digits(5..7)?, digits(8..10)?)
+}
+
+/// Parses a time as "hh:mm:ss", allowing any single separator character.
+fn parse_loose_time(s: &str) -> Option<NaiveTime> {
+    let s = s.trim();
+    let digits = |range: std::ops::Range<usize>| s.get(range)?.parse::<u32>().ok();
+    NaiveTime::from_hms_opt(digits(0..2)?, digits(3..5)?, digits(6..8)?)
+}
+
+fn samples_to_duration(samples: u64, fs: u32) -> Duration {
+    let secs = samples / fs as u64;
+    let nanos = (samples % fs as u64) * 1_000_000_000 / fs as u64;
+    Duration::seconds(secs as i64) + Duration::nanoseconds(nanos as i64)
+}
+
+/// Returns the text content of the first `<tag>` element in an XML document.
+fn xml_tag<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let open = format!("<{}>", tag);
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&format!("</{}>", tag))?;
+    Some(xml[start..end].trim())
+}
+
+/// Start time from the BEXT section of an iXML chunk, or from its SPEED timestamp
+/// (samples since midnight) combined with the BEXT origination date.
+fn ixml_start_time(xml: &str, sample_rate: Option<u32>) -> Option<NaiveDateTime> {
+    let date = parse_loose_date(xml_tag(xml, "BWF_ORIGINATION_DATE")?)?;
+    let number = |tag: &str| xml_tag(xml, tag).and_then(|v| v.parse::<u64>().ok());
+
+    let speed_samples = number("TIMESTAMP_SAMPLES_SINCE_MIDNIGHT_HI")
+        .zip(number("TIMESTAMP_SAMPLES_SINCE_MIDNIGHT_LO"))
+        .map(|(hi, lo)| (hi << 32) | lo);
+    let bext_samples = number("BWF_TIME_REFERENCE_HIGH")
+        .zip(number("BWF_TIME_REFERENCE_LOW"))
+        .map(|(hi, lo)| (hi << 32) | lo);
+    let fs = number("TIMESTAMP_SAMPLE_RATE").map(|fs| fs as u32).or(sample_rate);
+
+    match (speed_samples.or(bext_samples).filter(|&s| s > 0), fs.filter(|&fs| fs > 0)) {
+        (Some(samples), Some(fs)) => Some(date.and_time(NaiveTime::MIN) + samples_to_duration(samples, fs)),
+        _ => Some(date.and_time(parse_loose_time(xml_tag(xml, "BWF_ORIGINATION_TIME")?)?)),
+    }
+}
+
+/// Start time from the "Timestamp" field of a GUANO metadata chunk, and whether it is UTC.
+/// Timestamps with a UTC offset are converted to UTC; timestamps without one are local time.
+fn guano_start_time(guano: &str) -> Option<(NaiveDateTime, bool)> {
+    let value = guano.lines().find_map(|line| line.strip_prefix("Timestamp:"))?.trim();
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(value) {
+        return Some((datetime.naive_utc(), true));
+    }
+    if let Some(utc) = value.strip_suffix('Z') {
+        return NaiveDateTime::parse_from_str(utc, "%Y-%m-%dT%H:%M:%S%.f").ok().map(|t| (t, true));
+    }
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f"))
+        .ok()
+        .map(|t| (t, false))
+}
+
+/// Start time from an AudioMoth comment, e.g. "Recorded at 16:47:21 17/07/2024 (UTC+1) by
+/// AudioMoth ...", converted to UTC.
+fn audiomoth_start_time(comment: &str) -> Option<NaiveDateTime> {
+    let regex = Regex::new(
+        r"Recorded at (\d{2}):(\d{2}):(\d{2})(?:\.(\d{3}))? (\d{2})/(\d{2})/(\d{4}) \(UTC(?:([+-])(\d{1,2})(?::(\d{2}))?)?\)",
+    )
+    .ok()?;
+    let caps = regex.captures(comment)?;
+    let field = |i: usize| caps.get(i).map_or(Some(0), |m| m.as_str().parse::<u32>().ok());
+    let local = NaiveDate::from_ymd_opt(field(7)? as i32, field(6)?, field(5)?)?
+        .and_hms_milli_opt(field(1)?, field(2)?, field(3)?, field(4)?)?;
+    let offset = Duration::hours(field(9)? as i64) + Duration::minutes(field(10)? as i64);
+    match caps.get(8).map(|m| m.as_str()) {
+        Some("-") => Some(local + offset),
+        _ => Some(local - offset),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn datetime(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f").unwrap()
+    }
+
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend((data.len() as u32).to_le_bytes());
+        bytes.extend(data);
+        if data.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_bext_and_list_chunks() {
+        let mut fmt = vec![1, 0, 1, 0];
+        fmt.extend(48_000u32.to_le_bytes());
+        fmt.extend([0u8; 8]);
+        let mut bext = vec![0u8; 602];
+        bext[320..330].copy_from_slice(b"2024-07-17");
+        bext[330..338].copy_from_slice(b"16:47:21");
+        // 16:47:21.5 as samples since midnight at 48 kHz
+        let samples: u64 = (16 * 3600 + 47 * 60 + 21) * 48_000 + 24_000;
+        bext[338..346].copy_from_slice(&samples.to_le_bytes());
+        let mut list = b"INFO".to_vec();
+        list.extend(chunk(b"ICMT", b"Recorded at 17:47:21 17/07/2024 (UTC+1) by AudioMoth 24E1"));
+
+        let body = [chunk(b"fmt ", &fmt), chunk(b"bext", &bext), chunk(b"LIST", &list), chunk(b"data", &[0u8; 7])].concat();
+        let mut file = b"RIFF".to_vec();
+        file.extend(((body.len() + 4) as u32).to_le_bytes());
+        file.extend(b"WAVE");
+        file.extend(body);
+
+        let chunks = audio_io::read_riff_chunks(&mut Cursor::new(file)).unwrap();
+        let metadata = WavMetadata::from_chunks(&chunks);
+        assert_eq!(metadata.sample_rate, Some(48_000));
+        let start = metadata.start_time().unwrap();
+        assert_eq!((start.time, start.source, start.is_utc), (datetime("2024-07-17 16:47:21.5"), "bext", false));
+        assert_eq!(audiomoth_start_time(metadata.comment.as_deref().unwrap()), Some(datetime("2024-07-17 16:47:21")));
+    }
+
+    #[test]
+    fn test_text_metadata_timestamps() {
+        let guano = "GUANO|Version: 1.0\nMake: Wildlife Acoustics\nTimestamp: 2024-07-17T18:47:21.250+02:00\n";
+        assert_eq!(guano_start_time(guano), Some((datetime("2024-07-17 16:47:21.25"), true)));
+
+        let ixml = "<BWFXML><SPEED><TIMESTAMP_SAMPLES_SINCE_MIDNIGHT_HI>0</TIMESTAMP_SAMPLES_SINCE_MIDNIGHT_HI>\
+            <TIMESTAMP_SAMPLES_SINCE_MIDNIGHT_LO>60420</TIMESTAMP_SAMPLES_SINCE_MIDNIGHT_LO>\
+            <TIMESTAMP_SAMPLE_RATE>1000</TIMESTAMP_SAMPLE_RATE></SPEED>\
+            <BEXT><BWF_ORIGINATION_DATE>2024:07:17</BWF_ORIGINATION_DATE></BEXT></BWFXML>";
+        assert_eq!(ixml_start_time(ixml, None), Some(datetime("2024-07-17 00:01:00.42")));
+
+        assert_eq!(
+            audiomoth_start_time("Recorded at 16:47:21.125 17/07/2024 (UTC) by AudioMoth"),
+            Some(datetime("2024-07-17 16:47:21.125"))
+        );
+    }
+}
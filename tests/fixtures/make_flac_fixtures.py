@@ -0,0 +1,138 @@
+#!/usr/bin/env python3
+"""Writes the 24-bit FLAC test fixture and the equivalent WAV files.
+
+tone_16bit.wav and tone_24bit.{flac,wav} hold the same mono 8 kHz signal: the full-scale
+extremes (+/-(2^(bits-1) - 1)) and zero, followed by a sine. The FLAC file is encoded with
+fixed (order 2) predictors and Rice-coded residuals, in 256 sample blocks. The 16-bit FLAC
+fixture comes from the reference encoder instead (see README.md).
+"""
+import hashlib
+import math
+import struct
+from pathlib import Path
+
+FS = 8000
+BLOCK = 256
+N = 1000
+
+
+class Bits:
+    def __init__(self):
+        self.bits = []
+
+    def put(self, value, n):
+        self.bits.extend((value >> (n - 1 - i)) & 1 for i in range(n))
+
+    def put_signed(self, value, n):
+        self.put(value & ((1 << n) - 1), n)
+
+    def align(self):
+        while len(self.bits) % 8:
+            self.bits.append(0)
+
+    def bytes(self):
+        self.align()
+        return bytes(int("".join(map(str, self.bits[i:i + 8])), 2) for i in range(0, len(self.bits), 8))
+
+
+def crc8(data):
+    crc = 0
+    for byte in data:
+        crc ^= byte
+        for _ in range(8):
+            crc = ((crc << 1) ^ 0x07) & 0xFF if crc & 0x80 else (crc << 1) & 0xFF
+    return crc
+
+
+def crc16(data):
+    crc = 0
+    for byte in data:
+        crc ^= byte << 8
+        for _ in range(8):
+            crc = ((crc << 1) ^ 0x8005) & 0xFFFF if crc & 0x8000 else (crc << 1) & 0xFFFF
+    return crc
+
+
+def signal(bits):
+    full_scale = (1 << (bits - 1)) - 1
+    samples = [full_scale, -full_scale, 0, 1, -1]
+    samples += [round(0.6 * full_scale * math.sin(2 * math.pi * 440 * i / FS)) for i in range(N - len(samples))]
+    return samples
+
+
+def frame(number, block, bits):
+    header = Bits()
+    header.put(0b11111111111110, 14)
+    header.put(0, 1)  # Reserved
+    header.put(0, 1)  # Fixed block size
+    header.put(0b0111, 4)  # Block size - 1 follows as 16 bits
+    header.put(0b0000, 4)  # Sample rate from STREAMINFO
+    header.put(0b0000, 4)  # Mono
+    header.put({16: 0b100, 24: 0b110}[bits], 3)
+    header.put(0, 1)
+    header.put(number, 8)  # Frame numbers below 128 are a single UTF-8 byte
+    header.put(len(block) - 1, 16)
+    header_bytes = header.bytes()
+
+    body = Bits()
+    for b in header_bytes:
+        body.put(b, 8)
+    body.put(crc8(header_bytes), 8)
+    body.put(0, 1)
+    body.put(0b001010, 6)  # Fixed predictor, order 2
+    body.put(0, 1)  # No wasted bits
+    for sample in block[:2]:
+        body.put_signed(sample, bits)
+    residuals = [block[i] - 2 * block[i - 1] + block[i - 2] for i in range(2, len(block))]
+    folded = [2 * r if r >= 0 else -2 * r - 1 for r in residuals]
+    mean = sum(folded) / len(folded)
+    k = max(0, int(math.log2(mean))) if mean >= 1 else 0
+    rice2 = k > 14
+    body.put(1 if rice2 else 0, 2)
+    body.put(0, 4)  # Partition order 0
+    body.put(k, 5 if rice2 else 4)
+    for u in folded:
+        body.bits.extend([0] * (u >> k))
+        body.put(1, 1)
+        body.put(u & ((1 << k) - 1), k)
+    frame_bytes = body.bytes()
+    return frame_bytes + struct.pack(">H", crc16(frame_bytes))
+
+
+def pcm_bytes(samples, bits):
+    width = bits // 8
+    return b"".join((s & ((1 << bits) - 1)).to_bytes(width, "little") for s in samples)
+
+
+def flac(samples, bits):
+    streaminfo = Bits()
+    streaminfo.put(BLOCK, 16)
+    streaminfo.put(BLOCK, 16)
+    streaminfo.put(0, 24)  # Frame sizes unknown
+    streaminfo.put(0, 24)
+    streaminfo.put(FS, 20)
+    streaminfo.put(0, 3)  # One channel
+    streaminfo.put(bits - 1, 5)
+    streaminfo.put(len(samples), 36)
+    info = streaminfo.bytes() + hashlib.md5(pcm_bytes(samples, bits)).digest()
+    out = b"fLaC" + bytes([0x80]) + len(info).to_bytes(3, "big") + info
+    for number, start in enumerate(range(0, len(samples), BLOCK)):
+        out += frame(number, samples[start:start + BLOCK], bits)
+    return out
+
+
+def wav(samples, bits):
+    data = pcm_bytes(samples, bits)
+    width = bits // 8
+    fmt = struct.pack("<HHIIHH", 1, 1, FS, FS * width, width, bits)
+    body = b"WAVE" + b"fmt " + struct.pack("<I", len(fmt)) + fmt + b"data" + struct.pack("<I", len(data)) + data
+    return b"RIFF" + struct.pack("<I", len(body)) + body
+
+
+if __name__ == "__main__":
+    directory = Path(__file__).parent
+    for bits in (16, 24):
+        samples = signal(bits)
+        if bits == 24:
+            (directory / f"tone_{bits}bit.flac").write_bytes(flac(samples, bits))
+        (directory / f"tone_{bits}bit.wav").write_bytes(wav(samples, bits))